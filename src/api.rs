@@ -0,0 +1,202 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! The stable entry point for tools embedding movefmt.
+//! Nothing here prints to stdout, and no type of the Move compiler leaks out of it.
+
+use crate::core::fmt::EngineError;
+use crate::core::fmt_recover::UnformattedBlock;
+use crate::core::fmt_verify::find_line_overflows;
+use crate::core::format_source;
+use crate::tools::utils::Timer;
+use commentfmt::Config;
+use move_compiler::diagnostics::Diagnostics;
+use std::fmt;
+
+/// The result of `format_str`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOutput {
+    /// The formatted text.
+    pub text: String,
+    /// Whether `text` differs from the input.
+    pub changed: bool,
+    /// `None` on platforms which cannot time execution.
+    pub timing: Option<FormatTiming>,
+    /// Findings which didn't prevent formatting, e.g. lines still wider than `max_width`.
+    pub warnings: Vec<FormatWarning>,
+}
+
+/// Time spent by `format_str`, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatTiming {
+    pub parse_secs: f32,
+    pub format_secs: f32,
+}
+
+impl FormatTiming {
+    /// `None` unless `timer` went through both phases.
+    pub fn from_timer(timer: Timer) -> Option<Self> {
+        match timer {
+            Timer::DoneFormatting(..) => Some(FormatTiming {
+                parse_secs: timer.get_parse_time(),
+                format_secs: timer.get_format_time(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A finding on the formatted text, lines and columns are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatWarning {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// A parse error of the input, lines and columns are 1-based, columns count characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    /// What went wrong, e.g. `unexpected token`.
+    pub message: String,
+    /// Details pointing at the location, e.g. `Expected ';'`.
+    pub label: String,
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        if !self.label.is_empty() {
+            write!(f, ": {}", self.label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why `format_str` could not format its input.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FormatError {
+    /// The input doesn't parse.
+    #[error("{}", display_diagnostics(.0))]
+    Parse(Vec<ParseDiagnostic>),
+    /// The formatted result would have lost a comment or changed a token,
+    /// only with `verify_formatting`.
+    #[error("{0}")]
+    Verify(String),
+}
+
+fn display_diagnostics(diagnostics: &[ParseDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Format the Move source `content` with `config`, using the engine selected by
+/// `use_functional_formatter`. Never prints, whatever the `verbose` option.
+pub fn format_str(content: &str, config: &Config) -> Result<FormatOutput, FormatError> {
+    let output =
+        format_source(content, config.clone(), None).map_err(|e| structured_error(content, e))?;
+    let timing = output.timer.and_then(FormatTiming::from_timer);
+    let mut warnings = vec![];
+    for block in output.unformatted_blocks {
+        warnings.extend(unformatted_block_warnings(content, block));
+    }
+    let text = output.text;
+    warnings.extend(
+        find_line_overflows(&text, config)
+            .into_iter()
+            .map(|overflow| FormatWarning {
+                line: overflow.line,
+                column: overflow.col,
+                message: overflow.message(),
+            }),
+    );
+    Ok(FormatOutput {
+        changed: text != content,
+        text,
+        timing,
+        warnings,
+    })
+}
+
+fn structured_error(content: &str, e: EngineError) -> FormatError {
+    match e {
+        EngineError::Parse(diags) => FormatError::Parse(parse_diagnostics(content, diags)),
+        EngineError::Verify(e) => FormatError::Verify(e.to_string()),
+    }
+}
+
+/// Warnings pointing at the diagnostics of a block kept verbatim by `error_tolerant`.
+fn unformatted_block_warnings(content: &str, block: UnformattedBlock) -> Vec<FormatWarning> {
+    match structured_error(content, block.error) {
+        FormatError::Parse(diagnostics) => diagnostics
+            .into_iter()
+            .map(|diagnostic| FormatWarning {
+                line: diagnostic.line,
+                column: diagnostic.column,
+                message: format!("kept verbatim, {}", diagnostic),
+            })
+            .collect(),
+        FormatError::Verify(message) => {
+            let (line, column) = line_col(content, block.range.start);
+            vec![FormatWarning {
+                line,
+                column,
+                message: format!("kept verbatim, {}", message),
+            }]
+        }
+    }
+}
+
+fn parse_diagnostics(content: &str, diags: Diagnostics) -> Vec<ParseDiagnostic> {
+    diags
+        .into_codespan_format()
+        .into_iter()
+        .map(|(_, message, (loc, label), _, _)| {
+            let (line, column) = line_col(content, loc.start() as usize);
+            let (end_line, end_column) = line_col(content, loc.end() as usize);
+            ParseDiagnostic {
+                line,
+                column,
+                end_line,
+                end_column,
+                message: message.to_string(),
+                label,
+            }
+        })
+        .collect()
+}
+
+/// 1-based line and column of the byte `offset` of `content`.
+fn line_col(content: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let content = "module 0x1::m {\n    fun f() { é }\n}\n";
+        assert_eq!(line_col(content, 0), (1, 1));
+        assert_eq!(line_col(content, 16), (2, 1));
+        assert_eq!(line_col(content, content.find('}').unwrap()), (2, 17));
+        assert_eq!(line_col(content, content.len()), (4, 1));
+    }
+}
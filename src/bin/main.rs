@@ -2,15 +2,26 @@
 // Copyright (c) The BitsLab.MoveBit Contributors
 // SPDX-License-Identifier: Apache-2.0
 use anyhow::{Result, format_err};
-use commentfmt::{CliOptions, Config, EmitMode, Verbosity, load_config};
+use commentfmt::{CliOptions, Config, EmitMode, ErrorKind, Verbosity, load_config};
 use getopts::{Matches, Options};
 use io::Error as IoError;
 use movefmt::{
-    core::fmt::format_entry,
-    // core::fmt_state::format_entry_functional,
-    tools::movefmt_diff::{DIFF_CONTEXT_SIZE, make_diff, print_mismatches_default_message},
+    api::FormatTiming,
+    core::fmt::EngineError,
+    core::fmt_range::lines_to_byte_range,
+    core::fmt_recover::{TolerantOutput, UnformattedBlock},
+    core::fmt_verify::{LineOverflow, find_line_overflows, verify_idempotent},
+    core::format_source,
+    tools::checkstyle::to_checkstyle,
+    tools::git_changes::{GitChanges, changed_move_files},
+    tools::json_report::{JsonFileReport, JsonFileStatus, JsonReport, JsonUnformattedBlock},
+    tools::movefmt_diff::{
+        DIFF_CONTEXT_SIZE, make_diff, print_mismatches, print_mismatches_default_message,
+    },
+    tools::sarif::to_sarif,
     tools::utils::*,
 };
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
@@ -26,6 +37,8 @@ use colored::Colorize;
 const ERR_EMPTY_INPUT_FROM_STDIN: i32 = 1;
 const ERR_INVALID_MOVE_CODE_FROM_STDIN: i32 = 2;
 const ERR_FMT: i32 = 3;
+const ERR_CHECK_NOT_FORMATTED: i32 = 4;
+const ERR_IO: i32 = 5;
 
 #[derive(Error, Debug)]
 enum MoveFmtError {
@@ -37,7 +50,9 @@ enum MoveFmtError {
 }
 
 fn main() {
+    // stdout carries the formatted code and the reports
     tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             EnvFilter::try_from_env("MOVEFMT_LOG").unwrap_or_else(|_| EnvFilter::new("warn")),
         )
@@ -65,7 +80,13 @@ fn main() {
 enum Operation {
     /// Format files and their child modules. The bool value indicates whether
     /// the file is aspecified Move file from command line, which should not be escaped.
-    Format { files: Vec<(PathBuf, bool)> },
+    /// `file_lines` holds the line ranges to format of the files changed in git.
+    Format {
+        files: Vec<(PathBuf, bool)>,
+        file_lines: HashMap<PathBuf, Vec<(usize, usize)>>,
+    },
+    /// No Move file changed in git.
+    NothingChanged,
     /// Print the help message.
     Help(HelpOp),
     /// Print version information
@@ -93,6 +114,12 @@ pub enum OperationError {
     /// An error during escape check.
     #[error("{0}")]
     EscapeError(String),
+    /// An error while collecting the files changed in git.
+    #[error("{0}")]
+    GitError(String),
+    /// Options which can't be applied to the given input.
+    #[error("{0}")]
+    InvalidInput(String),
 }
 
 /// formatting errors.
@@ -119,9 +146,15 @@ enum HelpOp {
 
 fn make_opts() -> Options {
     let mut opts = Options::new();
-    let emit_opts = "[overwrite|new_file|stdout|diff]";
+    let emit_opts = "[overwrite|new_file|stdout|diff|json|checkstyle|sarif]";
 
     opts.optopt("", "emit", "What data to emit and how", emit_opts);
+    opts.optflag(
+        "",
+        "check",
+        "Run in 'check' mode. Prints the diffs and exits with a non-zero code \
+         if any file is not formatted, without writing anything",
+    );
     opts.optopt(
         "",
         "config-path",
@@ -152,11 +185,48 @@ fn make_opts() -> Options {
         "Format all Move files in the specified directory",
         "[Absolute path of specified directory]",
     );
+    opts.optopt(
+        "j",
+        "jobs",
+        "Number of files formatted in parallel, defaults to the number of CPUs",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "lines",
+        "Only format the top level items (functions, structs, spec blocks, uses...) \
+         overlapping the given lines of the single input file, the rest of it is kept as it is",
+        "START:END",
+    );
+    opts.optopt(
+        "",
+        "changed-since",
+        "Only format the Move files changed in git since REV, including the uncommitted \
+         and untracked ones",
+        "REV",
+    );
+    opts.optflag("", "staged", "Only format the Move files staged in git");
+    opts.optflag(
+        "",
+        "changed-lines",
+        "With `--changed-since` or `--staged`, only format the top level items \
+         touching the changed lines",
+    );
+    opts.optflag(
+        "",
+        "verify-idempotent",
+        "Format every file a second time and report the files whose second pass \
+         differs from the first one",
+    );
     opts.optflag("v", "verbose", "Print verbose output");
     opts.optflag("q", "quiet", "Print less output");
     opts.optflag("V", "version", "Show version information");
-    // opts.optflag("", "functional", "Use the new functional formatter (experimental)");
-    let help_topic_msg = "Show help".to_owned();
+    opts.optflag(
+        "",
+        "functional",
+        "Use the functional formatter engine (unstable, needs `unstable_features`)",
+    );
+    let help_topic_msg = "Show help, `--help=config` lists the configuration options".to_owned();
     opts.optflagopt("h", "help", &help_topic_msg, "=TOPIC");
     opts.optflag("i", "stdin", "Receive code text from stdin");
 
@@ -174,7 +244,7 @@ fn execute(opts: &Options) -> Result<i32> {
             Ok(0)
         }
         Operation::Help(HelpOp::Config) => {
-            print_usage_to_stdout(opts, "");
+            Config::print_docs(&mut io::stdout())?;
             Ok(0)
         }
         Operation::Version => {
@@ -201,48 +271,151 @@ fn execute(opts: &Options) -> Result<i32> {
             let file = file.canonicalize().unwrap_or(file);
 
             let (config, _) = load_config(Some(file.parent().unwrap()), Some(options))?;
-            let toml = config.all_options().to_toml()?;
+            let toml = config.to_toml_with_sources()?;
             io::stdout().write_all(toml.as_bytes())?;
 
             Ok(0)
         }
         Operation::Stdin { exit_code } => {
-            if exit_code > 0 {
+            if exit_code == ERR_CHECK_NOT_FORMATTED || exit_code == ERR_FMT {
+                Ok(exit_code)
+            } else if exit_code > 0 {
                 Err(MoveFmtError::ErrStdin(exit_code).into())
             } else {
                 Ok(0)
             }
         }
-        Operation::Format { files } => format(files, &options),
+        Operation::Format { files, file_lines } => format(files, &file_lines, &options),
+        Operation::NothingChanged => {
+            if !options.quiet.unwrap_or_default() {
+                println!("No Move file changed, nothing to format");
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Formats `content` through `core::format_source`,
+/// only the items overlapping `lines` are formatted if it is given.
+fn format_content(
+    content: &str,
+    config: Config,
+    lines: Option<&[(usize, usize)]>,
+) -> Result<TolerantOutput, EngineError> {
+    let ranges: Option<Vec<_>> = lines.map(|lines| {
+        lines
+            .iter()
+            .map(|(start_line, end_line)| lines_to_byte_range(content, *start_line, *end_line))
+            .collect()
+    });
+    format_source(content, config, ranges.as_deref())
+}
+
+/// Renders the compiler diagnostics of `content`, the content of the file `name`.
+/// Reports are read by tools, so `color` is off for them.
+fn render_diagnostics(
+    name: &str,
+    content: &str,
+    diags: move_compiler::diagnostics::Diagnostics,
+    color: bool,
+) -> Vec<u8> {
+    let mut files_source_text: move_compiler::diagnostics::FilesSourceText = HashMap::new();
+    files_source_text.insert(
+        move_command_line_common::files::FileHash::empty(),
+        (name.into(), content.to_string()),
+    );
+    if color {
+        move_compiler::diagnostics::report_diagnostics_to_color_buffer(&files_source_text, diags)
+    } else {
+        move_compiler::diagnostics::report_diagnostics_to_buffer(&files_source_text, diags)
+    }
+}
+
+/// The blocks of `content` kept verbatim by `error_tolerant`, as they are reported.
+fn mk_unformatted_blocks(
+    name: &str,
+    content: &str,
+    blocks: Vec<UnformattedBlock>,
+    color: bool,
+) -> Vec<JsonUnformattedBlock> {
+    let line_of = |offset: usize| content[..offset].matches('\n').count() + 1;
+    blocks
+        .into_iter()
+        .map(|block| JsonUnformattedBlock {
+            line: line_of(block.range.start),
+            end_line: line_of(block.range.end),
+            diagnostics: match block.error {
+                EngineError::Parse(diags) => {
+                    String::from_utf8_lossy(&render_diagnostics(name, content, diags, color))
+                        .into_owned()
+                }
+                EngineError::Verify(e) => e.to_string(),
+            },
+        })
+        .collect()
+}
+
+fn print_timing(timing: &FormatTiming) {
+    println!(
+        "Spent {0:.3} secs in the parsing phase, and {1:.3} secs in the formatting phase",
+        timing.parse_secs, timing.format_secs,
+    );
+}
+
+/// Prints the blocks kept verbatim by `error_tolerant` in the file `name`.
+fn print_unformatted_blocks(name: &str, blocks: &[JsonUnformattedBlock]) {
+    for block in blocks {
+        eprintln!(
+            "{}: {}:{}: the block was kept verbatim\n{}",
+            "error".red(),
+            name,
+            block.line,
+            block.diagnostics
+        );
     }
 }
 
 fn format_string(content_origin: String, options: GetOptsOptions) -> Result<i32> {
-    let (config, config_path) = load_config(None, Some(options.clone()))?;
+    let (mut config, config_path) = load_config(None, Some(options.clone()))?;
+    check_required_version(&config).map_err(|e| format_err!(e))?;
+    let emit_mode = options.emit_mode.unwrap_or(config.emit_mode());
+    if emits_report(emit_mode) {
+        // stdout carries the report
+        config.set().verbose(Verbosity::Quiet);
+    }
     let use_config = config.clone();
     if config.verbose() == Verbosity::Verbose {
         if let Some(path) = config_path.as_ref() {
             println!("Using movefmt config file {}", path.display());
         }
-        // if options.use_functional {
-        //     println!("Using experimental functional formatter");
-        // }
+        if config.use_functional_formatter() {
+            println!("Using experimental functional formatter");
+        }
     }
 
-    // TODO: This feature is expected to be available in September 2025
-    // let format_result = if options.use_functional {
-    //     format_entry_functional(content_origin.clone(), use_config.clone())
-    // } else {
-    //    format_entry(content_origin.clone(), use_config.clone())
-    // };
-
-    match format_entry(content_origin.clone(), use_config.clone()) {
-        Ok(formatted_text) => {
-            let emit_mode = if let Some(op_emit) = options.emit_mode {
-                op_emit
-            } else {
-                use_config.emit_mode()
-            };
+    match format_content(
+        &content_origin,
+        use_config.clone(),
+        options.lines.as_ref().map(std::slice::from_ref),
+    ) {
+        Ok(output) => {
+            if config.verbose() == Verbosity::Verbose {
+                if let Some(timing) = output.timer.and_then(FormatTiming::from_timer) {
+                    print_timing(&timing);
+                }
+            }
+            let formatted_text = output.text;
+            let unformatted_blocks = mk_unformatted_blocks(
+                "<stdin>",
+                &content_origin,
+                output.unformatted_blocks,
+                !emits_report(emit_mode),
+            );
+            let kept_verbatim = !unformatted_blocks.is_empty();
+            if !emits_report(emit_mode) {
+                print_unformatted_blocks("<stdin>", &unformatted_blocks);
+            }
+            let mut not_formatted = false;
             match emit_mode {
                 EmitMode::Diff => {
                     let compare = make_diff(&content_origin, &formatted_text, DIFF_CONTEXT_SIZE);
@@ -250,8 +423,19 @@ fn format_string(content_origin: String, options: GetOptsOptions) -> Result<i32>
                         let mut failures = HashMap::new();
                         failures.insert(PathBuf::new(), compare);
                         print_mismatches_default_message(failures);
+                        not_formatted = true;
                     }
                 }
+                _ if emits_report(emit_mode) => {
+                    let mut file_report = JsonFileReport::formatted(
+                        "<stdin>".to_string(),
+                        &content_origin,
+                        &formatted_text,
+                    );
+                    file_report.unformatted_blocks = unformatted_blocks;
+                    not_formatted = file_report.status == JsonFileStatus::Formatted;
+                    println!("{}", render_report(emit_mode, vec![file_report]));
+                }
                 _ => {
                     if options.quiet.is_none() || !options.quiet.unwrap() {
                         tracing::warn!(
@@ -263,23 +447,262 @@ fn format_string(content_origin: String, options: GetOptsOptions) -> Result<i32>
                     println!("{}", formatted_text);
                 }
             }
-            Ok(0)
+            Ok(check_exit_code(
+                options.check,
+                not_formatted as usize,
+                kept_verbatim as usize,
+                0,
+            ))
         }
         Err(_) => Err(FormattingError::ParseContentError("parse failed".to_string()).into()),
     }
 }
 
-fn format(files: Vec<(PathBuf, bool)>, options: &GetOptsOptions) -> Result<i32> {
-    if options.quiet.is_none() || !options.quiet.unwrap() {
-        println!("options = {:?}", options);
+/// The outcome of formatting a single file.
+enum FileStatus {
+    /// Formatted successfully, holds the original and the formatted text, the lines
+    /// still wider than `max_width`, the blocks kept verbatim by `error_tolerant`,
+    /// the result of a second pass if `--verify-idempotent` found it differs from `formatted`,
+    /// and the time spent formatting it.
+    Formatted {
+        origin: String,
+        formatted: String,
+        line_overflows: Vec<LineOverflow>,
+        unformatted_blocks: Vec<JsonUnformattedBlock>,
+        second_pass: Option<String>,
+        timing: Option<FormatTiming>,
+    },
+    /// Escaped by `skip_formatting_dirs` in movefmt.toml.
+    SkippedByConfig,
+    /// Escaped because the file doesn't belong to any Move-Package.
+    SkippedNotInPackage,
+    /// Parse failed, holds the rendered compiler diagnostics.
+    ParseError(Vec<u8>),
+    /// The formatted result would have lost a comment or changed a token,
+    /// so nothing was written.
+    VerifyError(String),
+    /// The file or its config could not be read or written.
+    IoError(String),
+    /// The config of the file has a `required_version` this movefmt doesn't satisfy,
+    /// so nothing was written.
+    VersionMismatch(String),
+}
+
+/// Everything `format()` needs to report about one file once all the files are done.
+struct FileReport {
+    file: PathBuf,
+    config_path: Option<PathBuf>,
+    emit_mode: EmitMode,
+    verbose: bool,
+    error_on_line_overflow: bool,
+    status: FileStatus,
+}
+
+impl FileReport {
+    fn io_error(file: &Path, options: &GetOptsOptions, msg: String) -> Self {
+        Self::not_formatted(file, options, FileStatus::IoError(msg))
     }
 
+    fn not_formatted(file: &Path, options: &GetOptsOptions, status: FileStatus) -> Self {
+        FileReport {
+            file: file.to_path_buf(),
+            config_path: None,
+            emit_mode: options.emit_mode.unwrap_or_default(),
+            verbose: false,
+            error_on_line_overflow: false,
+            status,
+        }
+    }
+}
+
+/// Resolves the config of `file`, formats it and writes the result if the emit mode
+/// targets a file. Runs on the rayon thread pool, so nothing is printed here:
+/// everything to report, warnings and timings included, goes in the returned `FileReport`
+/// and `format()` prints it in the order of the files.
+fn format_file(
+    file: &Path,
+    is_specified_file: bool,
+    global_config_path: Option<&PathBuf>,
+    options: &GetOptsOptions,
+    lines: Option<&[(usize, usize)]>,
+) -> FileReport {
+    if !file.exists() {
+        let msg = format!("file `{}` does not exist", file.display());
+        return FileReport::io_error(file, options, msg);
+    } else if file.is_dir() {
+        let msg = format!("`{}` is a directory", file.display());
+        return FileReport::io_error(file, options, msg);
+    }
+
+    // the config is resolved per file, so that the Move.toml of its package is read
+    // even with `--config-path`
+    let (mut use_config, config_path) = match load_config(file.parent(), Some(options.clone())) {
+        Ok((config, config_path)) => (config, config_path.or_else(|| global_config_path.cloned())),
+        Err(e) => {
+            let msg = format!("failed to load config for `{}`: {}", file.display(), e);
+            return FileReport::io_error(file, options, msg);
+        }
+    };
+    if let Some(root) = config_path.as_ref().and_then(|path| path.parent()) {
+        use_config = use_config.for_file(file, root);
+        // the command line still wins over `[[overrides]]`
+        options.clone().apply_to(&mut use_config);
+    }
+    if let Err(e) = check_required_version(&use_config) {
+        let msg = match config_path.as_ref() {
+            Some(path) => format!("{} of `{}`", e, path.display()),
+            None => e,
+        };
+        return FileReport::not_formatted(file, options, FileStatus::VersionMismatch(msg));
+    }
+    let emit_mode = options.emit_mode.unwrap_or(use_config.emit_mode());
+    if emits_report(emit_mode) {
+        // stdout carries the report
+        use_config.set().verbose(Verbosity::Quiet);
+    }
+    let verbose = use_config.verbose() == Verbosity::Verbose;
+    let error_on_line_overflow = use_config.error_on_line_overflow();
+    let status = format_file_status(
+        file,
+        is_specified_file,
+        use_config,
+        &config_path,
+        emit_mode,
+        lines,
+        options.verify_idempotent,
+    );
+    FileReport {
+        file: file.to_path_buf(),
+        config_path,
+        emit_mode,
+        verbose,
+        error_on_line_overflow,
+        status,
+    }
+}
+
+fn format_file_status(
+    file: &Path,
+    is_specified_file: bool,
+    use_config: Config,
+    config_path: &Option<PathBuf>,
+    emit_mode: EmitMode,
+    lines: Option<&[(usize, usize)]>,
+    check_idempotent: bool,
+) -> FileStatus {
+    if !is_specified_file && should_escape_not_in_package(file, &use_config) {
+        return FileStatus::SkippedNotInPackage;
+    }
+    if !is_specified_file && should_escape(file, &use_config, config_path.clone()).is_some() {
+        return FileStatus::SkippedByConfig;
+    }
+    if !is_specified_file {
+        match should_ignore(file, &use_config, config_path.as_deref()) {
+            Ok(true) => return FileStatus::SkippedByConfig,
+            Ok(false) => {}
+            Err(e) => return FileStatus::IoError(e.to_string()),
+        }
+    }
+
+    let content_origin = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            return FileStatus::IoError(format!("failed to read `{}`: {}", file.display(), e));
+        }
+    };
+
+    match format_content(&content_origin, use_config.clone(), lines) {
+        Ok(output) => {
+            let timing = output.timer.and_then(FormatTiming::from_timer);
+            let formatted_text = output.text;
+            let unformatted_blocks = mk_unformatted_blocks(
+                &file.display().to_string(),
+                &content_origin,
+                output.unformatted_blocks,
+                !emits_report(emit_mode),
+            );
+            // only whole files are checked, the lines out of `--lines` are left as they are
+            // and a second pass would format more items
+            let line_overflows = if lines.is_none() {
+                find_line_overflows(&formatted_text, &use_config)
+            } else {
+                vec![]
+            };
+            let second_pass = if check_idempotent && lines.is_none() {
+                match verify_idempotent(&formatted_text, use_config) {
+                    Ok(second_pass) => second_pass,
+                    Err(_) => {
+                        return FileStatus::VerifyError(
+                            "the formatted result could not be formatted again".to_string(),
+                        );
+                    }
+                }
+            } else {
+                None
+            };
+            let written = match emit_mode {
+                EmitMode::NewFile => std::fs::write(mk_result_filepath(file), &formatted_text),
+                EmitMode::Overwrite => std::fs::write(file, &formatted_text),
+                _ => Ok(()),
+            };
+            match written {
+                Ok(_) => FileStatus::Formatted {
+                    origin: content_origin,
+                    formatted: formatted_text,
+                    line_overflows,
+                    unformatted_blocks,
+                    second_pass,
+                    timing,
+                },
+                Err(e) => {
+                    FileStatus::IoError(format!("failed to write `{}`: {}", file.display(), e))
+                }
+            }
+        }
+        Err(EngineError::Verify(e)) => FileStatus::VerifyError(e.to_string()),
+        Err(EngineError::Parse(diags)) => FileStatus::ParseError(render_diagnostics(
+            &file.display().to_string(),
+            &content_origin,
+            diags,
+            !emits_report(emit_mode),
+        )),
+    }
+}
+
+fn format(
+    files: Vec<(PathBuf, bool)>,
+    file_lines: &HashMap<PathBuf, Vec<(usize, usize)>>,
+    options: &GetOptsOptions,
+) -> Result<i32> {
     let (config, config_path) = load_config(None, Some(options.clone()))?;
-    let mut use_config = config.clone();
-    let mut use_config_path = config_path.clone();
+    check_required_version(&config).map_err(|e| format_err!(e))?;
+    // stdout carries the report, nothing else is printed on it
+    let mut report_emit_mode = options.emit_mode.unwrap_or(config.emit_mode());
+    let emit_report = emits_report(report_emit_mode);
+    let quiet = (options.quiet.is_some() && options.quiet.unwrap()) || emit_report;
+    if !quiet {
+        println!("options = {:?}", options);
+    }
+    let verbose = config.verbose() == Verbosity::Verbose && !emit_report;
+
     let mut success_cnt = 0;
     let mut skips_cnt_expected = 0;
     let mut skips_cnt_not_belong_to_any_package = 0;
+    let mut not_formatted_cnt = 0;
+    let mut parse_failed_cnt = 0;
+    let mut verify_failed_cnt = 0;
+    let mut not_idempotent_cnt = 0;
+    let mut line_overflow_cnt = 0;
+    // files with blocks kept verbatim by `error_tolerant`
+    let mut kept_verbatim_cnt = 0;
+    let mut io_failed_cnt = 0;
+    // a `required_version` of a local config fails the run like the global one does
+    let mut version_mismatches: Vec<String> = vec![];
+    // `CI` with `--emit diff` is still honoured for the existing GitHub workflow,
+    // which predates `--check`.
+    let check_mode = options.check
+        || (std::env::var("CI").is_ok()
+            && options.emit_mode.unwrap_or(config.emit_mode()) == EmitMode::Diff);
     tracing::info!(
         "config.[verbose, indent] = [{:?}, {:?}], {:?}",
         config.verbose(),
@@ -287,7 +710,7 @@ fn format(files: Vec<(PathBuf, bool)>, options: &GetOptsOptions) -> Result<i32>
         options
     );
 
-    if config.verbose() == Verbosity::Verbose {
+    if verbose {
         if let Some(path) = config_path.as_ref() {
             println!("Using movefmt config file {}", path.display());
         }
@@ -312,7 +735,7 @@ fn format(files: Vec<(PathBuf, bool)>, options: &GetOptsOptions) -> Result<i32>
                 }
             }
 
-            if use_config.verbose() == Verbosity::Verbose {
+            if verbose {
                 tracing::warn!(
                     "\n{}\n{}{}\n{}",
                     "No file argument supplied.".red(),
@@ -332,137 +755,188 @@ fn format(files: Vec<(PathBuf, bool)>, options: &GetOptsOptions) -> Result<i32>
         }
     }
 
-    for (file, is_specified_file) in files {
-        if !file.exists() {
-            eprintln!("Error: file `{}` does not exist", file.to_str().unwrap());
-            continue;
-        } else if file.is_dir() {
-            eprintln!("Error: `{}` is a directory", file.to_str().unwrap());
-            continue;
-        } else {
-            // Check the file directory if the config-path could not be read or not provided
-            if config_path.is_none() {
-                let (local_config, config_path) =
-                    load_config(Some(file.parent().unwrap()), Some(options.clone()))?;
-                tracing::debug!("local config_path = {:?}", config_path);
-
-                if let Some(path) = config_path {
-                    if local_config.verbose() == Verbosity::Verbose {
-                        println!(
-                            "Using movefmt local config file {} for {}",
-                            path.display(),
-                            file.display()
-                        );
-                    }
-                    use_config = local_config.clone();
-                    use_config_path = Some(path);
-                }
-            }
-        }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or_default())
+        .build()?;
+    // `collect` keeps the order of `files`, so the reports below are printed in the
+    // same order no matter how the files were scheduled.
+    let reports: Vec<FileReport> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|(file, is_specified_file)| {
+                let lines = file_lines
+                    .get(file)
+                    .map(Vec::as_slice)
+                    .or(options.lines.as_ref().map(std::slice::from_ref));
+                format_file(
+                    file,
+                    *is_specified_file,
+                    config_path.as_ref(),
+                    options,
+                    lines,
+                )
+            })
+            .collect()
+    });
 
-        if !is_specified_file && should_escape_not_in_package(&file, &use_config) {
-            skips_cnt_not_belong_to_any_package += 1;
-            if use_config.verbose() == Verbosity::Verbose
-                && (options.quiet.is_none() || !options.quiet.unwrap())
-            {
-                tracing::warn!(
-                    "\n{}: {} {}\n",
-                    "Escape file".yellow(),
-                    file.display(),
-                    "because it's not belong to any Move-Package".yellow()
-                );
+    let mut report_files = vec![];
+    for report in reports {
+        if let FileStatus::VersionMismatch(msg) = report.status {
+            if !version_mismatches.contains(&msg) {
+                version_mismatches.push(msg);
             }
             continue;
         }
-
-        if !is_specified_file
-            && should_escape(&file, &use_config, use_config_path.clone()).is_some()
-        {
-            skips_cnt_expected += 1;
-            if use_config.verbose() == Verbosity::Verbose
-                && (options.quiet.is_none() || !options.quiet.unwrap())
-            {
-                tracing::warn!(
-                    "\n{}: {} {}: {}\n",
-                    "Escape file".yellow(),
-                    file.display(),
-                    "by config".yellow(),
-                    use_config_path.clone().unwrap_or_default().display()
-                );
+        let file = report.file;
+        if emits_report(report.emit_mode) {
+            report_emit_mode = report.emit_mode;
+            let file_report = mk_json_file_report(&file, report.status);
+            if !file_report.second_pass_chunks.is_empty() {
+                not_idempotent_cnt += 1;
+            }
+            if report.error_on_line_overflow && !file_report.line_overflows.is_empty() {
+                line_overflow_cnt += 1;
+            }
+            if !file_report.unformatted_blocks.is_empty() {
+                kept_verbatim_cnt += 1;
+            }
+            match file_report.status {
+                JsonFileStatus::Formatted => {
+                    success_cnt += 1;
+                    not_formatted_cnt += 1;
+                }
+                JsonFileStatus::Unchanged => success_cnt += 1,
+                JsonFileStatus::SkippedByConfig => skips_cnt_expected += 1,
+                JsonFileStatus::SkippedNotInPackage => skips_cnt_not_belong_to_any_package += 1,
+                JsonFileStatus::ParseError => parse_failed_cnt += 1,
+                JsonFileStatus::VerifyError => verify_failed_cnt += 1,
+                JsonFileStatus::IoError => io_failed_cnt += 1,
             }
+            report_files.push(file_report);
             continue;
         }
-
-        let content_origin = std::fs::read_to_string(file.as_path()).unwrap();
-        if use_config.verbose() == Verbosity::Verbose {
-            println!("Formatting {}", file.display());
-        }
-
-        // TODO: This feature is expected to be available in September 2025
-        // let format_result = if options.use_functional {
-        //     format_entry_functional(content_origin.clone(), use_config.clone())
-        // } else {
-        //    format_entry(content_origin.clone(), use_config.clone())
-        // };
-
-        match format_entry(content_origin.clone(), use_config.clone()) {
-            Ok(formatted_text) => {
+        let verbose = report.verbose && !quiet;
+        match report.status {
+            FileStatus::IoError(msg) => {
+                eprintln!("Error: {}", msg);
+                io_failed_cnt += 1;
+            }
+            FileStatus::SkippedNotInPackage => {
+                skips_cnt_not_belong_to_any_package += 1;
+                if verbose {
+                    tracing::warn!(
+                        "\n{}: {} {}\n",
+                        "Escape file".yellow(),
+                        file.display(),
+                        "because it's not belong to any Move-Package".yellow()
+                    );
+                }
+            }
+            FileStatus::SkippedByConfig => {
+                skips_cnt_expected += 1;
+                if verbose {
+                    tracing::warn!(
+                        "\n{}: {} {}: {}\n",
+                        "Escape file".yellow(),
+                        file.display(),
+                        "by config".yellow(),
+                        report.config_path.unwrap_or_default().display()
+                    );
+                }
+            }
+            FileStatus::ParseError(diags_buf) => {
+                if std::io::stdout().write_all(&diags_buf).is_err() {
+                    // Cannot output compiler diagnostics;
+                    // https://github.com/movebit/movefmt/issues/2
+                    eprintln!("file '{:?}' skipped because of parse not ok", file);
+                }
+                parse_failed_cnt += 1;
+            }
+            FileStatus::VerifyError(msg) => {
+                eprintln!("Error: `{}` {}", file.display(), msg);
+                verify_failed_cnt += 1;
+            }
+            FileStatus::Formatted {
+                origin,
+                formatted,
+                line_overflows,
+                unformatted_blocks,
+                second_pass,
+                timing,
+            } => {
                 success_cnt += 1;
-                let emit_mode = if let Some(op_emit) = options.emit_mode {
-                    op_emit
+                if !unformatted_blocks.is_empty() {
+                    print_unformatted_blocks(&file.display().to_string(), &unformatted_blocks);
+                    kept_verbatim_cnt += 1;
+                }
+                let level = if report.error_on_line_overflow {
+                    "error".red()
                 } else {
-                    use_config.emit_mode()
+                    "warning".yellow()
                 };
-                match emit_mode {
-                    EmitMode::NewFile => {
-                        std::fs::write(mk_result_filepath(&file.to_path_buf()), formatted_text)?
+                for overflow in line_overflows.iter().filter(|_| !quiet) {
+                    eprintln!(
+                        "{}: {}:{}:{}: {}",
+                        level,
+                        file.display(),
+                        overflow.line,
+                        overflow.col,
+                        overflow.message()
+                    );
+                }
+                if report.error_on_line_overflow && !line_overflows.is_empty() {
+                    line_overflow_cnt += 1;
+                }
+                if let Some(second_pass) = second_pass {
+                    let mut failures = HashMap::new();
+                    failures.insert(
+                        file.to_owned(),
+                        make_diff(&formatted, &second_pass, DIFF_CONTEXT_SIZE),
+                    );
+                    print_mismatches(failures, |file_name, line_num| {
+                        format!(
+                            "\nSecond formatting pass differs at {}:{}:",
+                            file_name.display(),
+                            line_num
+                        )
+                    });
+                    not_idempotent_cnt += 1;
+                }
+                if report.verbose {
+                    if let Some(path) = report.config_path.as_ref() {
+                        if config_path.is_none() {
+                            println!(
+                                "Using movefmt local config file {} for {}",
+                                path.display(),
+                                file.display()
+                            );
+                        }
                     }
-                    EmitMode::Overwrite => {
-                        std::fs::write(&file, formatted_text)?;
+                    println!("Formatting {}", file.display());
+                    if let Some(timing) = timing {
+                        print_timing(&timing);
                     }
+                }
+                match report.emit_mode {
                     EmitMode::Stdout => {
-                        println!("{}", formatted_text);
+                        println!("{}", formatted);
                     }
                     EmitMode::Diff => {
-                        let compare =
-                            make_diff(&content_origin, &formatted_text, DIFF_CONTEXT_SIZE);
+                        let compare = make_diff(&origin, &formatted, DIFF_CONTEXT_SIZE);
                         if !compare.is_empty() {
                             let mut failures = HashMap::new();
                             failures.insert(file.to_owned(), compare);
                             print_mismatches_default_message(failures);
-
-                            // Only for github CI
-                            if std::env::var("CI").is_ok() {
-                                return Err(format_err!(
-                                    "CHECK FAILED, your source code has not been formatted with movefmt."
-                                ));
-                            }
+                            not_formatted_cnt += 1;
                         }
                     }
+                    _ => {}
                 }
             }
-            Err(diags) => {
-                let mut files_source_text: move_compiler::diagnostics::FilesSourceText =
-                    HashMap::new();
-                files_source_text.insert(
-                    move_command_line_common::files::FileHash::empty(),
-                    (file.display().to_string().into(), content_origin.clone()),
-                );
-                let diags_buf = move_compiler::diagnostics::report_diagnostics_to_color_buffer(
-                    &files_source_text,
-                    diags,
-                );
-                if std::io::stdout().write_all(&diags_buf).is_err() {
-                    // Cannot output compiler diagnostics;
-                    // https://github.com/movebit/movefmt/issues/2
-                    eprintln!("file '{:?}' skipped because of parse not ok", file);
-                }
-                return Err(MoveFmtError::ErrFmt(ERR_FMT).into());
-            }
         }
     }
 
-    if options.quiet.is_none() || !options.quiet.unwrap() {
+    if !quiet {
         println!(
             "\n----------------------------------------------------------------------------\n"
         );
@@ -481,9 +955,151 @@ fn format(files: Vec<(PathBuf, bool)>, options: &GetOptsOptions) -> Result<i32>
         if success_cnt > 0 {
             println!("{:?} files successfully formatted\n", success_cnt);
         }
+        if not_formatted_cnt > 0 {
+            println!(
+                "{:?} files have not been formatted with movefmt\n",
+                not_formatted_cnt
+            );
+        }
+        if parse_failed_cnt > 0 {
+            println!(
+                "{:?} files skipped because of parse failed\n",
+                parse_failed_cnt
+            );
+        }
+        if line_overflow_cnt > 0 {
+            println!(
+                "{:?} files have lines exceeding max_width\n",
+                line_overflow_cnt
+            );
+        }
+        if kept_verbatim_cnt > 0 {
+            println!(
+                "{:?} files have blocks kept verbatim because they don't parse\n",
+                kept_verbatim_cnt
+            );
+        }
+        if not_idempotent_cnt > 0 {
+            println!(
+                "{:?} files are not stable under a second formatting pass\n",
+                not_idempotent_cnt
+            );
+        }
+        if verify_failed_cnt > 0 {
+            println!(
+                "{:?} files skipped because formatting would lose a comment or change a token\n",
+                verify_failed_cnt
+            );
+        }
+        if io_failed_cnt > 0 {
+            println!("{:?} files skipped because of io error\n", io_failed_cnt);
+        }
     }
+    if emit_report || !report_files.is_empty() {
+        println!("{}", render_report(report_emit_mode, report_files));
+    }
+    if !version_mismatches.is_empty() {
+        return Err(format_err!(version_mismatches.join("\n")));
+    }
+
+    let fmt_failed_cnt = parse_failed_cnt
+        + verify_failed_cnt
+        + not_idempotent_cnt
+        + line_overflow_cnt
+        + kept_verbatim_cnt;
+    if !check_mode && io_failed_cnt == 0 && fmt_failed_cnt > 0 {
+        return Err(MoveFmtError::ErrFmt(ERR_FMT).into());
+    }
+    Ok(check_exit_code(
+        check_mode,
+        not_formatted_cnt,
+        fmt_failed_cnt,
+        io_failed_cnt,
+    ))
+}
+
+/// Whether `emit_mode` prints one report of all the files instead of handling them one by one.
+fn emits_report(emit_mode: EmitMode) -> bool {
+    matches!(
+        emit_mode,
+        EmitMode::Json | EmitMode::Checkstyle | EmitMode::Sarif
+    )
+}
+
+fn render_report(emit_mode: EmitMode, files: Vec<JsonFileReport>) -> String {
+    match emit_mode {
+        EmitMode::Checkstyle => to_checkstyle(&files),
+        EmitMode::Sarif => to_sarif(&files),
+        _ => JsonReport { files }.to_json(),
+    }
+}
+
+/// Converts the outcome of formatting `file` to its entry in the report of
+/// `--emit json`, `--emit checkstyle` or `--emit sarif`.
+fn mk_json_file_report(file: &Path, status: FileStatus) -> JsonFileReport {
+    let name = file.display().to_string();
+    match status {
+        FileStatus::Formatted {
+            origin,
+            formatted,
+            line_overflows,
+            unformatted_blocks,
+            second_pass,
+            ..
+        } => {
+            let mut file_report = JsonFileReport::formatted(name, &origin, &formatted);
+            file_report.line_overflows = line_overflows;
+            file_report.unformatted_blocks = unformatted_blocks;
+            match second_pass {
+                Some(second_pass) => file_report.with_second_pass(&formatted, &second_pass),
+                None => file_report,
+            }
+        }
+        FileStatus::SkippedByConfig => {
+            JsonFileReport::not_formatted(name, JsonFileStatus::SkippedByConfig, None)
+        }
+        FileStatus::SkippedNotInPackage => {
+            JsonFileReport::not_formatted(name, JsonFileStatus::SkippedNotInPackage, None)
+        }
+        FileStatus::ParseError(diags_buf) => JsonFileReport::not_formatted(
+            name,
+            JsonFileStatus::ParseError,
+            Some(String::from_utf8_lossy(&diags_buf).into_owned()),
+        ),
+        FileStatus::VerifyError(msg) => {
+            JsonFileReport::not_formatted(name, JsonFileStatus::VerifyError, Some(msg))
+        }
+        FileStatus::IoError(msg) => {
+            JsonFileReport::not_formatted(name, JsonFileStatus::IoError, Some(msg))
+        }
+        FileStatus::VersionMismatch(_) => {
+            unreachable!("a version mismatch fails the run instead of being reported")
+        }
+    }
+}
 
-    Ok(0)
+/// Maps the per-run failure counts to the exit code of the run.
+/// An io error takes priority over a parse error, which takes priority over
+/// unformatted code, so that scripts can tell the most severe problem apart.
+/// An io error fails the run with or without `--check`, the other problems only
+/// change the exit code of `--check`.
+fn check_exit_code(
+    check_mode: bool,
+    not_formatted_cnt: usize,
+    parse_failed_cnt: usize,
+    io_failed_cnt: usize,
+) -> i32 {
+    if io_failed_cnt > 0 {
+        ERR_IO
+    } else if !check_mode {
+        0
+    } else if parse_failed_cnt > 0 {
+        ERR_FMT
+    } else if not_formatted_cnt > 0 {
+        ERR_CHECK_NOT_FORMATTED
+    } else {
+        0
+    }
 }
 
 fn print_usage_to_stdout(opts: &Options, reason: &str) {
@@ -497,7 +1113,21 @@ fn print_usage_to_stdout(opts: &Options, reason: &str) {
 }
 
 fn print_version() {
-    println!("movefmt v1.3.0");
+    println!("movefmt v{}", env!("CARGO_PKG_VERSION"));
+}
+
+/// Refuses to format with a config whose `required_version` doesn't accept this movefmt.
+fn check_required_version(config: &Config) -> Result<(), String> {
+    let version = env!("CARGO_PKG_VERSION");
+    match config.version_meets_requirement(version) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ErrorKind::VersionMismatch(
+            version.to_string(),
+            config.required_version(),
+        )
+        .to_string()),
+        Err(e) => Err(e),
+    }
 }
 
 fn determine_operation(matches: &Matches) -> Result<Operation, OperationError> {
@@ -560,6 +1190,39 @@ fn determine_operation(matches: &Matches) -> Result<Operation, OperationError> {
         }
     }
 
+    // the lines of one file say nothing about the lines of another one
+    if matches.opt_present("lines") && !matches.opt_present("stdin") && files.len() != 1 {
+        return Err(OperationError::InvalidInput(format!(
+            "`--lines` requires exactly one input file, found {}",
+            files.len()
+        )));
+    }
+
+    let git_changes = if let Some(rev) = matches.opt_str("changed-since") {
+        Some(GitChanges::Since(rev))
+    } else if matches.opt_present("staged") {
+        Some(GitChanges::Staged)
+    } else {
+        None
+    };
+    let mut file_lines = HashMap::new();
+    if let Some(git_changes) = git_changes {
+        let changed_files = changed_move_files(&git_changes)
+            .map_err(|e| OperationError::GitError(format!("{e:#}")))?;
+        if changed_files.is_empty() && files.is_empty() {
+            return Ok(Operation::NothingChanged);
+        }
+        for changed_file in changed_files {
+            let changed_lines = changed_file
+                .changed_lines
+                .filter(|_| matches.opt_present("changed-lines"));
+            if let Some(lines) = changed_lines {
+                file_lines.insert(changed_file.path.clone(), lines);
+            }
+            files.push((changed_file.path, false));
+        }
+    }
+
     if matches.opt_present("stdin") {
         let mut buffer = String::new();
         io::stdin().read_to_string(&mut buffer)?;
@@ -572,8 +1235,8 @@ fn determine_operation(matches: &Matches) -> Result<Operation, OperationError> {
             return Ok(Operation::Stdin {
                 exit_code: ERR_EMPTY_INPUT_FROM_STDIN,
             });
-        } else if let Ok(_) = format_string(buffer, options) {
-            return Ok(Operation::Stdin { exit_code: 0 });
+        } else if let Ok(exit_code) = format_string(buffer, options) {
+            return Ok(Operation::Stdin { exit_code });
         } else {
             tracing::error!(
                 "{}, please re-enter a valid move code",
@@ -585,7 +1248,7 @@ fn determine_operation(matches: &Matches) -> Result<Operation, OperationError> {
         }
     }
 
-    Ok(Operation::Format { files })
+    Ok(Operation::Format { files, file_lines })
 }
 
 /// Parsed command line options.
@@ -596,7 +1259,11 @@ struct GetOptsOptions {
     config_path: Option<PathBuf>,
     emit_mode: Option<EmitMode>,
     inline_config: HashMap<String, String>,
-    // use_functional: bool,
+    check: bool,
+    jobs: Option<usize>,
+    use_functional: bool,
+    lines: Option<(usize, usize)>,
+    verify_idempotent: bool,
 }
 
 impl GetOptsOptions {
@@ -613,16 +1280,55 @@ impl GetOptsOptions {
                 None
             },
             config_path: matches.opt_str("config-path").map(PathBuf::from),
-            // use_functional: matches.opt_present("functional"),
+            check: matches.opt_present("check"),
+            use_functional: matches.opt_present("functional"),
+            verify_idempotent: matches.opt_present("verify-idempotent"),
             ..Default::default()
         };
         if options.verbose.is_some() && options.quiet.is_some() {
             return Err(format_err!("Can't use both `--verbose` and `--quiet`"));
         }
 
+        if let Some(ref jobs_str) = matches.opt_str("jobs") {
+            match jobs_str.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => options.jobs = Some(jobs),
+                _ => return Err(format_err!("`--jobs` expects a positive integer")),
+            }
+        }
+        if let Some(ref lines_str) = matches.opt_str("lines") {
+            options.lines = Some(lines_from_str(lines_str)?);
+        }
+        let git_mode = matches.opt_present("changed-since") || matches.opt_present("staged");
+        if matches.opt_present("changed-since") && matches.opt_present("staged") {
+            return Err(format_err!(
+                "Can't use both `--changed-since` and `--staged`"
+            ));
+        }
+        if matches.opt_present("changed-lines") && !git_mode {
+            return Err(format_err!(
+                "`--changed-lines` requires `--changed-since` or `--staged`"
+            ));
+        }
+        if git_mode && options.lines.is_some() {
+            return Err(format_err!(
+                "Can't use `--lines` with `--changed-since` or `--staged`"
+            ));
+        }
         if let Some(ref emit_str) = matches.opt_str("emit") {
             options.emit_mode = Some(emit_mode_from_emit_str(emit_str)?);
         }
+        if options.check {
+            match options.emit_mode {
+                None | Some(EmitMode::Diff) => options.emit_mode = Some(EmitMode::Diff),
+                Some(emit_mode) if emits_report(emit_mode) => {}
+                Some(_) => {
+                    return Err(format_err!(
+                        "Can't use `--check` with `--emit` other than `diff`, `json`, \
+                         `checkstyle` or `sarif`"
+                    ));
+                }
+            }
+        }
         options.inline_config = matches
             .opt_strs("config")
             .iter()
@@ -661,9 +1367,16 @@ impl CliOptions for GetOptsOptions {
         if let Some(emit_mode) = self.emit_mode {
             config.set().emit_mode(emit_mode);
         }
-        for (key, val) in self.inline_config {
+        let mut inline_config: Vec<_> = self.inline_config.into_iter().collect();
+        // `unstable_features` first, it gates the other options
+        inline_config.sort_by_key(|(key, _)| key != "unstable_features");
+        for (key, val) in inline_config {
             config.override_value(&key, &val);
         }
+        if self.use_functional {
+            // gated by `unstable_features` like `--config use_functional_formatter=true`
+            config.override_value("use_functional_formatter", "true");
+        }
     }
 
     fn config_path(&self) -> Option<&Path> {
@@ -671,12 +1384,28 @@ impl CliOptions for GetOptsOptions {
     }
 }
 
+fn lines_from_str(lines_str: &str) -> Result<(usize, usize)> {
+    let parsed = lines_str
+        .split_once(':')
+        .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
+    match parsed {
+        Some((start, end)) if start > 0 && start <= end => Ok((start, end)),
+        _ => Err(format_err!(
+            "`--lines` expects `START:END` with 1 <= START <= END, found `{}`",
+            lines_str
+        )),
+    }
+}
+
 fn emit_mode_from_emit_str(emit_str: &str) -> Result<EmitMode> {
     match emit_str {
         "overwrite" => Ok(EmitMode::Overwrite),
         "new_file" => Ok(EmitMode::NewFile),
         "stdout" => Ok(EmitMode::Stdout),
         "diff" => Ok(EmitMode::Diff),
+        "json" => Ok(EmitMode::Json),
+        "checkstyle" => Ok(EmitMode::Checkstyle),
+        "sarif" => Ok(EmitMode::Sarif),
         _ => Err(format_err!("Invalid value for `--emit`")),
     }
 }
@@ -705,6 +1434,23 @@ fn should_escape(
     escape
 }
 
+/// Whether `file` matches the `ignore` option or a `.movefmtignore` file, each relative
+/// to the directory of the config file it comes from. `--config ignore=...` is relative to
+/// the directory of movefmt.toml, or the current one without movefmt.toml.
+fn should_ignore(
+    file: &Path,
+    use_config: &Config,
+    config_path: Option<&Path>,
+) -> Result<bool, ErrorKind> {
+    let root = match config_path.and_then(|path| path.parent()) {
+        Some(root) => root.to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    use_config
+        .is_ignored(file, &root)
+        .map_err(ErrorKind::InvalidGlobPattern)
+}
+
 fn should_escape_not_in_package(file: &Path, use_config: &Config) -> bool {
     if !use_config.auto_apply_package() {
         return false;
@@ -0,0 +1,233 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! A language server speaking LSP over stdio, which exposes movefmt through
+//! `textDocument/formatting`, `textDocument/rangeFormatting` and `textDocument/onTypeFormatting`.
+
+use anyhow::Result;
+use commentfmt::{CliOptions, Config, ErrorKind, Verbosity, load_config};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentOnTypeFormattingOptions, DocumentOnTypeFormattingParams,
+    DocumentRangeFormattingParams, OneOf, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as LspNotification,
+    },
+    request::{Formatting, OnTypeFormatting, RangeFormatting, Request as LspRequest},
+};
+use movefmt::{
+    core::fmt_range::lines_to_byte_range, core::format_source, tools::text_edits::mk_text_edits,
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use tracing_subscriber::EnvFilter;
+
+/// Options applied on top of every `movefmt.toml` found by the server.
+struct LspOptions;
+
+impl CliOptions for LspOptions {
+    fn apply_to(self, config: &mut Config) {
+        // stdout carries the LSP messages, the formatter must not print anything on it.
+        config.set().verbose(Verbosity::Quiet);
+    }
+
+    fn config_path(&self) -> Option<&std::path::Path> {
+        None
+    }
+}
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::try_from_env("MOVEFMT_LOG").unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .init();
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: "}".to_string(),
+            more_trigger_character: Some(vec![";".to_string()]),
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    tracing::info!("movefmt-lsp initialized");
+
+    main_loop(connection)?;
+    io_threads.join()?;
+    tracing::info!("movefmt-lsp shutting down");
+    Ok(())
+}
+
+fn main_loop(connection: Connection) -> Result<()> {
+    // Full text of the documents opened by the client, keyed by uri.
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                let resp = on_request(&documents, req);
+                connection.sender.send(Message::Response(resp))?;
+            }
+            Message::Notification(notification) => on_notification(&mut documents, notification),
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn on_notification(documents: &mut HashMap<Url, String>, notification: Notification) {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            if let Ok(params) = parse_params::<DidOpenTextDocumentParams>(notification.params) {
+                documents.insert(params.text_document.uri, params.text_document.text);
+            }
+        }
+        DidChangeTextDocument::METHOD => {
+            if let Ok(mut params) = parse_params::<DidChangeTextDocumentParams>(notification.params)
+            {
+                // Only full document sync is advertised, the last change holds the whole text.
+                if let Some(change) = params.content_changes.pop() {
+                    documents.insert(params.text_document.uri, change.text);
+                }
+            }
+        }
+        DidCloseTextDocument::METHOD => {
+            if let Ok(params) = parse_params::<DidCloseTextDocumentParams>(notification.params) {
+                documents.remove(&params.text_document.uri);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn on_request(documents: &HashMap<Url, String>, req: Request) -> Response {
+    let id = req.id.clone();
+    // the document to format, and the range of it for range and on type formatting
+    let target = match req.method.as_str() {
+        Formatting::METHOD => parse_params::<DocumentFormattingParams>(req.params)
+            .map(|params| (params.text_document.uri, None)),
+        RangeFormatting::METHOD => parse_params::<DocumentRangeFormattingParams>(req.params)
+            .map(|params| (params.text_document.uri, Some(params.range))),
+        OnTypeFormatting::METHOD => {
+            parse_params::<DocumentOnTypeFormattingParams>(req.params).map(|params| {
+                let position = params.text_document_position;
+                let range = Range::new(position.position, position.position);
+                (position.text_document.uri, Some(range))
+            })
+        }
+        _ => {
+            return Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request: {}", req.method),
+            );
+        }
+    };
+    let result = target.map(|(uri, range)| format_document(documents, &uri, range.as_ref()));
+    mk_response(id, result)
+}
+
+fn mk_response(id: RequestId, result: Result<Option<Vec<TextEdit>>>) -> Response {
+    match result {
+        Ok(edits) => Response::new_ok(id, edits),
+        Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, format!("{e:#}")),
+    }
+}
+
+fn parse_params<P: DeserializeOwned>(params: serde_json::Value) -> Result<P> {
+    Ok(serde_json::from_value(params)?)
+}
+
+/// Format the document and return the edits which turn its text into the formatted one.
+/// If `range` is given, only the top level items overlapping its lines are formatted.
+/// `None` means the document could not be formatted, e.g. because it does not parse.
+fn format_document(
+    documents: &HashMap<Url, String>,
+    uri: &Url,
+    range: Option<&Range>,
+) -> Option<Vec<TextEdit>> {
+    let path = uri.to_file_path().ok();
+    let content = match documents.get(uri) {
+        Some(content) => content.clone(),
+        None => std::fs::read_to_string(path.as_ref()?).ok()?,
+    };
+
+    // Pick up the movefmt.toml of the document's directory, like the movefmt binary does.
+    let config = match load_config(path.as_ref().and_then(|p| p.parent()), Some(LspOptions)) {
+        Ok((config, Some(config_path))) => match (path.as_ref(), config_path.parent()) {
+            (Some(path), Some(root)) => {
+                let mut config = config.for_file(path, root);
+                LspOptions.apply_to(&mut config);
+                config
+            }
+            _ => config,
+        },
+        Ok((config, None)) => config,
+        Err(e) => {
+            tracing::warn!("failed to load config for {uri}: {e}");
+            return None;
+        }
+    };
+
+    match config.version_meets_requirement(env!("CARGO_PKG_VERSION")) {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(
+                "{uri} skipped: {}",
+                ErrorKind::VersionMismatch(
+                    env!("CARGO_PKG_VERSION").to_string(),
+                    config.required_version()
+                )
+            );
+            return None;
+        }
+        Err(e) => {
+            tracing::warn!("{uri} skipped: {e}");
+            return None;
+        }
+    }
+
+    let formatted = catch_unwind(AssertUnwindSafe(|| {
+        let byte_range = range.map(|range| {
+            let lines = (range.start.line as usize + 1, range.end.line as usize + 1);
+            lines_to_byte_range(&content, lines.0, lines.1)
+        });
+        format_source(
+            &content,
+            config,
+            byte_range.as_ref().map(std::slice::from_ref),
+        )
+        .map(|output| {
+            if !output.unformatted_blocks.is_empty() {
+                tracing::warn!(
+                    "{uri}: kept {} block(s) which don't parse verbatim",
+                    output.unformatted_blocks.len()
+                );
+            }
+            output.text
+        })
+    }));
+    match formatted {
+        Ok(Ok(formatted)) => Some(mk_text_edits(&content, &formatted)),
+        Ok(Err(_)) => {
+            tracing::debug!("{uri} skipped because of parse not ok");
+            None
+        }
+        Err(_) => {
+            tracing::error!("formatter panicked on {uri}");
+            None
+        }
+    }
+}
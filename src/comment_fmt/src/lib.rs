@@ -4,7 +4,9 @@ use std::io;
 use std::path::PathBuf;
 use thiserror::Error;
 
-pub use configurations::config::{load_config, CliOptions, Config, EmitMode, Verbosity};
+pub use configurations::config::{
+    load_config, CliOptions, Config, EmitMode, IgnoreList, IgnoreMatcher, StyleEdition, Verbosity,
+};
 
 #[macro_use]
 pub mod utils;
@@ -36,12 +38,19 @@ pub enum ErrorKind {
     #[error("parse error")]
     ParseError,
     /// The user mandated a version and the current version of movefmt does not
-    /// satisfy that requirement.
-    #[error("version mismatch")]
-    VersionMismatch,
+    /// satisfy that requirement (version, requirement).
+    #[error("movefmt {0} doesn't satisfy `required_version = \"{1}\"`")]
+    VersionMismatch(String, String),
     /// If we had formatted the given node, then we would have lost a comment.
     #[error("not formatted because a comment would be lost")]
     LostComment,
+    /// If we had formatted the given node, then a token would have been lost or changed.
+    #[error("not formatted because a token would be lost or changed: {0}")]
+    LostToken(String),
+    /// If we had formatted the given node, then some code marked with `#[fmt::skip]`
+    /// or `// movefmt::off` would not have been kept verbatim.
+    #[error("not formatted because some code to keep verbatim could not be restored: {0}")]
+    LostSkippedCode(String),
     /// Invalid glob pattern in `ignore` configuration option.
     #[error("Invalid glob pattern found in ignore list: {0}")]
     InvalidGlobPattern(ignore::Error),
@@ -149,7 +149,7 @@ fn impl_from_str(ident: &syn::Ident, variants: &Variants) -> TokenStream {
 }
 
 fn doc_hint_of_variant(variant: &syn::Variant) -> String {
-    let mut text = find_doc_hint(&variant.attrs).unwrap_or(variant.ident.to_string());
+    let mut text = find_doc_hint(&variant.attrs).unwrap_or(config_value_of_variant(variant));
     if unstable_of_variant(&variant) {
         text.push_str(" (unstable)")
     };
@@ -48,6 +48,8 @@ macro_rules! create_config {
     ($($i:ident: $ty:ty, $def:expr, $stb:expr, $( $dstring:expr ),+ );+ $(;)*) => (
         #[cfg(test)]
         use std::collections::HashSet;
+        use std::collections::HashMap;
+        use std::io::Write;
 
         use serde::{Deserialize, Serialize};
 
@@ -59,8 +61,14 @@ macro_rules! create_config {
             // - 0: true if the value has been access
             // - 1: true if the option was manually initialized
             // - 2: the option value
-            // - 3: true if the option is unstable
-            $($i: (Cell<bool>, bool, $ty, bool)),+
+            // - 3: true if the option is stable
+            $($i: (Cell<bool>, bool, $ty, bool)),+,
+            // The `[[overrides]]` of movefmt.toml, applied by `for_file`.
+            overrides: Vec<ConfigOverride>,
+            // Where the options which are not defaults were set.
+            sources: HashMap<String, OptionSource>,
+            // The directories of the config files read, for their `.movefmtignore`.
+            config_dirs: Vec<PathBuf>,
         }
 
         // Just like the Config struct but with each property wrapped
@@ -128,12 +136,20 @@ macro_rules! create_config {
             }
 
             fn fill_from_parsed_config(mut self, parsed: PartialConfig) -> Config {
+                // `unstable_features` gates the other options, wherever it is in the file
+                let unstable_features = parsed.unstable_features
+                    .unwrap_or(self.unstable_features.2);
+                let default = Config::default();
             $(
                 if let Some(option_value) = parsed.$i {
                     let option_stable = self.$i.3;
-                    if $crate::config::config_type::is_stable_option_and_value(
-                        stringify!($i), option_stable, &option_value
-                    ) {
+                    // an unstable option left at its default changes nothing,
+                    // e.g. in the file written by `--print-config default`
+                    if option_value == default.$i.2
+                        || $crate::config::config_type::is_stable_option_and_value(
+                            stringify!($i), option_stable, &option_value, unstable_features
+                        )
+                    {
                         self.$i.1 = true;
                         self.$i.2 = option_value;
                     }
@@ -205,12 +221,17 @@ macro_rules! create_config {
                                                  val,
                                                  stringify!($ty)));
 
-                            // Users are currently allowed to set unstable
-                            // options/variants via the `--config` options override.
-                            // For now, do not validate whether the option or value is stable,
-                            // just always set it.
+                            if option_value != Config::default().$i.2
+                                && !$crate::config::config_type::is_stable_option_and_value(
+                                    stringify!($i), self.$i.3, &option_value,
+                                    self.unstable_features.2
+                                )
+                            {
+                                return;
+                            }
                             self.$i.1 = true;
                             self.$i.2 = option_value;
+                            self.sources.insert(key.to_owned(), OptionSource::CommandLine);
                         }
                     )+
                     _ => panic!("Unknown config key in override: {}", key)
@@ -221,6 +242,31 @@ macro_rules! create_config {
                 }
             }
 
+            /// Writes the name, type, default value and description of every option,
+            /// the unstable ones being marked as such, for `--help config`.
+            #[allow(unreachable_pub)]
+            pub fn print_docs(out: &mut dyn Write) -> std::io::Result<()> {
+                let default = Config::default();
+                $(
+                    let stability = if default.$i.3 { "" } else { " (unstable)" };
+                    let default_value = ::toml::Value::try_from(default.$i.2.clone())
+                        .map_or(String::new(), |value| value.to_string());
+                    writeln!(
+                        out,
+                        "{}{} {} Default: {}",
+                        stringify!($i),
+                        stability,
+                        <$ty>::doc_hint(),
+                        default_value
+                    )?;
+                    $(
+                        writeln!(out, "\t{}", $dstring)?;
+                    )+
+                    writeln!(out)?;
+                )+
+                Ok(())
+            }
+
             #[allow(unreachable_pub)]
             /// Returns `true` if the config key was explicitly set and is the default value.
             pub fn is_default(&self, key: &str) -> bool {
@@ -240,6 +286,9 @@ macro_rules! create_config {
                     $(
                         $i: (Cell::new(false), false, $def, $stb),
                     )+
+                    overrides: vec![],
+                    sources: HashMap::new(),
+                    config_dirs: vec![],
                 }
             }
         }
@@ -250,30 +299,30 @@ pub fn is_stable_option_and_value<T>(
     option_name: &str,
     option_stable: bool,
     option_value: &T,
+    unstable_features: bool,
 ) -> bool
 where
     T: PartialEq + std::fmt::Debug + ConfigType,
 {
-    let nightly = false;
     let variant_stable = option_value.stable_variant();
-    match (nightly, option_stable, variant_stable) {
+    match (unstable_features, option_stable, variant_stable) {
         // Stable with an unstable option
         (false, false, _) => {
-            tracing::info!(
-                "Warning: can't set `{option_name} = {option_value:?}`, unstable features are only \
-                       available in nightly channel."
+            tracing::warn!(
+                "can't set `{option_name} = {option_value:?}`, unstable options are only \
+                       available with `unstable_features = true`."
             );
             false
         }
         // Stable with a stable option, but an unstable variant
         (false, true, false) => {
-            tracing::info!(
-                "Warning: can't set `{option_name} = {option_value:?}`, unstable variants are only \
-                       available in nightly channel."
+            tracing::warn!(
+                "can't set `{option_name} = {option_value:?}`, unstable variants are only \
+                       available with `unstable_features = true`."
             );
             false
         }
-        // Nightly: everything allowed
+        // unstable_features: everything allowed
         // Stable with stable option and variant: allowed
         (true, _, _) | (false, true, true) => true,
     }
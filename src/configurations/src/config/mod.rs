@@ -5,6 +5,7 @@ use std::io::{Error, ErrorKind, Read};
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use thiserror::Error;
 
 use crate::config::config_type::ConfigType;
@@ -29,12 +30,130 @@ create_config! {
     emit_mode: EmitMode, EmitMode::Overwrite, true,
         "What emit Mode to use when none is supplied";
     verbose: Verbosity, Verbosity::Normal, true, "How much to information to emit to the user";
+    style_edition: StyleEdition, StyleEdition::Edition2024, true, "Edition of the formatting rules, the output of an edition never changes";
+    unstable_features: bool, false, true, "Enable the unstable options and values, which may change or be removed in any release";
     prefer_one_line_for_short_branch_blk: bool, true, true, "Prefer one line for short branch block";
     prefer_one_line_for_short_call_para_list: bool, true, true, "Prefer one line for short parameters list in function call";
     prefer_one_line_for_short_fn_header_para_list: bool, true, true, "Prefer one line for short parameters list in fun header";
     prefer_one_line_for_short_lambda_para_list: bool, true, true, "Prefer one line for short parameters list in lambda";
     skip_formatting_dirs: String, "".to_string(), true, "Dirs to skip during formatting";
+    ignore: IgnoreList, IgnoreList::default(), true, "Gitignore style globs of the files to skip, relative to movefmt.toml";
+    required_version: String, String::new(), true, "Semver requirement on the version of movefmt, e.g. \">=1.3, <1.4\"";
     auto_apply_package: bool, false, true, "Auto-format all Move files that belong to Move-Package in the specified dir or default current dir";
+    use_functional_formatter: bool, false, false, "Use the functional formatter engine instead of the default one";
+    verify_formatting: bool, true, true, "Refuse the formatted result if it would lose a comment or change a token";
+    error_on_line_overflow: bool, false, true, "Fail if some lines are still wider than max_width after formatting";
+    error_tolerant: bool, false, true, "Format the modules which parse and keep the others verbatim when a file doesn't fully parse";
+}
+
+const OVERRIDES_KEY: &str = "overrides";
+const OVERRIDE_FILES_KEY: &str = "files";
+const EXTENDS_KEY: &str = "extends";
+const ROOT_KEY: &str = "root";
+const MOVE_TOML_FILE_NAME: &str = "Move.toml";
+const MOVE_TOML_SECTION: &str = "movefmt";
+const MOVE_TOML_TOOL_TABLE: &str = "tool";
+
+/// Where the value of an option comes from, see `Config::to_toml_with_sources`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionSource {
+    /// A config file, possibly reached through `extends` or the search of the parent directories.
+    File(PathBuf),
+    /// `--config key=value`.
+    CommandLine,
+}
+
+impl std::fmt::Display for OptionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionSource::File(path) => write!(f, "{}", path.display()),
+            OptionSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// An entry of `[[overrides]]` in movefmt.toml: options applied to the files matching
+/// any of the gitignore style glob patterns of `files`, e.g.
+///
+/// ```toml
+/// [[overrides]]
+/// files = ["**/tests/**", "*.spec.move"]
+/// max_width = 120
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConfigOverride {
+    /// Relative to the directory of movefmt.toml.
+    pub files: Vec<String>,
+    options: ::toml::Table,
+    /// The directory of the movefmt.toml defining the entry, if known.
+    root: Option<PathBuf>,
+}
+
+impl ConfigOverride {
+    fn from_toml(value: &::toml::Value, root: Option<&Path>) -> Result<ConfigOverride, String> {
+        let mut options = value
+            .as_table()
+            .ok_or_else(|| format!("Error: `{OVERRIDES_KEY}` must be an array of tables"))?
+            .clone();
+        let files = match options.remove(OVERRIDE_FILES_KEY) {
+            Some(::toml::Value::String(pattern)) => vec![pattern],
+            Some(::toml::Value::Array(patterns)) => patterns
+                .into_iter()
+                .map(|pattern| pattern.as_str().map(ToOwned::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("Error: `{OVERRIDE_FILES_KEY}` must be strings"))?,
+            _ => {
+                return Err(format!(
+                    "Error: every entry of `{OVERRIDES_KEY}` needs `{OVERRIDE_FILES_KEY}`"
+                ))
+            }
+        };
+        for key in options.keys() {
+            if !Config::is_valid_name(key) {
+                return Err(format!(
+                    "Error: Unknown configuration option `{key}` in `{OVERRIDES_KEY}`"
+                ));
+            }
+        }
+        let over_ride = ConfigOverride {
+            files,
+            options,
+            root: root.map(ToOwned::to_owned),
+        };
+        over_ride.matcher(Path::new(""))?;
+        over_ride.partial_config()?;
+        Ok(over_ride)
+    }
+
+    fn matcher(&self, root: &Path) -> Result<Gitignore, String> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in &self.files {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Error: Invalid glob pattern `{pattern}`: {e}"))?;
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    fn partial_config(&self) -> Result<PartialConfig, String> {
+        ::toml::Value::Table(self.options.clone())
+            .try_into()
+            .map_err(|e| format!("Error: Decoding `{OVERRIDES_KEY}` failed: {e}"))
+    }
+
+    /// Whether `file` matches any pattern, relative to `root`.
+    fn matches(&self, file: &Path, root: &Path) -> bool {
+        let Ok(relative) = file.strip_prefix(root) else {
+            return false;
+        };
+        self.matcher(root)
+            .map(|matcher| {
+                matcher
+                    .matched_path_or_any_parents(relative, false)
+                    .is_ignore()
+            })
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -58,26 +177,86 @@ impl Config {
     /// Returns a `Config` if the config could be read and parsed from
     /// the file, otherwise errors.
     pub(super) fn from_toml_path(file_path: &Path) -> Result<Config, Error> {
-        let mut file = File::open(&file_path)?;
-        let mut toml = String::new();
-        file.read_to_string(&mut toml)?;
-        Config::from_toml(&toml).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+        Config::default().fill_from_toml_path(file_path, &mut vec![])
+    }
+
+    /// Reads the config file `file_path` on top of this config, after the file it `extends`.
+    /// `chain` holds the files being read, to detect a cycle of `extends`.
+    fn fill_from_toml_path(
+        self,
+        file_path: &Path,
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<Config, Error> {
+        let file_path = fs::canonicalize(file_path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Error: reading `{}`: {e}", file_path.display()),
+            )
+        })?;
+        if chain.contains(&file_path) {
+            let cycle = chain
+                .iter()
+                .chain(std::iter::once(&file_path))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Error: `{EXTENDS_KEY}` makes a cycle: {cycle}"),
+            ));
+        }
+        let toml = read_toml(&file_path)?;
+        let mut config = self;
+        if let Some(dir) = file_path.parent() {
+            if !config
+                .config_dirs
+                .iter()
+                .any(|config_dir| config_dir == dir)
+            {
+                config.config_dirs.push(dir.to_path_buf());
+            }
+        }
+        match toml_table(&toml)?.get(EXTENDS_KEY) {
+            Some(::toml::Value::String(base)) => {
+                let base = file_path.parent().unwrap_or(Path::new("")).join(base);
+                chain.push(file_path.clone());
+                config = config.fill_from_toml_path(&base, chain)?;
+                chain.pop();
+            }
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Error: `{EXTENDS_KEY}` of `{}` must be a path",
+                        file_path.display()
+                    ),
+                ))
+            }
+            None => {}
+        }
+        config
+            .fill_from_toml(&toml, Some(&file_path))
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))
     }
 
     /// Resolves the config for input in `dir`.
     ///
     /// Searches for `movefmt.toml` beginning with `dir`, and
-    /// recursively checking parents of `dir` if no config file is found.
+    /// recursively checking parents of `dir`, as well as for the `[movefmt]` table of `Move.toml`,
+    /// which a `movefmt.toml` in the same directory overrides. The search stops at the nearest
+    /// directory with a config file, unless it sets `root = false`; the config files found are
+    /// then applied from the farthest to the nearest one.
     /// If no config file exists in `dir` or in any parent, a
     /// default `Config` will be returned (and the returned path will be empty).
     ///
-    /// Returns the `Config` to use, and the path of the project file if there was
+    /// Returns the `Config` to use, and the path of the nearest project file if there was
     /// one.
     pub(super) fn from_resolved_toml_path(dir: &Path) -> Result<(Config, Option<PathBuf>), Error> {
-        /// Try to find a project file in the given directory and its parents.
-        /// Returns the path of a the nearest project file if one exists,
-        /// or `None` if no project file was found.
-        fn resolve_project_file(dir: &Path) -> Result<Option<PathBuf>, Error> {
+        /// Try to find the project files in the given directory and its parents,
+        /// up to the first directory whose files don't set `root = false`.
+        /// Returns their paths, the nearest project file first,
+        /// or nothing if no project file was found.
+        fn resolve_project_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
             let mut current = if dir.is_relative() {
                 env::current_dir()?.join(dir)
             } else {
@@ -86,11 +265,25 @@ impl Config {
 
             current = fs::canonicalize(current)?;
 
+            let mut paths = vec![];
             loop {
-                match get_toml_path(&current) {
-                    Ok(Some(path)) => return Ok(Some(path)),
-                    Err(e) => return Err(e),
-                    _ => (),
+                // movefmt.toml wins over the `[movefmt]` table of the Move.toml next to it
+                let mut is_root = None;
+                let mut found = false;
+                for path in get_toml_path(&current)?
+                    .into_iter()
+                    .chain(get_move_toml_path(&current))
+                {
+                    if is_root.is_none() {
+                        is_root = toml_table(&read_toml(&path)?)?
+                            .get(ROOT_KEY)
+                            .and_then(::toml::Value::as_bool);
+                    }
+                    paths.push(path);
+                    found = true;
+                }
+                if found && is_root != Some(false) {
+                    return Ok(paths);
                 }
 
                 // If the current directory has no parent, we're done searching.
@@ -98,11 +291,14 @@ impl Config {
                     break;
                 }
             }
+            if !paths.is_empty() {
+                return Ok(paths);
+            }
 
             // If nothing was found, check in the home directory.
             if let Some(home_dir) = dirs::home_dir() {
                 if let Some(path) = get_toml_path(&home_dir)? {
-                    return Ok(Some(path));
+                    return Ok(vec![path]);
                 }
             }
 
@@ -110,20 +306,108 @@ impl Config {
             if let Some(mut config_dir) = dirs::config_dir() {
                 config_dir.push("movefmt");
                 if let Some(path) = get_toml_path(&config_dir)? {
-                    return Ok(Some(path));
+                    return Ok(vec![path]);
                 }
             }
 
-            Ok(None)
+            Ok(vec![])
+        }
+
+        let paths = resolve_project_files(dir)?;
+        let mut config = Config::default();
+        for path in paths.iter().rev() {
+            config = config.fill_from_toml_path(path, &mut vec![])?;
+        }
+        Ok((config, paths.into_iter().next()))
+    }
+
+    /// The config of `file`: this one with the options of every `[[overrides]]` entry
+    /// matching `file` applied in order, the patterns being relative to the directory of
+    /// the movefmt.toml defining the entry, or `root` if unknown.
+    pub fn for_file(&self, file: &Path, root: &Path) -> Config {
+        let absolute = |path: &Path| {
+            fs::canonicalize(path)
+                .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+                .unwrap_or_else(|_| path.to_path_buf())
+        };
+        let (file, root) = (absolute(file), absolute(root));
+        let mut config = self.clone();
+        for over_ride in &self.overrides {
+            let root = over_ride.root.as_deref().map_or(root.clone(), absolute);
+            if !over_ride.matches(&file, &root) {
+                continue;
+            }
+            match over_ride.partial_config() {
+                Ok(parsed_config) => config = config.fill_from_parsed_config(parsed_config),
+                Err(e) => tracing::warn!("{e}"),
+            }
+        }
+        config
+    }
+
+    /// Whether `file` is excluded by the `ignore` option, relative to the directory of the
+    /// config file setting it or to `default_root` if it was set on the command line,
+    /// or by the `.movefmtignore` file of `default_root` or of the directory of any config
+    /// file read, relative to that directory.
+    pub fn is_ignored(&self, file: &Path, default_root: &Path) -> Result<bool, ignore::Error> {
+        let ignore_root = match self.sources.get("ignore") {
+            Some(OptionSource::File(path)) => path.parent().unwrap_or(default_root),
+            _ => default_root,
+        };
+        if IgnoreMatcher::new(ignore_root, &self.ignore())?.is_ignored(file) {
+            return Ok(true);
+        }
+        for dir in &self.config_dirs {
+            if IgnoreMatcher::new(dir, &IgnoreList::default())?.is_ignored(file) {
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        match resolve_project_file(dir)? {
-            None => Ok((Config::default(), None)),
-            Some(path) => Config::from_toml_path(&path).map(|config| (config, Some(path))),
+    /// Whether `version`, the version of movefmt, satisfies `required_version`,
+    /// an empty `required_version` accepts any version.
+    pub fn version_meets_requirement(&self, version: &str) -> Result<bool, String> {
+        let required_version = self.required_version();
+        if required_version.trim().is_empty() {
+            return Ok(true);
         }
+        let requirement = semver::VersionReq::parse(&required_version).map_err(|e| {
+            format!("Error: invalid `required_version = \"{required_version}\"`: {e}")
+        })?;
+        let version = semver::Version::parse(version).map_err(|e| e.to_string())?;
+        Ok(requirement.matches(&version))
+    }
+
+    /// All the options as TOML, each one followed by a comment telling where its value
+    /// comes from, for `--print-config current`.
+    pub fn to_toml_with_sources(&self) -> Result<String, ToTomlError> {
+        let toml = self.all_options().to_toml()?;
+        Ok(toml
+            .lines()
+            .map(|line| {
+                let source = line
+                    .split_once(" = ")
+                    .filter(|(key, _)| Config::is_valid_name(key))
+                    .map(|(key, _)| {
+                        self.sources
+                            .get(key)
+                            .map_or("default".to_string(), ToString::to_string)
+                    });
+                match source {
+                    Some(source) => format!("{line} # {source}\n"),
+                    None => format!("{line}\n"),
+                }
+            })
+            .collect())
     }
 
     pub fn from_toml(toml: &str) -> Result<Config, String> {
+        Config::default().fill_from_toml(toml, None)
+    }
+
+    /// Sets the options of `toml` on top of this config, `file_path` is the file it was read from.
+    fn fill_from_toml(mut self, toml: &str, file_path: Option<&Path>) -> Result<Config, String> {
         let parsed: ::toml::Value = toml
             .parse()
             .map_err(|e| format!("Could not parse TOML: {}", e))?;
@@ -131,8 +415,27 @@ impl Config {
         let table = parsed
             .as_table()
             .ok_or_else(|| String::from("Parsed config was not table"))?;
+        let overrides = match table.get(OVERRIDES_KEY) {
+            Some(::toml::Value::Array(overrides)) => overrides
+                .iter()
+                .map(|over_ride| {
+                    ConfigOverride::from_toml(over_ride, file_path.and_then(Path::parent))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(_) => {
+                return Err(format!(
+                    "Error: `{OVERRIDES_KEY}` must be an array of tables"
+                ))
+            }
+            None => vec![],
+        };
         for key in table.keys() {
-            if !Config::is_valid_name(key) {
+            if Config::is_valid_name(key) {
+                if let Some(file_path) = file_path {
+                    self.sources
+                        .insert(key.to_owned(), OptionSource::File(file_path.to_owned()));
+                }
+            } else if ![OVERRIDES_KEY, EXTENDS_KEY, ROOT_KEY].contains(&key.as_str()) {
                 let msg = &format!("Warning: Unknown configuration option `{key}`\n");
                 err.push_str(msg)
             }
@@ -142,7 +445,9 @@ impl Config {
                 if !err.is_empty() {
                     eprint!("{err}");
                 }
-                Ok(Config::default().fill_from_parsed_config(parsed_config))
+                let mut config = self.fill_from_parsed_config(parsed_config);
+                config.overrides.extend(overrides);
+                Ok(config)
             }
             Err(e) => {
                 err.push_str("Error: Decoding config file failed:\n");
@@ -156,6 +461,9 @@ impl Config {
 
 /// Loads a config by checking the client-supplied options and if appropriate, the
 /// file system (including searching the file system for overrides).
+/// A config file given on the command line replaces the search from `file_path`, but the
+/// `[movefmt]` table of the Move.toml of the package of `file_path` still applies on top of it.
+/// The options given on the command line win over both.
 pub fn load_config<O: CliOptions>(
     file_path: Option<&Path>,
     options: Option<O>,
@@ -166,7 +474,13 @@ pub fn load_config<O: CliOptions>(
     };
 
     let result = if let Some(over_ride) = over_ride {
-        Config::from_toml_path(over_ride.as_ref()).map(|p| (p, Some(over_ride.to_owned())))
+        let config = Config::from_toml_path(over_ride.as_ref())?;
+        // the `[movefmt]` table of the package of `file_path` is more specific
+        let config = match file_path.and_then(get_package_move_toml_path) {
+            Some(move_toml) => config.fill_from_toml_path(&move_toml, &mut vec![])?,
+            None => config,
+        };
+        Ok((config, Some(over_ride.to_owned())))
     } else if let Some(file_path) = file_path {
         Config::from_resolved_toml_path(file_path)
     } else {
@@ -181,6 +495,72 @@ pub fn load_config<O: CliOptions>(
     })
 }
 
+/// The content of the config file `file_path`, only the `[movefmt]` or `[tool.movefmt]` table
+/// of a Move.toml, which is empty without one.
+fn read_toml(file_path: &Path) -> Result<String, Error> {
+    let mut file = File::open(file_path)?;
+    let mut toml = String::new();
+    file.read_to_string(&mut toml)?;
+    if file_path.file_name() == Some(MOVE_TOML_FILE_NAME.as_ref()) {
+        return match move_toml_section(&toml)? {
+            Some(section) => ::toml::to_string(&section)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string())),
+            None => Ok(String::new()),
+        };
+    }
+    Ok(toml)
+}
+
+/// The `[movefmt]` table of the Move.toml `toml`, or else its `[tool.movefmt]` table.
+fn move_toml_section(toml: &str) -> Result<Option<::toml::Table>, Error> {
+    let table = toml_table(toml)?;
+    let section = table.get(MOVE_TOML_SECTION).or_else(|| {
+        table
+            .get(MOVE_TOML_TOOL_TABLE)
+            .and_then(|tool| tool.get(MOVE_TOML_SECTION))
+    });
+    match section {
+        Some(::toml::Value::Table(section)) => Ok(Some(section.clone())),
+        Some(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Error: `{MOVE_TOML_SECTION}` of {MOVE_TOML_FILE_NAME} must be a table"),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// The Move.toml of `dir` if it has a `[movefmt]` or `[tool.movefmt]` table.
+/// A Move.toml which doesn't parse is left to the Move compiler to report.
+fn get_move_toml_path(dir: &Path) -> Option<PathBuf> {
+    let move_toml = dir.join(MOVE_TOML_FILE_NAME);
+    let toml = fs::read_to_string(&move_toml).ok()?;
+    match move_toml_section(&toml) {
+        Ok(section) => section.map(|_| move_toml),
+        Err(e) => {
+            tracing::warn!("ignoring `{}`: {e}", move_toml.display());
+            None
+        }
+    }
+}
+
+/// The Move.toml of the package containing `dir`, i.e. the nearest one in `dir` or its parents,
+/// if it has a `[movefmt]` or `[tool.movefmt]` table.
+fn get_package_move_toml_path(dir: &Path) -> Option<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() {
+        env::current_dir().ok()?
+    } else {
+        fs::canonicalize(dir).ok()?
+    };
+    dir.ancestors()
+        .find(|dir| dir.join(MOVE_TOML_FILE_NAME).is_file())
+        .and_then(get_move_toml_path)
+}
+
+fn toml_table(toml: &str) -> Result<::toml::Table, Error> {
+    toml.parse()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Could not parse TOML: {e}")))
+}
+
 // Check for the presence of known config file names (`movefmt.toml, `.movefmt.toml`) in `dir`
 //
 // Return the path if a config file exists, empty if no file exists, and Error for IO errors
@@ -233,3 +613,275 @@ fn config_path(options: &dyn CliOptions) -> Result<Option<PathBuf>, Error> {
         path => Ok(path.map(ToOwned::to_owned)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overrides_for_file() {
+        let config = Config::from_toml(
+            r#"
+max_width = 80
+
+[[overrides]]
+files = ["**/tests/**", "*.spec.move"]
+max_width = 120
+
+[[overrides]]
+files = "sources/legacy.move"
+max_width = 100
+prefer_one_line_for_short_call_para_list = false
+"#,
+        )
+        .unwrap();
+        let root = Path::new("/repo");
+        let max_width = |file: &str| config.for_file(&root.join(file), root).max_width();
+        assert_eq!(max_width("sources/m.move"), 80);
+        assert_eq!(max_width("tests/m.move"), 120);
+        assert_eq!(max_width("pkg/tests/unit/m.move"), 120);
+        assert_eq!(max_width("sources/m.spec.move"), 120);
+        assert_eq!(max_width("sources/legacy.move"), 100);
+        assert!(!config
+            .for_file(&root.join("sources/legacy.move"), root)
+            .prefer_one_line_for_short_call_para_list());
+        assert_eq!(
+            config
+                .for_file(Path::new("/other/tests/m.move"), root)
+                .max_width(),
+            80
+        );
+    }
+
+    #[test]
+    fn test_extends_and_root() {
+        let dir = env::temp_dir().join("movefmt_test_extends_and_root");
+        let package = dir.join("org/package");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(dir.join("movefmt.toml"), "indent_size = 8\n").unwrap();
+        fs::write(
+            dir.join("org/base.toml"),
+            "max_width = 120\ntab_spaces = 2\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("org/movefmt.toml"),
+            "extends = \"base.toml\"\ntab_spaces = 8\n",
+        )
+        .unwrap();
+        fs::write(package.join("movefmt.toml"), "hard_tabs = true\n").unwrap();
+
+        // only the nearest movefmt.toml by default
+        let (config, _) = Config::from_resolved_toml_path(&package).unwrap();
+        assert!(config.hard_tabs());
+        assert_eq!(config.tab_spaces(), 4);
+
+        fs::write(
+            package.join("movefmt.toml"),
+            "root = false\nhard_tabs = true\n",
+        )
+        .unwrap();
+
+        let (config, path) = Config::from_resolved_toml_path(&package).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        assert_eq!(path, Some(dir.join("org/package/movefmt.toml")));
+        assert!(config.hard_tabs());
+        assert_eq!(config.tab_spaces(), 8);
+        assert_eq!(config.max_width(), 120);
+        // org/movefmt.toml doesn't set `root = false`, the search stops there
+        assert_eq!(config.indent_size(), 4);
+
+        let toml = config.to_toml_with_sources().unwrap();
+        let source_of = |key: &str| {
+            toml.lines()
+                .find(|line| line.starts_with(&format!("{key} = ")))
+                .and_then(|line| line.split_once(" # "))
+                .map(|(_, source)| source.to_string())
+                .unwrap()
+        };
+        assert_eq!(
+            source_of("hard_tabs"),
+            dir.join("org/package/movefmt.toml").display().to_string()
+        );
+        assert_eq!(
+            source_of("tab_spaces"),
+            dir.join("org/movefmt.toml").display().to_string()
+        );
+        assert_eq!(
+            source_of("max_width"),
+            dir.join("org/base.toml").display().to_string()
+        );
+        assert_eq!(source_of("indent_size"), "default");
+
+        fs::write(dir.join("org/base.toml"), "extends = \"movefmt.toml\"\n").unwrap();
+        let err = Config::from_resolved_toml_path(&package).unwrap_err();
+        assert!(err.to_string().contains("cycle"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_toml_section() {
+        let dir = env::temp_dir().join("movefmt_test_move_toml_section");
+        let package = dir.join("package");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(
+            package.join("Move.toml"),
+            "[package]\nname = \"p\"\n\n[movefmt]\nroot = true\nmax_width = 100\nindent_size = 2\n",
+        )
+        .unwrap();
+        fs::write(dir.join("movefmt.toml"), "tab_spaces = 8\n").unwrap();
+        let (config, path) = Config::from_resolved_toml_path(&package).unwrap();
+        let package = fs::canonicalize(&package).unwrap();
+        assert_eq!(path, Some(package.join("Move.toml")));
+        assert_eq!(config.max_width(), 100);
+        assert_eq!(config.tab_spaces(), 4);
+
+        fs::write(package.join("movefmt.toml"), "max_width = 80\n").unwrap();
+        let (config, path) = Config::from_resolved_toml_path(&package).unwrap();
+        assert_eq!(path, Some(package.join("movefmt.toml")));
+        assert_eq!(config.max_width(), 80);
+        assert_eq!(config.indent_size(), 2);
+
+        fs::write(
+            package.join("Move.toml"),
+            "[package]\nname = \"p\"\n\n[tool.movefmt]\nroot = false\nindent_size = 3\n",
+        )
+        .unwrap();
+        let (config, _) = Config::from_resolved_toml_path(&package).unwrap();
+        assert_eq!(config.indent_size(), 3);
+        assert_eq!(config.tab_spaces(), 8);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_toml_section_with_config_path() {
+        struct ConfigPath(PathBuf);
+        impl CliOptions for ConfigPath {
+            fn apply_to(self, _: &mut Config) {}
+            fn config_path(&self) -> Option<&Path> {
+                Some(&self.0)
+            }
+        }
+
+        let dir = env::temp_dir().join("movefmt_test_move_toml_section_with_config_path");
+        let sources = dir.join("package/sources");
+        fs::create_dir_all(&sources).unwrap();
+        fs::write(
+            dir.join("package/Move.toml"),
+            "[package]\nname = \"p\"\n\n[movefmt]\nmax_width = 100\n",
+        )
+        .unwrap();
+        let global = dir.join("global.toml");
+        fs::write(&global, "max_width = 80\nindent_size = 2\n").unwrap();
+
+        let (config, path) = load_config(Some(&sources), Some(ConfigPath(global.clone()))).unwrap();
+        assert_eq!(path, Some(global.clone()));
+        assert_eq!(config.max_width(), 100);
+        assert_eq!(config.indent_size(), 2);
+
+        let (config, _) = load_config(Some(&dir), Some(ConfigPath(global))).unwrap();
+        assert_eq!(config.max_width(), 80);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ignore_relative_to_config_dir() {
+        let dir = env::temp_dir().join("movefmt_test_ignore_relative_to_config_dir");
+        let package = dir.join("package");
+        fs::create_dir_all(package.join("sources")).unwrap();
+        fs::write(dir.join("base.toml"), "ignore = [\"generated/*.move\"]\n").unwrap();
+        fs::write(dir.join(IGNORE_FILE_NAME), "vendor/\n").unwrap();
+        fs::write(
+            package.join("movefmt.toml"),
+            "extends = \"../base.toml\"\nmax_width = 100\n",
+        )
+        .unwrap();
+        fs::write(package.join(IGNORE_FILE_NAME), "sources/legacy.move\n").unwrap();
+
+        let (config, _) = Config::from_resolved_toml_path(&package).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let package = dir.join("package");
+        let is_ignored = |file: &Path| config.is_ignored(file, &package).unwrap();
+        // `ignore` of base.toml is relative to its own directory
+        assert!(is_ignored(&dir.join("generated/m.move")));
+        assert!(!is_ignored(&package.join("generated/m.move")));
+        assert!(is_ignored(&dir.join("vendor/m.move")));
+        assert!(is_ignored(&package.join("sources/legacy.move")));
+        assert!(!is_ignored(&package.join("sources/m.move")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_required_version() {
+        let config = |required: &str| {
+            let mut config = Config::default();
+            config.set().required_version(required.to_string());
+            config
+        };
+        assert_eq!(config("").version_meets_requirement("1.3.0"), Ok(true));
+        assert_eq!(
+            config(">=1.3, <1.4").version_meets_requirement("1.3.2"),
+            Ok(true)
+        );
+        assert_eq!(
+            config(">=1.3, <1.4").version_meets_requirement("1.4.0"),
+            Ok(false)
+        );
+        assert_eq!(config("~1.2").version_meets_requirement("1.3.0"), Ok(false));
+        assert!(config("latest").version_meets_requirement("1.3.0").is_err());
+    }
+
+    #[test]
+    fn test_unstable_features() {
+        let config =
+            Config::from_toml("use_functional_formatter = true\nstyle_edition = \"2025\"\n")
+                .unwrap();
+        assert!(!config.use_functional_formatter());
+        assert_eq!(config.style_edition(), StyleEdition::Edition2024);
+
+        let config = Config::from_toml(
+            "use_functional_formatter = true\nstyle_edition = \"2025\"\nunstable_features = true\n",
+        )
+        .unwrap();
+        assert!(config.use_functional_formatter());
+        assert_eq!(config.style_edition(), StyleEdition::Edition2025);
+
+        let mut config = Config::default();
+        config.override_value("style_edition", "2025");
+        assert_eq!(config.style_edition(), StyleEdition::Edition2024);
+        config.override_value("unstable_features", "true");
+        config.override_value("style_edition", "2025");
+        assert_eq!(config.style_edition(), StyleEdition::Edition2025);
+
+        let mut docs = vec![];
+        Config::print_docs(&mut docs).unwrap();
+        let docs = String::from_utf8(docs).unwrap();
+        assert!(docs.contains("use_functional_formatter (unstable) <boolean> Default: false"));
+        assert!(docs.contains("style_edition [2024|2025 (unstable)] Default: \"2024\""));
+        assert!(docs.contains("max_width <unsigned integer> Default: 90"));
+    }
+
+    #[test]
+    fn test_print_config_default_round_trip() {
+        // what `--print-config default` writes
+        let toml = Config::default().all_options().to_toml().unwrap();
+        let config = Config::from_toml(&toml).unwrap();
+        // an option dropped by the `unstable_features` gate would not be set
+        for key in Config::hash_set() {
+            assert!(config.is_default(&key), "`{key}` was not read back");
+        }
+        assert!(!config.use_functional_formatter());
+
+        let mut config = Config::default();
+        config.override_value("use_functional_formatter", "false");
+        assert!(config.is_default("use_functional_formatter"));
+    }
+
+    #[test]
+    fn test_invalid_overrides() {
+        assert!(Config::from_toml("[[overrides]]\nmax_width = 120").is_err());
+        assert!(Config::from_toml("[[overrides]]\nfiles = \"*.move\"\nwidth = 120").is_err());
+        assert!(Config::from_toml("[[overrides]]\nfiles = \"*.move\"\nmax_width = \"x\"").is_err());
+        assert!(Config::from_toml("overrides = 1").is_err());
+    }
+}
@@ -1,6 +1,10 @@
+use crate::config::config_type::ConfigType;
 use crate::config::Config;
 use config_proc_macro::config_type;
-use std::path::Path;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 /// What movefmt should emit. Mostly corresponds to the `--emit` command line
 /// option.
@@ -17,6 +21,14 @@ pub enum EmitMode {
     /// This option is designed to be run in CI where a non-zero exit signifies
     /// non-standard code formatting. Used for `--check`.
     Diff,
+    /// Prints one JSON document reporting the status, the changed hunks and
+    /// the diagnostics of every file, without writing anything.
+    Json,
+    /// Prints the changed hunks as a checkstyle XML report, without writing anything.
+    Checkstyle,
+    /// Prints the changed hunks as a SARIF log for code scanning tools,
+    /// without writing anything.
+    Sarif,
 }
 
 /// How chatty should movefmt be?
@@ -30,6 +42,40 @@ pub enum Verbosity {
     Quiet,
 }
 
+/// The formatting rules movefmt applies. The output of an edition never changes:
+/// a change of the heuristics only applies from the edition it comes with,
+/// e.g. behind `config.style_edition().at_least(StyleEdition::Edition2025)`.
+#[config_type]
+pub enum StyleEdition {
+    /// The rules of movefmt 1.3.
+    #[value = "2024"]
+    Edition2024,
+    /// The next edition, its rules may still change: a block never starts with a blank line.
+    #[value = "2025"]
+    #[unstable_variant]
+    Edition2025,
+}
+
+impl StyleEdition {
+    /// Whether this edition includes the rules of `edition`.
+    pub fn at_least(self, edition: StyleEdition) -> bool {
+        self.year() >= edition.year()
+    }
+
+    fn year(self) -> u16 {
+        match self {
+            StyleEdition::Edition2024 => 2024,
+            StyleEdition::Edition2025 => 2025,
+        }
+    }
+}
+
+impl Default for StyleEdition {
+    fn default() -> StyleEdition {
+        StyleEdition::Edition2024
+    }
+}
+
 impl Default for EmitMode {
     fn default() -> EmitMode {
         EmitMode::Overwrite
@@ -42,3 +88,104 @@ pub trait CliOptions {
     fn apply_to(self, config: &mut Config);
     fn config_path(&self) -> Option<&Path>;
 }
+
+/// Gitignore style glob patterns of the files movefmt leaves alone,
+/// e.g. `ignore = ["**/build/**", "generated/*.move"]`.
+/// On the command line, the patterns are separated by `;`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct IgnoreList(Vec<String>);
+
+impl IgnoreList {
+    pub fn patterns(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl FromStr for IgnoreList {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(IgnoreList(
+            s.split(';')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(ToOwned::to_owned)
+                .collect(),
+        ))
+    }
+}
+
+impl ConfigType for IgnoreList {
+    fn doc_hint() -> String {
+        String::from("[<string>,..]")
+    }
+}
+
+/// Name of the file listing more `ignore` patterns, next to movefmt.toml.
+pub const IGNORE_FILE_NAME: &str = ".movefmtignore";
+
+/// Matches the files excluded by the `ignore` option and by the `.movefmtignore` file of `root`,
+/// the directory of movefmt.toml, the patterns being relative to `root`.
+pub struct IgnoreMatcher {
+    root: PathBuf,
+    matcher: Gitignore,
+}
+
+impl IgnoreMatcher {
+    pub fn new(root: &Path, ignore: &IgnoreList) -> Result<IgnoreMatcher, ignore::Error> {
+        let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut builder = GitignoreBuilder::new(&root);
+        let ignore_file = root.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(ignore_file) {
+                return Err(e);
+            }
+        }
+        for pattern in ignore.patterns() {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(IgnoreMatcher {
+            root,
+            matcher: builder.build()?,
+        })
+    }
+
+    /// Whether `file` or one of its parent directories is ignored,
+    /// a file out of `root` never is.
+    pub fn is_ignored(&self, file: &Path) -> bool {
+        let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        match file.strip_prefix(&self.root) {
+            Ok(relative) => self
+                .matcher
+                .matched_path_or_any_parents(relative, file.is_dir())
+                .is_ignore(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ignore_matcher() {
+        let root = std::env::temp_dir().join("movefmt_test_ignore_matcher");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join(IGNORE_FILE_NAME), "# vendored\nvendor/\n").unwrap();
+        let ignore: IgnoreList = "**/build/**; generated/*.move".parse().unwrap();
+        let matcher = IgnoreMatcher::new(&root, &ignore).unwrap();
+        assert!(matcher.is_ignored(&root.join("build/m.move")));
+        assert!(matcher.is_ignored(&root.join("pkg/build/sources/m.move")));
+        assert!(matcher.is_ignored(&root.join("generated/m.move")));
+        assert!(!matcher.is_ignored(&root.join("generated/sub/m.move")));
+        assert!(matcher.is_ignored(&root.join("vendor/dep/m.move")));
+        assert!(!matcher.is_ignored(&root.join("sources/m.move")));
+        assert!(!matcher.is_ignored(Path::new("/elsewhere/build/m.move")));
+
+        let invalid: IgnoreList = "generated/{a,b".parse().unwrap();
+        assert!(IgnoreMatcher::new(&root, &invalid).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -4,7 +4,9 @@ use std::io;
 use std::path::PathBuf;
 use thiserror::Error;
 
-pub use crate::config::{load_config, CliOptions, Config, EmitMode, Verbosity};
+pub use crate::config::{
+    load_config, CliOptions, Config, EmitMode, IgnoreList, IgnoreMatcher, StyleEdition, Verbosity,
+};
 
 #[macro_use]
 pub mod config;
@@ -33,9 +35,9 @@ pub enum ErrorKind {
     #[error("parse error")]
     ParseError,
     /// The user mandated a version and the current version of movefmt does not
-    /// satisfy that requirement.
-    #[error("version mismatch")]
-    VersionMismatch,
+    /// satisfy that requirement (version, requirement).
+    #[error("movefmt {0} doesn't satisfy `required_version = \"{1}\"`")]
+    VersionMismatch(String, String),
     /// If we had formatted the given node, then we would have lost a comment.
     #[error("not formatted because a comment would be lost")]
     LostComment,
@@ -2,19 +2,20 @@
 // Copyright (c) The BitsLab.MoveBit Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::core::fmt_layout::*;
+use crate::core::fmt_verify::verify_formatting;
 use crate::core::token_tree::*;
 use crate::syntax_fmt::bin_op_fmt::BinOpHandler;
 use crate::syntax_fmt::branch_fmt::BranchHandler;
 use crate::syntax_fmt::call_fmt::CallHandler;
-use crate::syntax_fmt::fun_fmt::FunHandler;
 use crate::syntax_fmt::let_fmt::LetHandler;
 use crate::syntax_fmt::quant_fmt::QuantHandler;
-use crate::syntax_fmt::skip_fmt::{SkipHandler, SkipType};
+use crate::syntax_fmt::skip_fmt::{SkipHandler, block_body_type, item_config, keep_skipped_code};
 use crate::syntax_fmt::syntax_handler::SyntaxHandler;
 use crate::syntax_fmt::{big_block_fmt, expr_fmt, fun_fmt, spec_fmt};
 use crate::tools::utils::*;
 use commentfmt::comment::contains_comment;
-use commentfmt::{Config, Verbosity};
+use commentfmt::{Config, ErrorKind, Verbosity};
 use move_command_line_common::files::FileHash;
 use move_compiler::diagnostics::Diagnostics;
 use move_compiler::parser::lexer::{Lexer, Tok};
@@ -22,15 +23,17 @@ use move_compiler::parser::{ast::*, syntax::parse_file_string};
 use move_ir_types::location::ByteIndex;
 use std::cell::Cell;
 use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
 use std::result::Result::*;
 use std::sync::Arc;
 
-const EXIST_MULTI_MODULE_TAG: &str = "module fmt";
-const EXIST_MULTI_ADDRESS_TAG: &str = "address fmt";
+pub(crate) const EXIST_MULTI_MODULE_TAG: &str = "module fmt";
+pub(crate) const EXIST_MULTI_ADDRESS_TAG: &str = "address fmt";
 
-const MAX_ANALYZE_LENGTH: usize = 64;
-const MIN_BREAK_LENGTH: usize = 32;
-const MIN_NESTED_LENGTH: usize = 16;
+pub(crate) const MAX_ANALYZE_LENGTH: usize = 64;
+pub(crate) const MIN_BREAK_LENGTH: usize = 32;
+pub(crate) const MIN_NESTED_LENGTH: usize = 16;
 
 pub struct FormatContext {
     pub content: String,
@@ -53,8 +56,10 @@ impl FormatContext {
 }
 
 pub struct Format {
-    pub(crate) local_cfg: FormatConfig,
-    pub(crate) global_cfg: Config,
+    pub(crate) global_cfg: Rc<Config>,
+    /// The configs of the module and function bodies being formatted which override
+    /// some options with attributes, the innermost last, see `Format::cfg`.
+    pub(crate) item_cfg_stack: RefCell<Vec<Rc<Config>>>,
     pub(crate) depth: Cell<usize>,
     pub(crate) token_tree: Vec<TokenTree>,
     pub(crate) comments: Vec<Comment>,
@@ -66,13 +71,7 @@ pub struct Format {
     pub(crate) syntax_handler: SyntaxHandler,
 }
 
-#[derive(Clone, Default)]
-pub struct FormatConfig {
-    pub(crate) indent_size: usize,
-    pub(crate) max_len_no_add_line: f32,
-}
-
-fn is_bin_op(op_token: Tok) -> bool {
+pub(crate) fn is_bin_op(op_token: Tok) -> bool {
     matches!(
         op_token,
         Tok::Equal
@@ -100,36 +99,7 @@ fn is_bin_op(op_token: Tok) -> bool {
     )
 }
 
-fn is_statement_start_token(tok: Tok) -> bool {
-    matches!(
-        tok,
-        Tok::Friend
-            | Tok::Const
-            | Tok::Fun
-            | Tok::While
-            | Tok::Use
-            | Tok::Struct
-            | Tok::Spec
-            | Tok::Return
-            | Tok::Public
-            | Tok::Native
-            | Tok::Inline
-            | Tok::Move
-            | Tok::Module
-            | Tok::Loop
-            | Tok::Let
-            | Tok::Invariant
-            | Tok::If
-            | Tok::Continue
-            | Tok::Break
-            | Tok::NumSign
-            | Tok::Amp
-            | Tok::LParen
-            | Tok::Abort
-    )
-}
-
-fn token_to_ability(token: Tok, content: &str) -> Option<Ability_> {
+pub(crate) fn token_to_ability(token: Tok, content: &str) -> Option<Ability_> {
     match (token, content) {
         (Tok::Copy, _) => Some(Ability_::Copy),
         (Tok::Identifier, Ability_::DROP) => Some(Ability_::Drop),
@@ -139,7 +109,103 @@ fn token_to_ability(token: Tok, content: &str) -> Option<Ability_> {
     }
 }
 
-fn tune_module_buf(module_body: String, config: &Config) -> String {
+/// The config the code is laid out with. The formatter only indents with spaces,
+/// so with `hard_tabs` one level of indentation is laid out as `tab_spaces` spaces:
+/// line widths are then measured as rendered, and `apply_hard_tabs` turns the
+/// leading spaces into tabs once the formatting is done.
+pub(crate) fn layout_config(config: &Config) -> Config {
+    let mut layout_cfg = config.clone();
+    if config.hard_tabs() {
+        layout_cfg.set().indent_size(config.tab_spaces());
+        layout_cfg.set().hard_tabs(false);
+    }
+    layout_cfg
+}
+
+/// Turn every `tab_spaces` leading spaces of each line into a tab if `hard_tabs` is set,
+/// the remaining spaces are kept for alignment.
+/// Lines starting inside a block comment or a multi-line string literal are left untouched.
+pub(crate) fn apply_hard_tabs(fmt_buffer: String, config: &Config) -> String {
+    let tab_spaces = config.tab_spaces();
+    if !config.hard_tabs() || tab_spaces == 0 {
+        return fmt_buffer;
+    }
+
+    let mut multi_line_ranges = vec![];
+    let mut lexer = Lexer::new(&fmt_buffer, FileHash::empty());
+    if lexer.advance().is_ok() {
+        // the comments are in the gaps the lexer skips between two tokens
+        let mut gap_start = 0;
+        loop {
+            let start = lexer.start_loc();
+            collect_block_comments(&fmt_buffer, gap_start..start, &mut multi_line_ranges);
+            if lexer.peek() == Tok::EOF {
+                break;
+            }
+            if lexer.content().contains('\n') {
+                multi_line_ranges.push(start..start + lexer.content().len());
+            }
+            gap_start = start + lexer.content().len();
+            if lexer.advance().is_err() {
+                break;
+            }
+        }
+    }
+
+    let mut result = String::with_capacity(fmt_buffer.len());
+    let mut line_start = 0;
+    for line in fmt_buffer.split_inclusive('\n') {
+        let in_multi_line = multi_line_ranges
+            .iter()
+            .any(|range| range.start < line_start && line_start < range.end);
+        let leading_space_cnt = line.len() - line.trim_start_matches(' ').len();
+        if in_multi_line {
+            result.push_str(line);
+        } else {
+            result.push_str(&"\t".repeat(leading_space_cnt / tab_spaces));
+            result.push_str(&line[leading_space_cnt - leading_space_cnt % tab_spaces..]);
+        }
+        line_start += line.len();
+    }
+    result
+}
+
+/// Push the byte ranges of the block comments of `content[gap]`, which holds nothing but
+/// whitespace and comments, to `ranges`. A block comment left open runs to the end of the gap.
+fn collect_block_comments(content: &str, gap: Range<usize>, ranges: &mut Vec<Range<usize>>) {
+    let bytes = &content.as_bytes()[gap.clone()];
+    let mut idx = 0;
+    let mut depth = 0;
+    let mut comment_start = 0;
+    while idx < bytes.len() {
+        let rest = &bytes[idx..];
+        if depth == 0 && rest.starts_with(b"//") {
+            idx += rest
+                .iter()
+                .position(|ch| *ch == b'\n')
+                .unwrap_or(rest.len());
+        } else if rest.starts_with(b"/*") {
+            if depth == 0 {
+                comment_start = idx;
+            }
+            depth += 1;
+            idx += 2;
+        } else if depth > 0 && rest.starts_with(b"*/") {
+            depth -= 1;
+            idx += 2;
+            if depth == 0 {
+                ranges.push(gap.start + comment_start..gap.start + idx);
+            }
+        } else {
+            idx += 1;
+        }
+    }
+    if depth > 0 {
+        ranges.push(gap.start + comment_start..gap.end);
+    }
+}
+
+pub(crate) fn tune_module_buf(module_body: String, config: &Config) -> String {
     let mut ret_module_body = fun_fmt::fmt_fun(module_body.clone(), config.clone());
     if module_body.contains("spec ") {
         ret_module_body = spec_fmt::fmt_spec(ret_module_body.clone(), config.clone());
@@ -155,11 +221,8 @@ impl Format {
         line_mapping.update(content);
         Self {
             comments_index: Default::default(),
-            local_cfg: FormatConfig {
-                indent_size: global_cfg.indent_size(),
-                max_len_no_add_line: global_cfg.max_width() as f32 * 0.75,
-            },
-            global_cfg,
+            global_cfg: Rc::new(global_cfg),
+            item_cfg_stack: Default::default(),
             depth: Default::default(),
             token_tree: vec![],
             comments: ce.comments,
@@ -182,6 +245,44 @@ impl Format {
         Ok("parse ok".to_string())
     }
 
+    /// The config in effect: the one of the innermost module or function body being
+    /// formatted if its attributes override some options, the global one otherwise.
+    pub(crate) fn cfg(&self) -> Rc<Config> {
+        self.item_cfg_stack
+            .borrow()
+            .last()
+            .cloned()
+            .unwrap_or_else(|| self.global_cfg.clone())
+    }
+
+    pub(crate) fn local_cfg(&self) -> FormatConfig {
+        FormatConfig::new(&self.cfg())
+    }
+
+    /// Runs the layout decision `f` on the output so far.
+    fn with_layout<R>(&self, f: impl FnOnce(&LayoutCtx) -> R) -> R {
+        let cfg = self.cfg();
+        let last_line = self.last_line();
+        let format_context = self.format_context.borrow();
+        f(&LayoutCtx {
+            cfg: &cfg,
+            content: &format_context.content,
+            line_mapping: &self.line_mapping,
+            syntax_handler: &self.syntax_handler,
+            last_line: &last_line,
+            depth: self.depth.get(),
+            pre_simple_token: &format_context.pre_simple_token,
+        })
+    }
+
+    /// The config of the module or function body `kind`, if its attributes
+    /// like `#[fmt::max_width = 120]` override some options of the config in effect.
+    fn block_body_cfg(&self, kind: &NestKind, note: &Option<Note>) -> Option<Config> {
+        self.syntax_handler
+            .handler_immut::<SkipHandler>()
+            .block_body_config(kind, block_body_type(note), &self.cfg())
+    }
+
     pub fn format_token_trees(mut self) -> String {
         let mut pound_sign_idx = None;
         for (index, t) in self.token_tree.clone().into_iter().enumerate() {
@@ -218,7 +319,9 @@ impl Format {
 
             fmt_operator();
 
-            let cfg = self.global_cfg.clone();
+            let cfg = self
+                .block_body_cfg(&nkind, &note)
+                .unwrap_or_else(|| self.global_cfg.as_ref().clone());
             // top level
             if is_mod_blk {
                 self.new_line(Some(t.end_pos()));
@@ -245,7 +348,9 @@ impl Format {
                 };
                 for mod_def in &address_def.modules {
                     let m = &fmt_buf[mod_def.loc.start() as usize..mod_def.loc.end() as usize];
-                    let tuning_mod_body = tune_module_buf(m.to_string(), &cfg);
+                    let mod_cfg = item_config(&cfg, &mod_def.attributes);
+                    let tuning_mod_body =
+                        tune_module_buf(m.to_string(), mod_cfg.as_ref().unwrap_or(&cfg));
                     fmt_slice.push_str(&fmt_buf[last_mod_end_loc..mod_def.loc.start() as usize]);
                     fmt_slice.push_str(&tuning_mod_body);
                     last_mod_end_loc = mod_def.loc.end() as usize;
@@ -271,351 +376,6 @@ impl Format {
         self.ret.into_inner()
     }
 
-    fn is_long_nested_token(current: &TokenTree) -> (bool, usize) {
-        let (mut result, mut elements_len) = (false, 0);
-        if let TokenTree::Nested { elements, kind, .. } = current {
-            result = matches!(kind.kind, NestKind_::Brace | NestKind_::ParentTheses)
-                && analyze_token_tree_length(elements, MAX_ANALYZE_LENGTH) > MIN_BREAK_LENGTH;
-            elements_len = elements.len();
-        }
-        (result, elements_len)
-    }
-
-    fn check_next_tok_canbe_break(next: Option<&TokenTree>) -> bool {
-        if let Some((next_tok, next_content)) = next.map(|x| match x {
-            TokenTree::SimpleToken {
-                content,
-                pos: _,
-                tok,
-                ..
-            } => (*tok, content.clone()),
-            TokenTree::Nested { kind, .. } => {
-                (kind.kind.start_tok(), kind.kind.start_tok().to_string())
-            }
-        }) {
-            if is_statement_start_token(next_tok) {
-                true
-            } else if next_tok == Tok::Identifier {
-                next_content.as_str() == "entry"
-            } else {
-                false
-            }
-        } else {
-            true
-        }
-    }
-
-    fn check_cur_token_is_long_bin_op(
-        &self,
-        current: &TokenTree,
-        next: Option<&TokenTree>,
-        next_tok: Tok,
-        index: usize,
-        kind: &NestKind,
-        elements: &[TokenTree],
-    ) -> bool {
-        let let_handler = self.syntax_handler.handler_immut::<LetHandler>();
-        let call_handler = self.syntax_handler.handler_immut::<CallHandler>();
-        let cur_token_str = current.simple_str().unwrap_or_default();
-        if matches!(cur_token_str, "==>" | "<==>") {
-            let next_token_start_pos = next.unwrap().start_pos();
-            if self.translate_line(next_token_start_pos)
-                <= self.translate_line(current.end_pos()) + 1
-                && let_handler.is_long_bin_op(current.clone())
-            {
-                return true;
-            }
-        }
-
-        let judge_equal_tok_is_long_op_fn = || {
-            let_handler.is_long_assign(
-                current.clone(),
-                next.clone(),
-                self.global_cfg.clone(),
-                self.last_line().len() + 2,
-            )
-        };
-
-        // updated in 20240607: fix https://github.com/movebit/movefmt/issues/7
-        if cur_token_str == "="
-            && next.unwrap().simple_str().unwrap_or_default() != "vector"
-            && next_tok != Tok::LBrace
-            && call_handler.component_is_complex_blk(
-                self.global_cfg.clone(),
-                kind,
-                elements,
-                index as i64,
-                self.get_cur_line_len(),
-            ) != 2
-        {
-            tracing::debug!("\n\n----------\n");
-            return judge_equal_tok_is_long_op_fn();
-        }
-
-        false
-    }
-
-    fn check_next_token_is_long_bin_op(
-        &self,
-        current: &TokenTree,
-        next_t: Option<&TokenTree>,
-        next_token: Tok,
-    ) -> bool {
-        let let_handler = self.syntax_handler.handler_immut::<LetHandler>();
-        let bin_op_handler = self.syntax_handler.handler_immut::<BinOpHandler>();
-        if matches!(next_token, Tok::AmpAmp | Tok::PipePipe)
-            && let_handler.is_long_bin_op(next_t.unwrap().clone())
-        {
-            return true;
-        }
-        if matches!(
-            next_token,
-            Tok::EqualEqualGreater | Tok::LessEqualEqualGreater | Tok::Equal
-        ) {
-            return false;
-        }
-        let current_token_len =
-            analyze_token_tree_length(&[current.clone()], self.global_cfg.max_width());
-        let len_plus_cur_token = self.last_line().len() + current_token_len + 2;
-        if len_plus_cur_token > self.global_cfg.max_width() {
-            return false;
-        }
-
-        if let TokenTree::Nested { elements, .. } = current {
-            let delimiter = analyze_token_tree_delimiter(elements).0;
-            let cur_nested_break_mode = self.get_break_mode_begin_nested(current, delimiter);
-            if cur_nested_break_mode.0 || cur_nested_break_mode.1 == Some(true) {
-                return false;
-            }
-            for nested_nested_in_current_tree in elements {
-                if let TokenTree::Nested {
-                    elements: _ele,
-                    kind: tmp_kind,
-                    ..
-                } = nested_nested_in_current_tree
-                {
-                    if nested_nested_in_current_tree.token_len() as usize > MIN_BREAK_LENGTH
-                        && tmp_kind.kind == NestKind_::Brace
-                    {
-                        return false;
-                    }
-                }
-            }
-        };
-
-        if is_bin_op(next_token) {
-            let r_exp_len_tuple = bin_op_handler.get_bin_op_right_part_len(next_t.unwrap().clone());
-            if r_exp_len_tuple.0 == 0 && r_exp_len_tuple.1 < 8 {
-                return false;
-            }
-            tracing::trace!(
-                "self.last_line().len() = {:?}, r_exp_len_tuple = {:?}",
-                self.last_line().len(),
-                r_exp_len_tuple
-            );
-            let len_bin_op_full = len_plus_cur_token
-                + 2
-                + next_t.unwrap().simple_str().unwrap_or_default().len()
-                + r_exp_len_tuple.1;
-            if len_bin_op_full >= self.global_cfg.max_width() {
-                bin_op_handler.record_long_op(r_exp_len_tuple.0);
-                return true;
-            }
-        }
-        false
-    }
-
-    fn check_next_token_is_quant_body(
-        &self,
-        current: &TokenTree,
-        next_t: Option<&TokenTree>,
-    ) -> bool {
-        let quant_handler = self.syntax_handler.handler_immut::<QuantHandler>();
-        if current.get_end_tok() == Tok::Colon {
-            let (quant_exp_idx, quant_body_len) =
-                quant_handler.get_quant_body_len(next_t.unwrap().clone());
-            if quant_body_len < 8 {
-                return false;
-            }
-
-            let len_plus_cur_token = self.last_line().len() + current.token_len() as usize + 2;
-            if len_plus_cur_token > self.global_cfg.max_width() {
-                return false;
-            }
-            if len_plus_cur_token + quant_body_len > self.global_cfg.max_width() {
-                quant_handler.record_long_quant_exp(quant_exp_idx);
-                return true;
-            }
-        }
-        false
-    }
-
-    fn check_new_line_mode_for_cur_tok(
-        &self,
-        kind_outer: &NestKind,
-        delimiter: Option<Delimiter>,
-        _has_colon: bool,
-        current: &TokenTree,
-        next: Option<&TokenTree>,
-    ) -> bool {
-        if next.and_then(|x| x.simple_str()) == delimiter.map(|x| x.to_static_str()) {
-            return false;
-        }
-
-        let b_judge_next_token = Self::check_next_tok_canbe_break(next);
-
-        // special case for `}}`
-        if let TokenTree::Nested { kind, .. } = current {
-            if kind.kind == NestKind_::Brace
-                && kind_outer.kind == NestKind_::Brace
-                && b_judge_next_token
-            {
-                return true;
-            }
-        }
-
-        // added in 20240426: special case for current is long nested type
-        if matches!(kind_outer.kind, NestKind_::Brace | NestKind_::ParentTheses) {
-            let result_inner = Self::is_long_nested_token(current);
-            if b_judge_next_token && result_inner.0 && result_inner.1 > 4 {
-                return true;
-            }
-        }
-
-        // added in 20240911
-        // special case: there are no comma between ENUM fields
-        if current.get_end_tok() == Tok::RBrace
-            && next.is_some()
-            && next.unwrap().get_start_tok() == Tok::Identifier
-            && !matches!(next.unwrap().simple_str().unwrap_or_default(), "to" | "for")
-        {
-            return true;
-        }
-        false
-    }
-
-    fn get_new_line_mode_for_cur_tok(
-        &self,
-        kind_outer: &NestKind,
-        current: &TokenTree,
-        next: Option<&TokenTree>,
-    ) -> bool {
-        if kind_outer.end_pos - current.end_pos() < MIN_NESTED_LENGTH.try_into().unwrap() {
-            return false;
-        }
-        let b_judge_next_token = next.is_some() && Self::check_next_tok_canbe_break(next);
-        if matches!(kind_outer.kind, NestKind_::Brace | NestKind_::ParentTheses)
-            && b_judge_next_token
-            && Self::is_long_nested_token(current).0
-        {
-            return true;
-        }
-        false
-    }
-
-    fn need_new_line_for_cur_tok_finished(
-        &self,
-        nested_token: &TokenTree,
-        delimiter: Option<Delimiter>,
-        has_colon: bool,
-        index: usize,
-        component_break_mode: bool,
-        nested_kind_len: usize,
-    ) -> bool {
-        let TokenTree::Nested { elements, kind, .. } = nested_token else {
-            return false;
-        };
-
-        let t = elements.get(index).unwrap();
-        let next_t = elements.get(index + 1);
-        let d = delimiter.map(|x| x.to_static_str());
-        let t_str = t.simple_str();
-
-        let mut new_line = if component_break_mode {
-            self.check_new_line_mode_for_cur_tok(kind, delimiter, has_colon, t, next_t)
-                || (d == t_str && d.is_some() && kind.kind != NestKind_::Type)
-        } else {
-            self.get_new_line_mode_for_cur_tok(kind, t, next_t)
-        };
-
-        // comma in fun resource access specifier not change new line
-        if d == t_str && d.is_some() {
-            if let Some(deli_str) = d {
-                if deli_str.contains(',') {
-                    let mut idx = index;
-                    while idx != 0 {
-                        let ele = elements.get(idx).unwrap();
-                        idx -= 1;
-                        if let Some(key) = ele.simple_str() {
-                            if key.contains("fun") {
-                                break;
-                            }
-                        }
-                        if ele.simple_str().is_none() {
-                            continue;
-                        }
-                        if matches!(
-                            ele.simple_str().unwrap(),
-                            "acquires" | "reads" | "writes" | "pure"
-                        ) {
-                            new_line = false;
-                            break;
-                        }
-                    }
-                }
-            }
-        }
-
-        // ablility not change new line
-        // optimize in 20240510: maybe like variable name or struct field name are ability, like "key"
-        // fixed bug in 20240718: you can see case [tests/bug/input4.move]
-        let mut next_token = Tok::EOF;
-        if let Some((next_tok, next_content)) = next_t.map(|x| match x {
-            TokenTree::SimpleToken {
-                content,
-                pos: _,
-                tok,
-                ..
-            } => (*tok, content.clone()),
-            TokenTree::Nested { kind, .. } => {
-                (kind.kind.start_tok(), kind.kind.start_tok().to_string())
-            }
-        }) {
-            if new_line
-                && d == t_str
-                && t_str.unwrap_or_default() == ","
-                && token_to_ability(
-                    self.get_pre_simple_tok(),
-                    &self
-                        .format_context
-                        .borrow()
-                        .pre_simple_token
-                        .simple_str()
-                        .unwrap_or_default(),
-                )
-                .is_some()
-                && token_to_ability(next_tok, &next_content).is_some()
-            {
-                new_line = false;
-            }
-            next_token = next_tok;
-        }
-
-        if nested_kind_len > MIN_NESTED_LENGTH && kind.kind != NestKind_::Type {
-            new_line |=
-                self.check_cur_token_is_long_bin_op(t, next_t, next_token, index, kind, &elements);
-            if !new_line && next_t.is_some() {
-                if self.check_next_token_is_long_bin_op(t, next_t, next_token) {
-                    return true;
-                }
-                if self.check_next_token_is_quant_body(t, next_t) {
-                    return true;
-                }
-            }
-        }
-        new_line
-    }
-
     fn process_fn_header(&self) {
         let cur_ret = self.ret.clone().into_inner();
         if let Some(last_fun_idx) = cur_ret.rfind("fun") {
@@ -623,7 +383,7 @@ impl Format {
             if let Some(specifier_idx) = fun_header.rfind("fun") {
                 let indent_str = " "
                     .to_string()
-                    .repeat((self.depth.get() + 1) * self.local_cfg.indent_size);
+                    .repeat((self.depth.get() + 1) * self.local_cfg().indent_size);
                 let fun_specifier_fmted_str = fun_fmt::fun_header_specifier_fmt(
                     &fun_header[specifier_idx + 1..],
                     &indent_str,
@@ -640,304 +400,6 @@ impl Format {
         }
     }
 
-    fn get_break_mode_of_fun_call(
-        &self,
-        token: &TokenTree,
-        nested_token_len: usize,
-        opt_component_break_mode: &mut bool,
-    ) -> bool {
-        let TokenTree::Nested { elements, kind, .. } = token else {
-            return false;
-        };
-        let call_handler = self.syntax_handler.handler_immut::<CallHandler>();
-        let mut new_line_mode = false;
-        let elements_str = serde_json::to_string(&elements).unwrap_or_default();
-        let has_multi_para = elements_str.matches("\"content\":\",\"").count() > 2;
-        if call_handler.get_call_component_split_mode(
-            self.global_cfg.clone(),
-            kind,
-            &elements,
-            self.last_line().len(),
-        ) {
-            new_line_mode = true;
-
-            let next_line_len = " "
-                .to_string()
-                .repeat((self.depth.get() + 1) * self.local_cfg.indent_size)
-                .len();
-            if call_handler.get_call_component_split_mode(
-                self.global_cfg.clone(),
-                kind,
-                &elements,
-                next_line_len,
-            ) {
-                *opt_component_break_mode = true;
-            }
-        }
-
-        if !*opt_component_break_mode
-            && has_multi_para
-            && (nested_token_len as f32 > self.local_cfg.max_len_no_add_line
-                || (!self.global_cfg.prefer_one_line_for_short_call_para_list() && new_line_mode))
-        {
-            *opt_component_break_mode = true;
-        }
-        new_line_mode
-    }
-
-    fn get_break_mode_begin_paren(&self, token: &TokenTree) -> (bool, Option<bool>) {
-        let TokenTree::Nested { elements, kind, .. } = token else {
-            return (false, None);
-        };
-        if NestKind_::ParentTheses != kind.kind {
-            return (false, None);
-        }
-        if elements.len() == 1 && elements[0].simple_str().is_none() {
-            return (false, None);
-        }
-        let mut new_line_mode = false;
-        let nested_token_len = self.get_kind_len_after_trim_space(*kind, true);
-
-        let mut opt_component_break_mode = nested_token_len
-            + self.depth.get() * self.local_cfg.indent_size
-            >= self.global_cfg.max_width();
-
-        let maybe_in_fun_header = self
-            .syntax_handler
-            .handler_immut::<FunHandler>()
-            .is_parameter_paren_in_fun_header(kind);
-        if matches!(self.get_pre_simple_tok(), Tok::If | Tok::While) {
-            new_line_mode = false;
-        } else if maybe_in_fun_header.0 {
-            new_line_mode |= maybe_in_fun_header.1 > self.global_cfg.max_width();
-            // Reserve 25% space for return ty and specifier
-            new_line_mode |= (self.get_cur_line_len() + nested_token_len) as f32
-                > self.local_cfg.max_len_no_add_line;
-
-            let nested_and_comma_pair = expr_fmt::get_nested_and_comma_num(elements);
-            if self
-                .global_cfg
-                .prefer_one_line_for_short_fn_header_para_list()
-            {
-                opt_component_break_mode |= (nested_and_comma_pair.0 >= 4
-                    || nested_and_comma_pair.1 > 2)
-                    && token.token_len() as f32 > self.local_cfg.max_len_no_add_line;
-            } else {
-                opt_component_break_mode |= nested_and_comma_pair.1 > 1;
-            }
-
-            new_line_mode |= opt_component_break_mode;
-        } else if self.get_cur_line_len() > self.global_cfg.max_width() {
-            new_line_mode = true;
-        } else {
-            let elements_str = serde_json::to_string(&elements).unwrap_or_default();
-            let has_multi_para = elements_str.matches("\"content\":\",\"").count() > 2;
-            let is_in_fun_call = self
-                .syntax_handler
-                .handler_immut::<CallHandler>()
-                .paren_in_call(kind);
-            if is_in_fun_call {
-                new_line_mode |= self.get_break_mode_of_fun_call(
-                    token,
-                    nested_token_len,
-                    &mut opt_component_break_mode,
-                );
-            } else {
-                new_line_mode |= has_multi_para && self.get_pre_simple_tok() == Tok::Identifier;
-            }
-            if elements[0].simple_str().is_some() {
-                let is_plus_nested_over_width = self.get_cur_line_len() + nested_token_len
-                    > self.global_cfg.max_width()
-                    && nested_token_len > 8;
-                let is_nested_len_too_large =
-                    nested_token_len as f32 > 2.0 * self.local_cfg.max_len_no_add_line;
-                new_line_mode |= is_plus_nested_over_width || is_nested_len_too_large;
-            }
-
-            let first_ele_len =
-                analyze_token_tree_length(&[elements[0].clone()], self.global_cfg.max_width());
-            let is_plus_first_ele_over_width = self.get_cur_line_len() + first_ele_len
-                > self.global_cfg.max_width()
-                && first_ele_len > 8;
-
-            new_line_mode |= is_plus_first_ele_over_width;
-            new_line_mode |= opt_component_break_mode && has_multi_para;
-        }
-
-        let nested_blk_str =
-            &self.format_context.borrow().content[kind.start_pos as usize..kind.end_pos as usize];
-        if !new_line_mode
-            && contains_comment(&nested_blk_str)
-            && nested_blk_str.find("//").is_some()
-        {
-            new_line_mode = true;
-        }
-        return (new_line_mode, Some(opt_component_break_mode));
-    }
-
-    fn get_control_blk_cnt(&self, elements: &[TokenTree]) -> usize {
-        let mut control_blk_cnt = 0;
-        for ele in elements {
-            if matches!(
-                ele.get_start_tok(),
-                Tok::If | Tok::Else | Tok::Loop | Tok::While
-            ) {
-                control_blk_cnt += 1;
-            }
-        }
-        control_blk_cnt
-    }
-
-    fn get_break_mode_begin_branch_blk(&self, kind: &NestKind) -> bool {
-        let branch_handler = self.syntax_handler.handler_immut::<BranchHandler>();
-        if branch_handler
-            .com_if_else
-            .then_loc_vec
-            .iter()
-            .any(|&x| x.start() == kind.start_pos)
-            || branch_handler
-                .com_if_else
-                .else_loc_vec
-                .iter()
-                .any(|&x| x.start() == kind.start_pos)
-        {
-            if self.global_cfg.prefer_one_line_for_short_branch_blk() {
-                return self.get_kind_len_after_trim_space(*kind, true) > 8;
-            } else {
-                return true;
-            }
-        }
-        false
-    }
-
-    fn get_break_mode_begin_nested(
-        &self,
-        token: &TokenTree,
-        delimiter: Option<Delimiter>,
-    ) -> (bool, Option<bool>) {
-        let TokenTree::Nested {
-            elements,
-            kind,
-            note,
-        } = token
-        else {
-            return (false, None);
-        };
-        let max_len_no_add_line = self.local_cfg.max_len_no_add_line;
-        let max_line_width = self.global_cfg.max_width();
-        let nested_blk_str =
-            &self.format_context.borrow().content[kind.start_pos as usize..kind.end_pos as usize];
-        let nested_len = self.get_kind_len_after_trim_space(*kind, true);
-        if elements.is_empty() {
-            let should_break = nested_len as f32 > max_len_no_add_line
-                || (contains_comment(nested_blk_str) && nested_blk_str.lines().count() > 1);
-            return (should_break, None);
-        }
-
-        // 20240329 updated
-        // fun body brace always change new line;
-        // if ParentTheses is empty, not change new line;
-        // 20240425 updated
-        // The value of new_line_mode here is not associated with Paren, only with Brace.
-        // Because Paren may come from fn_para or call or expression statements...
-        let is_stct_def = note.map_or(false, |x| x == Note::StructDefinition);
-        let mut new_line_mode = {
-            delimiter.map_or(false, |d| d == Delimiter::Semicolon)
-                || is_stct_def
-                || note.map_or(false, |x| x == Note::FunBody)
-        };
-        if new_line_mode && kind.kind != NestKind_::Type {
-            if is_stct_def {
-                return (true, Some(true));
-            }
-            return (true, None);
-        }
-
-        match kind.kind {
-            NestKind_::Type => {
-                // added in 20240112: if type in fun header, not change new line
-                if self
-                    .syntax_handler
-                    .handler_immut::<FunHandler>()
-                    .is_generic_ty_in_fun_header(kind)
-                {
-                    return (false, None);
-                }
-
-                let first_ele_len =
-                    analyze_token_tree_length(&[elements[0].clone()], max_line_width);
-                new_line_mode =
-                    self.get_cur_line_len() + first_ele_len > max_line_width && first_ele_len > 8;
-            }
-            NestKind_::ParentTheses => return self.get_break_mode_begin_paren(token),
-            NestKind_::Bracket => {
-                let is_annotation = self.get_pre_simple_tok() == Tok::NumSign;
-                new_line_mode = (is_annotation && nested_len > max_line_width)
-                    || (!is_annotation && nested_len as f32 > max_len_no_add_line);
-                if elements.len() > MIN_BREAK_LENGTH {
-                    let mut bin_op_cnt = 0;
-                    let mut complex_ele_cnt = 0;
-                    for ele in elements {
-                        let result_inner = Self::is_long_nested_token(ele);
-                        if result_inner.0 && result_inner.1 > 4 {
-                            complex_ele_cnt += 1;
-                        }
-                        if is_bin_op(ele.get_start_tok()) {
-                            bin_op_cnt += 1;
-                        }
-                    }
-                    return (new_line_mode, Some(complex_ele_cnt > 4 || bin_op_cnt > 4));
-                }
-            }
-            NestKind_::Lambda => {
-                new_line_mode |=
-                    (self.get_cur_line_len() + nested_len) as f32 > max_len_no_add_line;
-                let mut opt_component_break_mode = false;
-                let nested_and_comma_pair = expr_fmt::get_nested_and_comma_num(elements);
-                if self.global_cfg.prefer_one_line_for_short_lambda_para_list() {
-                    opt_component_break_mode |= (nested_and_comma_pair.0 >= 4
-                        || nested_and_comma_pair.1 > 2)
-                        && token.token_len() as f32 > max_len_no_add_line;
-                } else {
-                    opt_component_break_mode |= nested_and_comma_pair.1 > 1;
-                }
-
-                new_line_mode |= opt_component_break_mode;
-                if delimiter.is_none() && nested_len as f32 <= max_len_no_add_line {
-                    new_line_mode = false;
-                }
-            }
-            NestKind_::Brace => {
-                if nested_len > 4 {
-                    // case1: over max width
-                    new_line_mode |= self.get_cur_line_len() + nested_len > max_line_width;
-                    new_line_mode |= self.last_line().len() + nested_len > max_line_width;
-
-                    // case2: has special keyword
-                    new_line_mode |= has_special_key_for_break_line_in_code_buf(self.last_line());
-                }
-
-                // case3: nested_len too long
-                new_line_mode |= nested_len as f32 > max_len_no_add_line;
-
-                // case4: contains comment
-                new_line_mode |=
-                    contains_comment(nested_blk_str) && nested_blk_str.lines().count() > 1;
-
-                // case5: has too much nested blks
-                let (nested_cnt, _) = expr_fmt::get_nested_and_comma_num(elements);
-                new_line_mode |= nested_cnt >= 2 && nested_len > MIN_BREAK_LENGTH;
-
-                // case6: has too much control blks
-                new_line_mode |= self.get_control_blk_cnt(elements) >= 2;
-
-                // case7: maybe in branch blk
-                new_line_mode |= self.get_break_mode_begin_branch_blk(&kind);
-            }
-        }
-        (new_line_mode, None)
-    }
-
     fn top_half_after_kind_start(
         &self,
         kind: &NestKind,
@@ -1092,7 +554,6 @@ impl Format {
         &self,
         nested_token: &TokenTree,
         delimiter: Option<Delimiter>,
-        has_colon: bool,
         component_break_mode: bool,
     ) {
         let TokenTree::Nested { elements, kind, .. } = nested_token else {
@@ -1120,18 +581,20 @@ impl Format {
                 .unwrap_or_default();
 
             let cur_token_tree = elements.get(internal_token_idx).unwrap();
-            let mut new_line = self.need_new_line_for_cur_tok_finished(
-                nested_token,
-                delimiter,
-                has_colon,
-                internal_token_idx,
-                need_get_break_mode_on_component,
-                nestd_kind_len,
-            );
+            let mut new_line = self.with_layout(|ctx| {
+                need_new_line_for_cur_tok_finished(
+                    ctx,
+                    nested_token,
+                    delimiter,
+                    internal_token_idx,
+                    need_get_break_mode_on_component,
+                    nestd_kind_len,
+                )
+            });
             if is_call {
                 new_line |= component_break_mode
                     && call_handler.should_call_component_split(
-                        self.global_cfg.clone(),
+                        self.cfg().as_ref().clone(),
                         kind,
                         elements,
                         internal_token_idx,
@@ -1206,49 +669,11 @@ impl Format {
         self.format_context.borrow_mut().cur_nested_kind = old_kind;
     }
 
-    fn judge_add_space_around_brace(
-        &self,
-        nested_token: &TokenTree,
-        b_new_line_mode: bool,
-    ) -> bool {
-        let TokenTree::Nested { elements, kind, .. } = nested_token else {
-            return true;
-        };
-        let nested_token_head = self.get_pre_simple_tok();
-        // optimize in 20240425
-        // there are 2 cases which not add space
-        // eg1: When braces are used for arithmetic operations
-        // let intermediate3: u64 = (a * {c + d}) - (b / {e - 2});
-        // shouldn't formated like `let intermediate3: u64 = (a * { c + d }) - (b / { e - 2 });`
-        // eg2: When the braces are used for use
-        // use A::B::{C, D}
-        // shouldn't formated like `use A::B::{ C, D }`
-        let is_arithmetic_op = matches!(
-            nested_token_head,
-            Tok::Plus | Tok::Minus | Tok::Star | Tok::Slash | Tok::Percent
-        );
-        let b_not_arithmetic_op_brace = !is_arithmetic_op && kind.kind == NestKind_::Brace;
-        let b_not_use_brace = Tok::ColonColon != nested_token_head && kind.kind == NestKind_::Brace;
-        let nested_blk_str = &self.format_context.borrow().content
-            [kind.start_pos as usize + 1..kind.end_pos as usize];
-        (elements.is_empty() && contains_comment(nested_blk_str))
-            || (b_not_arithmetic_op_brace
-                && b_not_use_brace
-                && !b_new_line_mode
-                && !elements.is_empty())
-    }
-
     fn need_skip_nested_token(&self, kind: &NestKind, note: &Option<Note>) -> bool {
-        let block_body_ty = match note.unwrap_or_default() {
-            Note::StructDefinition => SkipType::SkipStructBody,
-            Note::FunBody => SkipType::SkipFunBody,
-            Note::ModuleDef => SkipType::SkipModuleBody,
-            _ => SkipType::SkipNone,
-        };
         if self
             .syntax_handler
             .handler_immut::<SkipHandler>()
-            .should_skip_block_body(kind, block_body_ty)
+            .should_skip_block_body(kind, block_body_type(note))
         {
             let blk_body_str = &self.format_context.borrow().content
                 [kind.start_pos as usize..kind.end_pos as usize + 1];
@@ -1279,13 +704,17 @@ impl Format {
         if self.need_skip_nested_token(&kind, note) {
             return;
         }
+        let item_cfg = self.block_body_cfg(kind, note).map(Rc::new);
+        if let Some(item_cfg) = &item_cfg {
+            self.item_cfg_stack.borrow_mut().push(item_cfg.clone());
+        }
 
-        let (delimiter, has_colon) = analyze_token_tree_delimiter(elements);
+        let (delimiter, _) = analyze_token_tree_delimiter(elements);
         if note.map_or(false, |x| x == Note::FunBody) {
             self.process_fn_header();
         }
         let (mut b_new_line_mode, opt_component_break_mode) =
-            self.get_break_mode_begin_nested(nested_token, delimiter);
+            self.with_layout(|ctx| get_break_mode_begin_nested(ctx, nested_token, delimiter));
 
         let mut b_add_indent = true;
         for i in 0..elements.len() {
@@ -1301,8 +730,8 @@ impl Format {
         }
 
         let nested_token_head = self.get_pre_simple_tok();
-        let b_add_space_around_brace =
-            self.judge_add_space_around_brace(nested_token, b_new_line_mode);
+        let b_add_space_around_brace = self
+            .with_layout(|ctx| judge_add_space_around_brace(ctx, nested_token, b_new_line_mode));
 
         // step1-step3
         self.top_half_after_kind_start(
@@ -1317,7 +746,6 @@ impl Format {
         self.format_each_token_in_nested_elements(
             nested_token,
             delimiter,
-            has_colon,
             opt_component_break_mode.unwrap_or(b_new_line_mode),
         );
 
@@ -1330,6 +758,9 @@ impl Format {
             nested_token_head,
             opt_component_break_mode.unwrap_or(b_new_line_mode),
         );
+        if item_cfg.is_some() {
+            self.item_cfg_stack.borrow_mut().pop();
+        }
 
         // step8 -- format end_token
         self.format_token_trees_internal(&kind.end_token_tree(), None, false);
@@ -1368,7 +799,7 @@ impl Format {
             && branch_handler.need_new_line_after_branch(
                 self.last_line(),
                 *pos,
-                self.global_cfg.clone(),
+                self.cfg().as_ref().clone(),
                 end_pos_of_if_cond_or_else,
             )
         {
@@ -1377,7 +808,11 @@ impl Format {
             let cur_line = self.last_line();
             if cur_line.trim_start().len() == 0 {
                 // maybe already added new line because of judge_cond() is a long nested expr
-                self.push_str(" ".to_string().repeat(self.local_cfg.indent_size).as_str());
+                self.push_str(
+                    " ".to_string()
+                        .repeat(self.local_cfg().indent_size)
+                        .as_str(),
+                );
                 return;
             }
             return self.new_line(None);
@@ -1401,7 +836,7 @@ impl Format {
                     + content.len()
                     + 2
                     + next_token.unwrap().token_len() as usize
-                    > self.global_cfg.max_width() - MIN_NESTED_LENGTH
+                    > self.cfg().max_width() - MIN_NESTED_LENGTH
                 {
                     new_line_before_else = true;
                 }
@@ -1410,7 +845,7 @@ impl Format {
                 if branch_handler.else_branch_too_long(
                     self.last_line(),
                     next_token.unwrap().start_pos() as ByteIndex,
-                    self.global_cfg.clone(),
+                    self.cfg().as_ref().clone(),
                 ) {
                     new_line_before_else = true;
                 }
@@ -1485,6 +920,7 @@ impl Format {
         */
         if (self.translate_line(*pos) - self.cur_line.get()) > 1
             && expr_fmt::need_newline_when_trim_blank_line(&self.get_pre_simple_tok(), tok)
+            && !expr_fmt::drop_blank_lines_after(&self.get_pre_simple_tok(), &self.cfg())
         {
             // There are multiple blank lines between the cur_line and the current code simple_token
             tracing::debug!(
@@ -1521,18 +957,22 @@ impl Format {
             .trim_start_matches(char::is_whitespace)
             .len();
         let mut leading_space_cnt = self.last_line().len() - last_line_len_after_trim_leading_space;
-        if leading_space_cnt > self.local_cfg.indent_size && leading_space_cnt % 2 == 1 {
+        if leading_space_cnt > self.local_cfg().indent_size && leading_space_cnt % 2 == 1 {
             leading_space_cnt -= 1;
             let mut ret_cp = self.ret.clone().into_inner();
             ret_cp.remove(
-                ret_cp.len() - last_line_len_after_trim_leading_space - self.local_cfg.indent_size,
+                ret_cp.len()
+                    - last_line_len_after_trim_leading_space
+                    - self.local_cfg().indent_size,
             );
             *self.ret.borrow_mut() = ret_cp;
         }
         let mut split_line_after_content = false;
         if !not_break_special_tok
             && last_line_len_after_trim_leading_space > 0
-            && self.judge_change_new_line_when_over_limits(content.clone(), *tok, *note, next_token)
+            && self.with_layout(|ctx| {
+                judge_change_new_line_when_over_limits(ctx, content, *tok, *note, next_token)
+            })
         {
             tracing::trace!("last_line = {:?}", self.last_line());
             tracing::trace!(
@@ -1555,8 +995,8 @@ impl Format {
                     NestKind_::Bracket | NestKind_::ParentTheses
                 );
                 if need_inc_depth {
-                    let cur_indent_cnt = self.depth.get() * self.local_cfg.indent_size;
-                    if leading_space_cnt + self.local_cfg.indent_size == cur_indent_cnt {
+                    let cur_indent_cnt = self.depth.get() * self.local_cfg().indent_size;
+                    if leading_space_cnt + self.local_cfg().indent_size == cur_indent_cnt {
                         tracing::debug!("cur_indent_cnt: {}", cur_indent_cnt);
                         self.new_line(None);
                     } else {
@@ -1756,7 +1196,9 @@ impl Format {
                 // comment
                 fun func() {}
                 */
-                if self.get_pre_simple_tok() != Tok::NumSign {
+                if self.get_pre_simple_tok() != Tok::NumSign
+                    && !expr_fmt::drop_blank_lines_after(&self.get_pre_simple_tok(), &self.cfg())
+                {
                     self.new_line(None);
                 }
             }
@@ -1770,15 +1212,15 @@ impl Format {
 
             // tracing::debug!("-- add_comments: line(c.start_offset) - cur_line = {:?}",
             //     this_cmt_start_line - self.cur_line.get());
-            if self.no_space_or_new_line_for_comment() {
+            if no_space_or_new_line_for_comment(&self.ret.borrow()) {
                 self.push_str(" ");
             }
 
             self.push_str(c.format_comment(
                 c.comment_kind(),
-                self.depth.get() * self.local_cfg.indent_size,
+                self.depth.get() * self.local_cfg().indent_size,
                 0,
-                &self.global_cfg,
+                &self.cfg(),
             ));
 
             match c.comment_kind() {
@@ -1851,30 +1293,16 @@ impl Format {
         self.ret.borrow_mut().push_str(s);
     }
 
-    fn no_space_or_new_line_for_comment(&self) -> bool {
-        if self.ret.borrow().chars().last().is_some() {
-            !self.ret.borrow().ends_with('\n')
-                && !self.ret.borrow().ends_with(' ')
-                && !self.ret.borrow().ends_with('(')
-        } else {
-            false
-        }
-    }
-
     fn indent(&self) {
         self.push_str(
             " ".to_string()
-                .repeat(self.depth.get() * self.local_cfg.indent_size)
+                .repeat(self.depth.get() * self.local_cfg().indent_size)
                 .as_str(),
         );
     }
 
     fn translate_line(&self, pos: u32) -> u32 {
-        self.line_mapping
-            .translate(pos, pos)
-            .unwrap_or_default()
-            .start
-            .line
+        translate_line(&self.line_mapping, pos)
     }
 
     fn process_same_line_comment(
@@ -1894,11 +1322,11 @@ impl Format {
             let kind = c.comment_kind();
             let fmted_cmt_str = c.format_comment(
                 kind,
-                self.depth.get() * self.local_cfg.indent_size,
+                self.depth.get() * self.local_cfg().indent_size,
                 0,
-                &self.global_cfg,
+                &self.cfg(),
             );
-            if self.no_space_or_new_line_for_comment() {
+            if no_space_or_new_line_for_comment(&self.ret.borrow()) {
                 self.push_str(" ");
             }
 
@@ -1935,26 +1363,7 @@ impl Format {
 
 impl Format {
     fn get_kind_len_after_trim_space(&self, kind: NestKind, join_by_space: bool) -> usize {
-        // let nested_blk_str = &self.format_context.borrow().content
-        //     [kind.start_pos as usize..kind.end_pos as usize]
-        //     .replace('\n', "");
-        // let tok_vec = nested_blk_str.split_whitespace().collect::<Vec<&str>>();
-        // if join_by_space {
-        //     tok_vec.join(" ").len()
-        // } else {
-        //     tok_vec.join("").len()
-        // }
-
-        let nested_blk_str = &self.format_context.borrow().content
-            [kind.start_pos as usize..kind.end_pos as usize]
-            .replace('\n', "");
-        let new_nested_blk_str = nested_blk_str[1..].to_string();
-        let tok_vec = new_nested_blk_str.split_whitespace().collect::<Vec<&str>>();
-        if join_by_space {
-            tok_vec.join(" ").len() + 2
-        } else {
-            tok_vec.join("").len() + 2
-        }
+        get_kind_len_after_trim_space(&self.format_context.borrow().content, kind, join_by_space)
     }
 
     fn last_line(&self) -> String {
@@ -1966,72 +1375,10 @@ impl Format {
             .unwrap_or_default()
     }
 
-    fn tok_suitable_for_new_line(tok: Tok, note: Option<Note>, next: Option<&TokenTree>) -> bool {
-        if next
-            .and_then(|x| match x {
-                TokenTree::SimpleToken { .. } => None,
-                TokenTree::Nested { kind, .. } => Some(kind.kind == NestKind_::Type),
-            })
-            .unwrap_or_default()
-        {
-            // not break for generic <Type>
-            return false;
-        }
-        let is_bin = note.map(|x| x == Note::BinaryOP).unwrap_or_default();
-        let ret = match tok {
-            Tok::Less | Tok::Amp | Tok::Star | Tok::Greater if is_bin => true,
-            Tok::ExclaimEqual
-            | Tok::Percent
-            | Tok::AmpAmp
-            | Tok::Plus
-            | Tok::Minus
-            | Tok::Period
-            | Tok::Slash
-            | Tok::LessEqual
-            | Tok::LessLess
-            | Tok::Equal
-            | Tok::EqualEqual
-            | Tok::EqualEqualGreater
-            | Tok::LessEqualEqualGreater
-            | Tok::GreaterEqual
-            | Tok::GreaterGreater
-            | Tok::Pipe
-            | Tok::PipePipe
-            | Tok::NumValue
-            | Tok::NumTypedValue => true,
-            _ => false,
-        };
-        tracing::trace!("tok_suitable_for_new_line ret = {}", ret);
-        ret
-    }
-
     fn get_cur_line_len(&self) -> usize {
         get_code_buf_len(self.last_line())
     }
 
-    fn judge_change_new_line_when_over_limits(
-        &self,
-        tok_str: String,
-        tok: Tok,
-        note: Option<Note>,
-        next: Option<&TokenTree>,
-    ) -> bool {
-        let len_plus_tok_len = self.get_cur_line_len() + tok_str.len();
-        if tok == Tok::AtSign && next.is_some() {
-            let next_tok_len = next.unwrap().simple_str().unwrap_or_default().len();
-            if next_tok_len > 8 && len_plus_tok_len + next_tok_len > self.global_cfg.max_width() {
-                return true;
-            }
-        }
-
-        if self.get_pre_simple_tok() == Tok::AtSign {
-            return false;
-        }
-
-        len_plus_tok_len > self.global_cfg.max_width()
-            && Self::tok_suitable_for_new_line(tok, note, next)
-    }
-
     fn remove_trailing_whitespaces(&mut self) {
         *self.ret.borrow_mut() = remove_trailing_whitespaces_util(self.ret.clone().into_inner());
     }
@@ -2045,9 +1392,48 @@ impl Format {
     }
 }
 
-pub fn format_entry(content: impl AsRef<str>, config: Config) -> Result<String, Diagnostics> {
+/// Why some content could not be formatted, with the compiler diagnostics.
+/// Tools embedding movefmt get `api::FormatError` from `api::format_str` instead.
+#[derive(Debug)]
+pub enum EngineError {
+    /// The content doesn't parse.
+    Parse(Diagnostics),
+    /// The formatted result was refused by `verify_formatting`.
+    Verify(ErrorKind),
+}
+
+impl From<Diagnostics> for EngineError {
+    fn from(diags: Diagnostics) -> Self {
+        EngineError::Parse(diags)
+    }
+}
+
+impl From<ErrorKind> for EngineError {
+    fn from(e: ErrorKind) -> Self {
+        EngineError::Verify(e)
+    }
+}
+
+/// Format `content` with this engine, whatever `use_functional_formatter`.
+/// Before `verify_formatting`, the error was the `Diagnostics` now in `EngineError::Parse`.
+pub fn format_entry(content: impl AsRef<str>, config: Config) -> Result<String, EngineError> {
+    let (result, timer) = format_entry_timed(content.as_ref(), &config)?;
+    if config.verbose() == Verbosity::Verbose {
+        println!(
+            "Spent {0:.3} secs in the parsing phase, and {1:.3} secs in the formatting phase",
+            timer.get_parse_time(),
+            timer.get_format_time(),
+        );
+    }
+    Ok(result)
+}
+
+/// Same as `format_entry`, but returns the timer instead of printing it.
+pub(crate) fn format_entry_timed(
+    content: &str,
+    config: &Config,
+) -> Result<(String, Timer), EngineError> {
     let mut timer = Timer::start();
-    let content = content.as_ref();
 
     {
         // https://github.com/movebit/movefmt/issues/2
@@ -2055,7 +1441,7 @@ pub fn format_entry(content: impl AsRef<str>, config: Config) -> Result<String,
     }
 
     let mut full_fmt = Format::new(
-        config.clone(),
+        layout_config(config),
         content,
         FormatContext::new(content.to_string()),
     );
@@ -2064,14 +1450,10 @@ pub fn format_entry(content: impl AsRef<str>, config: Config) -> Result<String,
     timer = timer.done_parsing();
 
     // wait for notify
-    let result = full_fmt.format_token_trees();
-    timer = timer.done_formatting();
-    if config.verbose() == Verbosity::Verbose {
-        println!(
-            "Spent {0:.3} secs in the parsing phase, and {1:.3} secs in the formatting phase",
-            timer.get_parse_time(),
-            timer.get_format_time(),
-        );
+    let result = apply_hard_tabs(full_fmt.format_token_trees(), config);
+    let result = keep_skipped_code(content, result)?;
+    if config.verify_formatting() {
+        verify_formatting(content, &result)?;
     }
-    Ok(result)
+    Ok((result, timer.done_formatting()))
 }
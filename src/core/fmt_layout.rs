@@ -0,0 +1,823 @@
+// Copyright © Aptos Foundation
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! The layout decisions shared by both engines, `core::fmt::Format` and
+//! `core::fmt_state::FunctionalFormat`: where a line breaks and where a space goes.
+//! They only read the token tree, the config and the output so far, through a
+//! `LayoutCtx` each engine builds from its own state, so both engines decide alike.
+
+use crate::core::fmt::{
+    MAX_ANALYZE_LENGTH, MIN_BREAK_LENGTH, MIN_NESTED_LENGTH, is_bin_op, token_to_ability,
+};
+use crate::core::token_tree::*;
+use crate::syntax_fmt::bin_op_fmt::BinOpHandler;
+use crate::syntax_fmt::branch_fmt::BranchHandler;
+use crate::syntax_fmt::call_fmt::CallHandler;
+use crate::syntax_fmt::expr_fmt;
+use crate::syntax_fmt::fun_fmt::FunHandler;
+use crate::syntax_fmt::let_fmt::LetHandler;
+use crate::syntax_fmt::quant_fmt::QuantHandler;
+use crate::syntax_fmt::syntax_handler::SyntaxHandler;
+use crate::tools::utils::*;
+use commentfmt::Config;
+use commentfmt::comment::contains_comment;
+use move_compiler::parser::lexer::Tok;
+
+#[derive(Clone, Copy, Default)]
+pub struct FormatConfig {
+    pub(crate) indent_size: usize,
+    pub(crate) max_len_no_add_line: f32,
+}
+
+impl FormatConfig {
+    pub(crate) fn new(config: &Config) -> Self {
+        FormatConfig {
+            indent_size: config.indent_size(),
+            max_len_no_add_line: config.max_width() as f32 * 0.75,
+        }
+    }
+}
+
+/// What the layout decisions read of an engine: the source being formatted
+/// and the state of the output so far.
+pub(crate) struct LayoutCtx<'a> {
+    /// The config in effect, see `Format::cfg`.
+    pub(crate) cfg: &'a Config,
+    pub(crate) content: &'a str,
+    pub(crate) line_mapping: &'a FileLineMappingOneFile,
+    pub(crate) syntax_handler: &'a SyntaxHandler,
+    /// The last line of the output so far.
+    pub(crate) last_line: &'a str,
+    pub(crate) depth: usize,
+    pub(crate) pre_simple_token: &'a TokenTree,
+}
+
+impl LayoutCtx<'_> {
+    fn local_cfg(&self) -> FormatConfig {
+        FormatConfig::new(self.cfg)
+    }
+
+    fn cur_line_len(&self) -> usize {
+        get_code_buf_len(self.last_line.to_string())
+    }
+
+    fn pre_simple_tok(&self) -> Tok {
+        self.pre_simple_token.get_end_tok()
+    }
+}
+
+pub(crate) fn translate_line(line_mapping: &FileLineMappingOneFile, pos: u32) -> u32 {
+    line_mapping
+        .translate(pos, pos)
+        .unwrap_or_default()
+        .start
+        .line
+}
+
+fn is_long_nested_token(current: &TokenTree) -> (bool, usize) {
+    let (mut result, mut elements_len) = (false, 0);
+    if let TokenTree::Nested { elements, kind, .. } = current {
+        result = matches!(kind.kind, NestKind_::Brace | NestKind_::ParentTheses)
+            && analyze_token_tree_length(elements, MAX_ANALYZE_LENGTH) > MIN_BREAK_LENGTH;
+        elements_len = elements.len();
+    }
+    (result, elements_len)
+}
+
+fn is_statement_start_token(tok: Tok) -> bool {
+    matches!(
+        tok,
+        Tok::Friend
+            | Tok::Const
+            | Tok::Fun
+            | Tok::While
+            | Tok::Use
+            | Tok::Struct
+            | Tok::Spec
+            | Tok::Return
+            | Tok::Public
+            | Tok::Native
+            | Tok::Inline
+            | Tok::Move
+            | Tok::Module
+            | Tok::Loop
+            | Tok::Let
+            | Tok::Invariant
+            | Tok::If
+            | Tok::Continue
+            | Tok::Break
+            | Tok::NumSign
+            | Tok::Amp
+            | Tok::LParen
+            | Tok::Abort
+    )
+}
+
+fn check_next_tok_canbe_break(next: Option<&TokenTree>) -> bool {
+    if let Some((next_tok, next_content)) = next.map(|x| match x {
+        TokenTree::SimpleToken {
+            content,
+            pos: _,
+            tok,
+            ..
+        } => (*tok, content.clone()),
+        TokenTree::Nested { kind, .. } => {
+            (kind.kind.start_tok(), kind.kind.start_tok().to_string())
+        }
+    }) {
+        if is_statement_start_token(next_tok) {
+            true
+        } else if next_tok == Tok::Identifier {
+            next_content.as_str() == "entry"
+        } else {
+            false
+        }
+    } else {
+        true
+    }
+}
+
+fn tok_suitable_for_new_line(tok: Tok, note: Option<Note>, next: Option<&TokenTree>) -> bool {
+    if next
+        .and_then(|x| match x {
+            TokenTree::SimpleToken { .. } => None,
+            TokenTree::Nested { kind, .. } => Some(kind.kind == NestKind_::Type),
+        })
+        .unwrap_or_default()
+    {
+        // not break for generic <Type>
+        return false;
+    }
+    let is_bin = note.map(|x| x == Note::BinaryOP).unwrap_or_default();
+    let ret = match tok {
+        Tok::Less | Tok::Amp | Tok::Star | Tok::Greater if is_bin => true,
+        Tok::ExclaimEqual
+        | Tok::Percent
+        | Tok::AmpAmp
+        | Tok::Plus
+        | Tok::Minus
+        | Tok::Period
+        | Tok::Slash
+        | Tok::LessEqual
+        | Tok::LessLess
+        | Tok::Equal
+        | Tok::EqualEqual
+        | Tok::EqualEqualGreater
+        | Tok::LessEqualEqualGreater
+        | Tok::GreaterEqual
+        | Tok::GreaterGreater
+        | Tok::Pipe
+        | Tok::PipePipe
+        | Tok::NumValue
+        | Tok::NumTypedValue => true,
+        _ => false,
+    };
+    tracing::trace!("tok_suitable_for_new_line ret = {}", ret);
+    ret
+}
+
+/// The length of the nested token `kind` on one line, its tokens joined by a space or not.
+pub(crate) fn get_kind_len_after_trim_space(
+    content: &str,
+    kind: NestKind,
+    join_by_space: bool,
+) -> usize {
+    let nested_blk_str = content[kind.start_pos as usize..kind.end_pos as usize].replace('\n', "");
+    let tok_vec = nested_blk_str[1..]
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    if join_by_space {
+        tok_vec.join(" ").len() + 2
+    } else {
+        tok_vec.join("").len() + 2
+    }
+}
+
+/// Whether a comment appended to `output` needs a space before it.
+pub(crate) fn no_space_or_new_line_for_comment(output: &str) -> bool {
+    if let Some(last_char) = output.chars().last() {
+        !matches!(last_char, '\n' | ' ' | '(')
+    } else {
+        false
+    }
+}
+
+fn check_cur_token_is_long_bin_op(
+    ctx: &LayoutCtx,
+    kind: &NestKind,
+    elements: &[TokenTree],
+    index: usize,
+    next_tok: Tok,
+) -> bool {
+    let current = &elements[index];
+    let next = elements.get(index + 1);
+    let let_handler = ctx.syntax_handler.handler_immut::<LetHandler>();
+    let call_handler = ctx.syntax_handler.handler_immut::<CallHandler>();
+    let cur_token_str = current.simple_str().unwrap_or_default();
+    if matches!(cur_token_str, "==>" | "<==>") {
+        let next_token_start_pos = next.unwrap().start_pos();
+        if translate_line(ctx.line_mapping, next_token_start_pos)
+            <= translate_line(ctx.line_mapping, current.end_pos()) + 1
+            && let_handler.is_long_bin_op(current.clone())
+        {
+            return true;
+        }
+    }
+
+    // updated in 20240607: fix https://github.com/movebit/movefmt/issues/7
+    if cur_token_str == "="
+        && next.unwrap().simple_str().unwrap_or_default() != "vector"
+        && next_tok != Tok::LBrace
+        && call_handler.component_is_complex_blk(
+            ctx.cfg.clone(),
+            kind,
+            elements,
+            index as i64,
+            ctx.cur_line_len(),
+        ) != 2
+    {
+        return let_handler.is_long_assign(
+            current.clone(),
+            next,
+            ctx.cfg.clone(),
+            ctx.last_line.len() + 2,
+        );
+    }
+
+    false
+}
+
+fn check_next_token_is_long_bin_op(
+    ctx: &LayoutCtx,
+    current: &TokenTree,
+    next_t: Option<&TokenTree>,
+    next_token: Tok,
+) -> bool {
+    let let_handler = ctx.syntax_handler.handler_immut::<LetHandler>();
+    let bin_op_handler = ctx.syntax_handler.handler_immut::<BinOpHandler>();
+    let max_width = ctx.cfg.max_width();
+    if matches!(next_token, Tok::AmpAmp | Tok::PipePipe)
+        && let_handler.is_long_bin_op(next_t.unwrap().clone())
+    {
+        return true;
+    }
+    if matches!(
+        next_token,
+        Tok::EqualEqualGreater | Tok::LessEqualEqualGreater | Tok::Equal
+    ) {
+        return false;
+    }
+    let current_token_len = analyze_token_tree_length(&[current.clone()], max_width);
+    let len_plus_cur_token = ctx.last_line.len() + current_token_len + 2;
+    if len_plus_cur_token > max_width {
+        return false;
+    }
+
+    if let TokenTree::Nested { elements, .. } = current {
+        let delimiter = analyze_token_tree_delimiter(elements).0;
+        let cur_nested_break_mode = get_break_mode_begin_nested(ctx, current, delimiter);
+        if cur_nested_break_mode.0 || cur_nested_break_mode.1 == Some(true) {
+            return false;
+        }
+        for nested_nested_in_current_tree in elements {
+            if let TokenTree::Nested { kind: tmp_kind, .. } = nested_nested_in_current_tree {
+                if nested_nested_in_current_tree.token_len() as usize > MIN_BREAK_LENGTH
+                    && tmp_kind.kind == NestKind_::Brace
+                {
+                    return false;
+                }
+            }
+        }
+    };
+
+    if is_bin_op(next_token) {
+        let r_exp_len_tuple = bin_op_handler.get_bin_op_right_part_len(next_t.unwrap().clone());
+        if r_exp_len_tuple.0 == 0 && r_exp_len_tuple.1 < 8 {
+            return false;
+        }
+        tracing::trace!(
+            "last_line.len() = {:?}, r_exp_len_tuple = {:?}",
+            ctx.last_line.len(),
+            r_exp_len_tuple
+        );
+        let len_bin_op_full = len_plus_cur_token
+            + 2
+            + next_t.unwrap().simple_str().unwrap_or_default().len()
+            + r_exp_len_tuple.1;
+        if len_bin_op_full >= max_width {
+            bin_op_handler.record_long_op(r_exp_len_tuple.0);
+            return true;
+        }
+    }
+    false
+}
+
+fn check_next_token_is_quant_body(
+    ctx: &LayoutCtx,
+    current: &TokenTree,
+    next_t: Option<&TokenTree>,
+) -> bool {
+    let quant_handler = ctx.syntax_handler.handler_immut::<QuantHandler>();
+    let max_width = ctx.cfg.max_width();
+    if current.get_end_tok() == Tok::Colon {
+        let (quant_exp_idx, quant_body_len) =
+            quant_handler.get_quant_body_len(next_t.unwrap().clone());
+        if quant_body_len < 8 {
+            return false;
+        }
+
+        let len_plus_cur_token = ctx.last_line.len() + current.token_len() as usize + 2;
+        if len_plus_cur_token > max_width {
+            return false;
+        }
+        if len_plus_cur_token + quant_body_len > max_width {
+            quant_handler.record_long_quant_exp(quant_exp_idx);
+            return true;
+        }
+    }
+    false
+}
+
+fn check_new_line_mode_for_cur_tok(
+    kind_outer: &NestKind,
+    delimiter: Option<Delimiter>,
+    current: &TokenTree,
+    next: Option<&TokenTree>,
+) -> bool {
+    if next.and_then(|x| x.simple_str()) == delimiter.map(|x| x.to_static_str()) {
+        return false;
+    }
+
+    let b_judge_next_token = check_next_tok_canbe_break(next);
+
+    // special case for `}}`
+    if let TokenTree::Nested { kind, .. } = current {
+        if kind.kind == NestKind_::Brace
+            && kind_outer.kind == NestKind_::Brace
+            && b_judge_next_token
+        {
+            return true;
+        }
+    }
+
+    // added in 20240426: special case for current is long nested type
+    if matches!(kind_outer.kind, NestKind_::Brace | NestKind_::ParentTheses) {
+        let result_inner = is_long_nested_token(current);
+        if b_judge_next_token && result_inner.0 && result_inner.1 > 4 {
+            return true;
+        }
+    }
+
+    // added in 20240911
+    // special case: there are no comma between ENUM fields
+    if current.get_end_tok() == Tok::RBrace
+        && next.is_some()
+        && next.unwrap().get_start_tok() == Tok::Identifier
+        && !matches!(next.unwrap().simple_str().unwrap_or_default(), "to" | "for")
+    {
+        return true;
+    }
+    false
+}
+
+fn get_new_line_mode_for_cur_tok(
+    kind_outer: &NestKind,
+    current: &TokenTree,
+    next: Option<&TokenTree>,
+) -> bool {
+    if kind_outer.end_pos - current.end_pos() < MIN_NESTED_LENGTH.try_into().unwrap() {
+        return false;
+    }
+    let b_judge_next_token = next.is_some() && check_next_tok_canbe_break(next);
+    matches!(kind_outer.kind, NestKind_::Brace | NestKind_::ParentTheses)
+        && b_judge_next_token
+        && is_long_nested_token(current).0
+}
+
+/// Whether the line breaks after the element `index` of `nested_token`.
+pub(crate) fn need_new_line_for_cur_tok_finished(
+    ctx: &LayoutCtx,
+    nested_token: &TokenTree,
+    delimiter: Option<Delimiter>,
+    index: usize,
+    component_break_mode: bool,
+    nested_kind_len: usize,
+) -> bool {
+    let TokenTree::Nested { elements, kind, .. } = nested_token else {
+        return false;
+    };
+
+    let t = elements.get(index).unwrap();
+    let next_t = elements.get(index + 1);
+    let d = delimiter.map(|x| x.to_static_str());
+    let t_str = t.simple_str();
+
+    let mut new_line = if component_break_mode {
+        check_new_line_mode_for_cur_tok(kind, delimiter, t, next_t)
+            || (d == t_str && d.is_some() && kind.kind != NestKind_::Type)
+    } else {
+        get_new_line_mode_for_cur_tok(kind, t, next_t)
+    };
+
+    // comma in fun resource access specifier not change new line
+    if let Some(deli_str) = d.filter(|_| d == t_str) {
+        if deli_str.contains(',') {
+            let mut idx = index;
+            while idx != 0 {
+                let ele = elements.get(idx).unwrap();
+                idx -= 1;
+                let Some(key) = ele.simple_str() else {
+                    continue;
+                };
+                if key.contains("fun") {
+                    break;
+                }
+                if matches!(key, "acquires" | "reads" | "writes" | "pure") {
+                    new_line = false;
+                    break;
+                }
+            }
+        }
+    }
+
+    // ablility not change new line
+    // optimize in 20240510: maybe like variable name or struct field name are ability, like "key"
+    // fixed bug in 20240718: you can see case [tests/bug/input4.move]
+    let mut next_token = Tok::EOF;
+    if let Some((next_tok, next_content)) = next_t.map(|x| match x {
+        TokenTree::SimpleToken { content, tok, .. } => (*tok, content.clone()),
+        TokenTree::Nested { kind, .. } => {
+            (kind.kind.start_tok(), kind.kind.start_tok().to_string())
+        }
+    }) {
+        if new_line
+            && d == t_str
+            && t_str.unwrap_or_default() == ","
+            && token_to_ability(
+                ctx.pre_simple_tok(),
+                ctx.pre_simple_token.simple_str().unwrap_or_default(),
+            )
+            .is_some()
+            && token_to_ability(next_tok, &next_content).is_some()
+        {
+            new_line = false;
+        }
+        next_token = next_tok;
+    }
+
+    if nested_kind_len > MIN_NESTED_LENGTH && kind.kind != NestKind_::Type {
+        new_line |= check_cur_token_is_long_bin_op(ctx, kind, elements, index, next_token);
+        if !new_line && next_t.is_some() {
+            if check_next_token_is_long_bin_op(ctx, t, next_t, next_token) {
+                return true;
+            }
+            if check_next_token_is_quant_body(ctx, t, next_t) {
+                return true;
+            }
+        }
+    }
+    new_line
+}
+
+fn get_break_mode_of_fun_call(
+    ctx: &LayoutCtx,
+    token: &TokenTree,
+    nested_token_len: usize,
+    opt_component_break_mode: &mut bool,
+) -> bool {
+    let TokenTree::Nested { elements, kind, .. } = token else {
+        return false;
+    };
+    let call_handler = ctx.syntax_handler.handler_immut::<CallHandler>();
+    let mut new_line_mode = false;
+    let elements_str = serde_json::to_string(&elements).unwrap_or_default();
+    let has_multi_para = elements_str.matches("\"content\":\",\"").count() > 2;
+    if call_handler.get_call_component_split_mode(
+        ctx.cfg.clone(),
+        kind,
+        elements,
+        ctx.last_line.len(),
+    ) {
+        new_line_mode = true;
+
+        let next_line_len = (ctx.depth + 1) * ctx.local_cfg().indent_size;
+        if call_handler.get_call_component_split_mode(
+            ctx.cfg.clone(),
+            kind,
+            elements,
+            next_line_len,
+        ) {
+            *opt_component_break_mode = true;
+        }
+    }
+
+    if !*opt_component_break_mode
+        && has_multi_para
+        && (nested_token_len as f32 > ctx.local_cfg().max_len_no_add_line
+            || (!ctx.cfg.prefer_one_line_for_short_call_para_list() && new_line_mode))
+    {
+        *opt_component_break_mode = true;
+    }
+    new_line_mode
+}
+
+fn get_break_mode_begin_paren(ctx: &LayoutCtx, token: &TokenTree) -> (bool, Option<bool>) {
+    let TokenTree::Nested { elements, kind, .. } = token else {
+        return (false, None);
+    };
+    if NestKind_::ParentTheses != kind.kind {
+        return (false, None);
+    }
+    if elements.len() == 1 && elements[0].simple_str().is_none() {
+        return (false, None);
+    }
+    let cfg = ctx.cfg;
+    let max_len_no_add_line = ctx.local_cfg().max_len_no_add_line;
+    let mut new_line_mode = false;
+    let nested_token_len = get_kind_len_after_trim_space(ctx.content, *kind, true);
+
+    let mut opt_component_break_mode =
+        nested_token_len + ctx.depth * ctx.local_cfg().indent_size >= cfg.max_width();
+
+    let maybe_in_fun_header = ctx
+        .syntax_handler
+        .handler_immut::<FunHandler>()
+        .is_parameter_paren_in_fun_header(kind);
+    if matches!(ctx.pre_simple_tok(), Tok::If | Tok::While) {
+        new_line_mode = false;
+    } else if maybe_in_fun_header.0 {
+        new_line_mode |= maybe_in_fun_header.1 > cfg.max_width();
+        // Reserve 25% space for return ty and specifier
+        new_line_mode |= (ctx.cur_line_len() + nested_token_len) as f32 > max_len_no_add_line;
+
+        let nested_and_comma_pair = expr_fmt::get_nested_and_comma_num(elements);
+        if cfg.prefer_one_line_for_short_fn_header_para_list() {
+            opt_component_break_mode |= (nested_and_comma_pair.0 >= 4
+                || nested_and_comma_pair.1 > 2)
+                && token.token_len() as f32 > max_len_no_add_line;
+        } else {
+            // one parameter per line as soon as there are several of them
+            opt_component_break_mode |= expr_fmt::get_top_level_item_num(elements) > 1;
+        }
+
+        new_line_mode |= opt_component_break_mode;
+    } else if ctx.cur_line_len() > cfg.max_width() {
+        new_line_mode = true;
+    } else {
+        let elements_str = serde_json::to_string(&elements).unwrap_or_default();
+        let has_multi_para = elements_str.matches("\"content\":\",\"").count() > 2;
+        let is_in_fun_call = ctx
+            .syntax_handler
+            .handler_immut::<CallHandler>()
+            .paren_in_call(kind);
+        if is_in_fun_call {
+            new_line_mode |= get_break_mode_of_fun_call(
+                ctx,
+                token,
+                nested_token_len,
+                &mut opt_component_break_mode,
+            );
+        } else {
+            new_line_mode |= has_multi_para && ctx.pre_simple_tok() == Tok::Identifier;
+        }
+        if elements[0].simple_str().is_some() {
+            let is_plus_nested_over_width =
+                ctx.cur_line_len() + nested_token_len > cfg.max_width() && nested_token_len > 8;
+            let is_nested_len_too_large = nested_token_len as f32 > 2.0 * max_len_no_add_line;
+            new_line_mode |= is_plus_nested_over_width || is_nested_len_too_large;
+        }
+
+        let first_ele_len = analyze_token_tree_length(&[elements[0].clone()], cfg.max_width());
+        let is_plus_first_ele_over_width =
+            ctx.cur_line_len() + first_ele_len > cfg.max_width() && first_ele_len > 8;
+
+        new_line_mode |= is_plus_first_ele_over_width;
+        new_line_mode |= opt_component_break_mode && has_multi_para;
+    }
+
+    let nested_blk_str = &ctx.content[kind.start_pos as usize..kind.end_pos as usize];
+    if !new_line_mode && contains_comment(nested_blk_str) && nested_blk_str.contains("//") {
+        new_line_mode = true;
+    }
+    (new_line_mode, Some(opt_component_break_mode))
+}
+
+fn get_control_blk_cnt(elements: &[TokenTree]) -> usize {
+    elements
+        .iter()
+        .filter(|ele| {
+            matches!(
+                ele.get_start_tok(),
+                Tok::If | Tok::Else | Tok::Loop | Tok::While
+            )
+        })
+        .count()
+}
+
+fn get_break_mode_begin_branch_blk(ctx: &LayoutCtx, kind: &NestKind) -> bool {
+    let branch_handler = ctx.syntax_handler.handler_immut::<BranchHandler>();
+    if branch_handler
+        .com_if_else
+        .then_loc_vec
+        .iter()
+        .any(|&x| x.start() == kind.start_pos)
+        || branch_handler
+            .com_if_else
+            .else_loc_vec
+            .iter()
+            .any(|&x| x.start() == kind.start_pos)
+    {
+        if ctx.cfg.prefer_one_line_for_short_branch_blk() {
+            return get_kind_len_after_trim_space(ctx.content, *kind, true) > 8;
+        } else {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether the nested token breaks its line after its opening token, and whether
+/// each of its elements goes on its own line when that's decided here.
+pub(crate) fn get_break_mode_begin_nested(
+    ctx: &LayoutCtx,
+    token: &TokenTree,
+    delimiter: Option<Delimiter>,
+) -> (bool, Option<bool>) {
+    let TokenTree::Nested {
+        elements,
+        kind,
+        note,
+    } = token
+    else {
+        return (false, None);
+    };
+    let max_len_no_add_line = ctx.local_cfg().max_len_no_add_line;
+    let max_line_width = ctx.cfg.max_width();
+    let nested_blk_str = &ctx.content[kind.start_pos as usize..kind.end_pos as usize];
+    let nested_len = get_kind_len_after_trim_space(ctx.content, *kind, true);
+    if elements.is_empty() {
+        let should_break = nested_len as f32 > max_len_no_add_line
+            || (contains_comment(nested_blk_str) && nested_blk_str.lines().count() > 1);
+        return (should_break, None);
+    }
+
+    // 20240329 updated
+    // fun body brace always change new line;
+    // if ParentTheses is empty, not change new line;
+    // 20240425 updated
+    // The value of new_line_mode here is not associated with Paren, only with Brace.
+    // Because Paren may come from fn_para or call or expression statements...
+    let is_stct_def = note.is_some_and(|x| x == Note::StructDefinition);
+    let mut new_line_mode = {
+        delimiter.is_some_and(|d| d == Delimiter::Semicolon)
+            || is_stct_def
+            || note.is_some_and(|x| x == Note::FunBody)
+    };
+    if new_line_mode && kind.kind != NestKind_::Type {
+        if is_stct_def {
+            return (true, Some(true));
+        }
+        return (true, None);
+    }
+
+    match kind.kind {
+        NestKind_::Type => {
+            // added in 20240112: if type in fun header, not change new line
+            if ctx
+                .syntax_handler
+                .handler_immut::<FunHandler>()
+                .is_generic_ty_in_fun_header(kind)
+            {
+                return (false, None);
+            }
+
+            let first_ele_len = analyze_token_tree_length(&[elements[0].clone()], max_line_width);
+            new_line_mode =
+                ctx.cur_line_len() + first_ele_len > max_line_width && first_ele_len > 8;
+        }
+        NestKind_::ParentTheses => return get_break_mode_begin_paren(ctx, token),
+        NestKind_::Bracket => {
+            let is_annotation = ctx.pre_simple_tok() == Tok::NumSign;
+            new_line_mode = (is_annotation && nested_len > max_line_width)
+                || (!is_annotation && nested_len as f32 > max_len_no_add_line);
+            if elements.len() > MIN_BREAK_LENGTH {
+                let mut bin_op_cnt = 0;
+                let mut complex_ele_cnt = 0;
+                for ele in elements {
+                    let result_inner = is_long_nested_token(ele);
+                    if result_inner.0 && result_inner.1 > 4 {
+                        complex_ele_cnt += 1;
+                    }
+                    if is_bin_op(ele.get_start_tok()) {
+                        bin_op_cnt += 1;
+                    }
+                }
+                return (new_line_mode, Some(complex_ele_cnt > 4 || bin_op_cnt > 4));
+            }
+        }
+        NestKind_::Lambda => {
+            new_line_mode |= (ctx.cur_line_len() + nested_len) as f32 > max_len_no_add_line;
+            let mut opt_component_break_mode = false;
+            let nested_and_comma_pair = expr_fmt::get_nested_and_comma_num(elements);
+            if ctx.cfg.prefer_one_line_for_short_lambda_para_list() {
+                opt_component_break_mode |= (nested_and_comma_pair.0 >= 4
+                    || nested_and_comma_pair.1 > 2)
+                    && token.token_len() as f32 > max_len_no_add_line;
+            } else {
+                opt_component_break_mode |= nested_and_comma_pair.1 > 1;
+            }
+
+            new_line_mode |= opt_component_break_mode;
+            if delimiter.is_none() && nested_len as f32 <= max_len_no_add_line {
+                new_line_mode = false;
+            }
+        }
+        NestKind_::Brace => {
+            if nested_len > 4 {
+                // case1: over max width
+                new_line_mode |= ctx.cur_line_len() + nested_len > max_line_width;
+                new_line_mode |= ctx.last_line.len() + nested_len > max_line_width;
+
+                // case2: has special keyword
+                new_line_mode |=
+                    has_special_key_for_break_line_in_code_buf(ctx.last_line.to_string());
+            }
+
+            // case3: nested_len too long
+            new_line_mode |= nested_len as f32 > max_len_no_add_line;
+
+            // case4: contains comment
+            new_line_mode |= contains_comment(nested_blk_str) && nested_blk_str.lines().count() > 1;
+
+            // case5: has too much nested blks
+            let (nested_cnt, _) = expr_fmt::get_nested_and_comma_num(elements);
+            new_line_mode |= nested_cnt >= 2 && nested_len > MIN_BREAK_LENGTH;
+
+            // case6: has too much control blks
+            new_line_mode |= get_control_blk_cnt(elements) >= 2;
+
+            // case7: maybe in branch blk
+            new_line_mode |= get_break_mode_begin_branch_blk(ctx, kind);
+        }
+    }
+    (new_line_mode, None)
+}
+
+/// Whether spaces go inside the braces of `nested_token`, like `{ a }`.
+pub(crate) fn judge_add_space_around_brace(
+    ctx: &LayoutCtx,
+    nested_token: &TokenTree,
+    b_new_line_mode: bool,
+) -> bool {
+    let TokenTree::Nested { elements, kind, .. } = nested_token else {
+        return true;
+    };
+    let nested_token_head = ctx.pre_simple_tok();
+    // optimize in 20240425
+    // there are 2 cases which not add space
+    // eg1: When braces are used for arithmetic operations
+    // let intermediate3: u64 = (a * {c + d}) - (b / {e - 2});
+    // shouldn't formated like `let intermediate3: u64 = (a * { c + d }) - (b / { e - 2 });`
+    // eg2: When the braces are used for use
+    // use A::B::{C, D}
+    // shouldn't formated like `use A::B::{ C, D }`
+    let is_arithmetic_op = matches!(
+        nested_token_head,
+        Tok::Plus | Tok::Minus | Tok::Star | Tok::Slash | Tok::Percent
+    );
+    let b_not_arithmetic_op_brace = !is_arithmetic_op && kind.kind == NestKind_::Brace;
+    let b_not_use_brace = Tok::ColonColon != nested_token_head && kind.kind == NestKind_::Brace;
+    let nested_blk_str = &ctx.content[kind.start_pos as usize + 1..kind.end_pos as usize];
+    (elements.is_empty() && contains_comment(nested_blk_str))
+        || (b_not_arithmetic_op_brace
+            && b_not_use_brace
+            && !b_new_line_mode
+            && !elements.is_empty())
+}
+
+/// Whether the line breaks before the simple token `tok` because it would go beyond `max_width`.
+pub(crate) fn judge_change_new_line_when_over_limits(
+    ctx: &LayoutCtx,
+    tok_str: &str,
+    tok: Tok,
+    note: Option<Note>,
+    next: Option<&TokenTree>,
+) -> bool {
+    let max_width = ctx.cfg.max_width();
+    let len_plus_tok_len = ctx.cur_line_len() + tok_str.len();
+    if tok == Tok::AtSign {
+        if let Some(next) = next {
+            let next_tok_len = next.simple_str().unwrap_or_default().len();
+            if next_tok_len > 8 && len_plus_tok_len + next_tok_len > max_width {
+                return true;
+            }
+        }
+    }
+
+    if ctx.pre_simple_tok() == Tok::AtSign {
+        return false;
+    }
+
+    len_plus_tok_len > max_width && tok_suitable_for_new_line(tok, note, next)
+}
@@ -0,0 +1,231 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::core::fmt::EngineError;
+use crate::core::format_source;
+use commentfmt::{Config, ErrorKind};
+use move_command_line_common::files::FileHash;
+use move_compiler::parser::lexer::{Lexer, Tok};
+use std::ops::Range;
+
+/// Byte range of the lines `start_line..=end_line` (1-based) of `content`,
+/// clamped to the end of `content`.
+pub fn lines_to_byte_range(content: &str, start_line: usize, end_line: usize) -> Range<usize> {
+    let mut start = content.len();
+    let mut end = content.len();
+    let mut offset = 0;
+    for (idx, line) in content.split_inclusive('\n').enumerate() {
+        let line_no = idx + 1;
+        if line_no == start_line {
+            start = offset;
+        }
+        offset += line.len();
+        if line_no == end_line {
+            end = offset;
+            break;
+        }
+    }
+    start..end.max(start)
+}
+
+/// Byte ranges of the top level items of `content`, in source order.
+/// A top level item is a member of a module or script: a function, struct, constant,
+/// spec block, use or friend declaration, together with its attributes.
+/// Comments before an item don't belong to it.
+pub fn collect_top_level_items(content: &str) -> Vec<Range<usize>> {
+    let mut items: Vec<Range<usize>> = vec![];
+    let mut lexer = Lexer::new(content, FileHash::empty());
+    if lexer.advance().is_err() {
+        return items;
+    }
+
+    let mut depth: usize = 0;
+    // brace depths of the module/script bodies we are in
+    let mut member_depths: Vec<usize> = vec![];
+    let mut expect_members_begin = false;
+    let mut cur_item_start: Option<usize> = None;
+    // `use`, `const` and `friend` may contain braces, but they always end with a `;`
+    let mut cur_item_ends_with_semicolon = false;
+    while lexer.peek() != Tok::EOF {
+        let tok = lexer.peek();
+        let tok_start = lexer.start_loc();
+        let tok_end = tok_start + lexer.content().len();
+        let at_member_level = member_depths.last() == Some(&depth);
+        if at_member_level && cur_item_start.is_none() && tok != Tok::RBrace {
+            cur_item_start = Some(tok_start);
+        }
+
+        match tok {
+            Tok::Module | Tok::Script if cur_item_start.is_none() => {
+                expect_members_begin = true;
+            }
+            Tok::Use | Tok::Const | Tok::Friend if at_member_level => {
+                cur_item_ends_with_semicolon = true;
+            }
+            Tok::LBrace => {
+                depth += 1;
+                if expect_members_begin {
+                    member_depths.push(depth);
+                    expect_members_begin = false;
+                }
+            }
+            Tok::RBrace => {
+                if at_member_level && cur_item_start.is_none() {
+                    member_depths.pop();
+                }
+                depth = depth.saturating_sub(1);
+                if member_depths.last() == Some(&depth) && !cur_item_ends_with_semicolon {
+                    if let Some(start) = cur_item_start.take() {
+                        items.push(start..tok_end);
+                    }
+                }
+            }
+            Tok::Semicolon if at_member_level => {
+                if let Some(start) = cur_item_start.take() {
+                    items.push(start..tok_end);
+                    cur_item_ends_with_semicolon = false;
+                }
+            }
+            _ => {}
+        }
+
+        if lexer.advance().is_err() {
+            break;
+        }
+    }
+    items
+}
+
+/// Format only the top level items of `content` overlapping the byte range `range`,
+/// everything else is kept byte-identical.
+/// An empty `range` selects the item containing the position `range.start`.
+pub fn format_range(
+    content: impl AsRef<str>,
+    config: Config,
+    range: Range<usize>,
+) -> Result<String, EngineError> {
+    format_ranges(content, config, &[range])
+}
+
+/// Like `format_range`, but formats the items overlapping any of the byte ranges `ranges`.
+pub fn format_ranges(
+    content: impl AsRef<str>,
+    config: Config,
+    ranges: &[Range<usize>],
+) -> Result<String, EngineError> {
+    format_source(content.as_ref(), config, Some(ranges)).map(|output| output.text)
+}
+
+/// `content` with the top level items overlapping any of the byte ranges `ranges`
+/// replaced by their counterpart in `formatted`, the whole `content` formatted.
+/// Fails if the items of both texts can't be matched.
+pub(crate) fn splice_items(
+    content: &str,
+    formatted: &str,
+    ranges: &[Range<usize>],
+) -> Result<String, ErrorKind> {
+    let origin_items = collect_top_level_items(content);
+    let formatted_items = collect_top_level_items(formatted);
+    if origin_items.len() != formatted_items.len() {
+        return Err(ErrorKind::LostToken(format!(
+            "{} top level items before formatting but {} after",
+            origin_items.len(),
+            formatted_items.len()
+        )));
+    }
+
+    let overlaps = |item: &Range<usize>| {
+        ranges.iter().any(|range| {
+            let range_end = range.end.max(range.start + 1);
+            item.start < range_end && range.start < item.end
+        })
+    };
+    let mut result = String::with_capacity(content.len());
+    let mut copied_until = 0;
+    for (origin_item, formatted_item) in origin_items.into_iter().zip(formatted_items) {
+        if overlaps(&origin_item) {
+            result.push_str(&content[copied_until..origin_item.start]);
+            result.push_str(&formatted[formatted_item]);
+            copied_until = origin_item.end;
+        }
+    }
+    result.push_str(&content[copied_until..]);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::format_with_engine;
+
+    const SOURCE: &str = "module 0x1::m {
+    use std::vector::{Self,
+        length};
+    #[test_only]
+    struct S has copy, drop { f: u64 }
+    // comment of f
+    fun f(x:u64):u64{x+1}
+    spec f { ensures result == x + 1; }
+    fun g(x:u64):u64{x+2}
+}
+";
+
+    #[test]
+    fn test_collect_top_level_items() {
+        let items: Vec<&str> = collect_top_level_items(SOURCE)
+            .into_iter()
+            .map(|item| &SOURCE[item])
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                "use std::vector::{Self,\n        length};",
+                "#[test_only]\n    struct S has copy, drop { f: u64 }",
+                "fun f(x:u64):u64{x+1}",
+                "spec f { ensures result == x + 1; }",
+                "fun g(x:u64):u64{x+2}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_range_keeps_other_items() {
+        let range = lines_to_byte_range(SOURCE, 7, 7);
+        let result = format_range(SOURCE, Config::default(), range).unwrap();
+        let formatted = format_with_engine(SOURCE, Config::default()).unwrap();
+        let formatted_f = &formatted[collect_top_level_items(&formatted)[2].clone()];
+        assert_eq!(result, SOURCE.replace("fun f(x:u64):u64{x+1}", formatted_f));
+        assert!(result.contains("fun g(x:u64):u64{x+2}"));
+        assert!(result.contains("use std::vector::{Self,\n        length};"));
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        let ranges = [
+            lines_to_byte_range(SOURCE, 2, 2),
+            lines_to_byte_range(SOURCE, 9, 9),
+        ];
+        let result = format_ranges(SOURCE, Config::default(), &ranges).unwrap();
+        assert!(result.contains("fun f(x:u64):u64{x+1}"));
+        assert!(!result.contains("fun g(x:u64):u64{x+2}"));
+        assert!(!result.contains("use std::vector::{Self,\n        length};"));
+    }
+
+    #[test]
+    fn test_splice_items_mismatch() {
+        let content = "module 0x1::m { fun f(){} fun g(){} }";
+        let formatted = "module 0x1::m {\n    fun f() {}\n}\n";
+        assert!(matches!(
+            splice_items(content, formatted, &[0..1]),
+            Err(ErrorKind::LostToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_lines_to_byte_range() {
+        let content = "a\nbc\nd";
+        assert_eq!(&content[lines_to_byte_range(content, 2, 2)], "bc\n");
+        assert_eq!(&content[lines_to_byte_range(content, 2, 9)], "bc\nd");
+        assert_eq!(lines_to_byte_range(content, 9, 9), 6..6);
+    }
+}
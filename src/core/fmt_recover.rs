@@ -0,0 +1,238 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::core::fmt::EngineError;
+use crate::core::{format_with_engine, format_with_engine_timed};
+use crate::tools::utils::{Timer, get_compile_env};
+use commentfmt::Config;
+use move_command_line_common::files::FileHash;
+use move_compiler::parser::lexer::{Lexer, Tok};
+use move_compiler::parser::syntax::parse_file_string;
+use std::ops::Range;
+
+/// A top level block kept verbatim by `format_entry_tolerant`.
+#[derive(Debug)]
+pub struct UnformattedBlock {
+    /// Byte range of the block in the input.
+    pub range: Range<usize>,
+    /// Why the block was not formatted, the locations of parse diagnostics
+    /// are those of the whole input.
+    pub error: EngineError,
+}
+
+/// The result of `format_entry_tolerant` and `core::format_source`.
+#[derive(Debug)]
+pub struct TolerantOutput {
+    pub text: String,
+    pub unformatted_blocks: Vec<UnformattedBlock>,
+    /// `None` if the content was formatted block by block.
+    pub timer: Option<Timer>,
+}
+
+/// Byte ranges of the top level blocks of `content`: the modules, address blocks and scripts
+/// together with their attributes, in source order.
+/// Each range starts where the previous one ends, so the comments before a block belong to it.
+/// Whatever follows the last complete block, e.g. a block missing its closing brace
+/// or the rest of the file after a lexing error, is one more block.
+pub fn collect_top_level_blocks(content: &str) -> Vec<Range<usize>> {
+    let mut blocks: Vec<Range<usize>> = vec![];
+    let mut block_start = 0;
+    let mut lexer = Lexer::new(content, FileHash::empty());
+    if lexer.advance().is_ok() {
+        let mut depth: usize = 0;
+        while lexer.peek() != Tok::EOF {
+            let tok_end = lexer.start_loc() + lexer.content().len();
+            match lexer.peek() {
+                Tok::LBrace => depth += 1,
+                Tok::RBrace if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        blocks.push(block_start..tok_end);
+                        block_start = tok_end;
+                    }
+                }
+                // e.g. `module 0x1::m;`
+                Tok::Semicolon if depth == 0 => {
+                    blocks.push(block_start..tok_end);
+                    block_start = tok_end;
+                }
+                _ => {}
+            }
+            if lexer.advance().is_err() {
+                break;
+            }
+        }
+    }
+    if !content[block_start..].trim().is_empty() {
+        blocks.push(block_start..content.len());
+    }
+    blocks
+}
+
+/// Format `content` even if some of it doesn't parse: if the whole file fails to parse,
+/// every top level block which parses on its own is formatted and the others are kept verbatim.
+/// Fails only if no block at all can be formatted.
+pub fn format_entry_tolerant(
+    content: impl AsRef<str>,
+    config: Config,
+) -> Result<TolerantOutput, EngineError> {
+    let content = content.as_ref();
+    let whole_file_error = match format_with_engine_timed(content, &config) {
+        Ok((text, timer)) => {
+            return Ok(TolerantOutput {
+                text,
+                unformatted_blocks: vec![],
+                timer: Some(timer),
+            });
+        }
+        Err(e @ EngineError::Parse(_)) => e,
+        Err(e) => return Err(e),
+    };
+
+    let mut text = String::with_capacity(content.len());
+    let mut unformatted_blocks = vec![];
+    let mut formatted_cnt = 0;
+    for block in collect_top_level_blocks(content) {
+        let block_content = &content[block.clone()];
+        let code_start = block.start + (block_content.len() - block_content.trim_start().len());
+        // the separating whitespace is kept as it is
+        text.push_str(&content[block.start..code_start]);
+        let formatted = match parse_file_string(
+            &mut get_compile_env(),
+            FileHash::empty(),
+            &blank_outside(content, &block),
+        ) {
+            Ok(_) => format_with_engine(&content[code_start..block.end], config.clone()),
+            Err(diags) => Err(EngineError::Parse(diags)),
+        };
+        match formatted {
+            Ok(formatted) => {
+                text.push_str(formatted.trim_end());
+                formatted_cnt += 1;
+            }
+            Err(error) => {
+                text.push_str(&content[code_start..block.end]);
+                unformatted_blocks.push(UnformattedBlock {
+                    range: code_start..block.end,
+                    error,
+                });
+            }
+        }
+    }
+    if formatted_cnt == 0 {
+        return Err(whole_file_error);
+    }
+    let text = text.trim_end().to_string() + "\n";
+    Ok(TolerantOutput {
+        text,
+        unformatted_blocks,
+        timer: None,
+    })
+}
+
+/// `content` with everything outside of `range` replaced by spaces, newlines excepted,
+/// so that the locations found by parsing the block are those of the whole file.
+fn blank_outside(content: &str, range: &Range<usize>) -> String {
+    let blank = |text: &str| -> String {
+        text.chars()
+            .map(|ch| {
+                if ch == '\n' {
+                    "\n".to_string()
+                } else {
+                    " ".repeat(ch.len_utf8())
+                }
+            })
+            .collect()
+    };
+    let mut result = blank(&content[..range.start]);
+    result.push_str(&content[range.clone()]);
+    result.push_str(&blank(&content[range.end..]));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "// first
+module 0x1::a {
+fun f() {}
+}
+
+#[test_only]
+module 0x1::b {
+    fun g() { let }
+}
+
+address 0x1 {
+module c {
+fun h() {}
+}
+}
+";
+
+    #[test]
+    fn test_collect_top_level_blocks() {
+        let blocks: Vec<&str> = collect_top_level_blocks(SOURCE)
+            .into_iter()
+            .map(|block| SOURCE[block].trim())
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                "// first\nmodule 0x1::a {\nfun f() {}\n}",
+                "#[test_only]\nmodule 0x1::b {\n    fun g() { let }\n}",
+                "address 0x1 {\nmodule c {\nfun h() {}\n}\n}",
+            ]
+        );
+        assert_eq!(
+            collect_top_level_blocks("module 0x1::a {}\nmodule 0x1::b {"),
+            vec![0..16, 16..32]
+        );
+    }
+
+    #[test]
+    fn test_format_entry_tolerant() {
+        let output = format_entry_tolerant(SOURCE, Config::default()).unwrap();
+        assert_eq!(
+            output.text,
+            "// first
+module 0x1::a {
+    fun f() {}
+}
+
+#[test_only]
+module 0x1::b {
+    fun g() { let }
+}
+
+address 0x1 {
+    module c {
+        fun h() {}
+    }
+}
+"
+        );
+        assert_eq!(output.unformatted_blocks.len(), 1);
+        let block = &output.unformatted_blocks[0];
+        assert!(SOURCE[block.range.clone()].starts_with("#[test_only]"));
+        assert!(matches!(block.error, EngineError::Parse(_)));
+
+        assert!(
+            format_entry_tolerant("module 0x1::b { fun g() { let } }", Config::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_format_source_tolerant_range() {
+        let content =
+            "module 0x1::a {\nfun f(){}\nfun g(){}\n}\n\nmodule 0x1::b {\n    fun h() { let }\n}\n";
+        let mut config = Config::default();
+        config.set().error_tolerant(true);
+        let range = crate::core::fmt_range::lines_to_byte_range(content, 2, 2);
+        let output = crate::core::format_source(content, config, Some(&[range])).unwrap();
+        assert_eq!(output.text, content.replace("fun f(){}", "fun f() {}"));
+        assert_eq!(output.unformatted_blocks.len(), 1);
+        assert!(content[output.unformatted_blocks[0].range.clone()].starts_with("module 0x1::b"));
+    }
+}
@@ -6,22 +6,35 @@
 //!
 //! This module provides a formatter implementation based on immutable state,
 //! replacing the original mutable state design based on RefCell/Cell.
-
+//! It can be selected with the `use_functional_formatter` option and is kept
+//! byte-identical with `core::fmt::format_entry`.
+
+use crate::core::fmt::{
+    EXIST_MULTI_ADDRESS_TAG, EXIST_MULTI_MODULE_TAG, EngineError, MAX_ANALYZE_LENGTH,
+    MIN_NESTED_LENGTH, apply_hard_tabs, is_bin_op, layout_config, tune_module_buf,
+};
+use crate::core::fmt_layout::*;
+use crate::core::fmt_verify::verify_formatting;
 use crate::core::token_tree::*;
-use crate::syntax_fmt::skip_fmt::SkipHandler;
+use crate::syntax_fmt::bin_op_fmt::BinOpHandler;
+use crate::syntax_fmt::branch_fmt::BranchHandler;
+use crate::syntax_fmt::call_fmt::CallHandler;
+use crate::syntax_fmt::let_fmt::LetHandler;
+use crate::syntax_fmt::quant_fmt::QuantHandler;
+use crate::syntax_fmt::skip_fmt::{SkipHandler, block_body_type, item_config, keep_skipped_code};
 use crate::syntax_fmt::syntax_handler::SyntaxHandler;
-use crate::syntax_fmt::{big_block_fmt, expr_fmt, fun_fmt, spec_fmt};
+use crate::syntax_fmt::{expr_fmt, fun_fmt};
 use crate::tools::utils::*;
 use commentfmt::Config;
+use commentfmt::comment::contains_comment;
 use move_command_line_common::files::FileHash;
 use move_compiler::diagnostics::Diagnostics;
 use move_compiler::parser::lexer::{Lexer, Tok};
 use move_compiler::parser::{ast::*, syntax::parse_file_string};
+use move_ir_types::location::ByteIndex;
+use std::rc::Rc;
 use std::sync::Arc;
 
-const EXIST_MULTI_MODULE_TAG: &str = "module fmt";
-const EXIST_MULTI_ADDRESS_TAG: &str = "address fmt";
-
 /// Immutable state during the formatting process
 #[derive(Clone)]
 pub struct FormatState {
@@ -37,6 +50,9 @@ pub struct FormatState {
     pub pre_simple_token: TokenTree,
     /// Current nested type
     pub cur_nested_kind: NestKind,
+    /// The configs of the module and function bodies being formatted which override
+    /// some options with attributes, the innermost last
+    pub item_cfg_stack: Vec<Rc<Config>>,
 }
 
 impl FormatState {
@@ -52,6 +68,7 @@ impl FormatState {
                 start_pos: 0,
                 end_pos: 0,
             },
+            item_cfg_stack: vec![],
         }
     }
 
@@ -68,6 +85,7 @@ impl FormatState {
                 start_pos: 0,
                 end_pos: 0,
             },
+            item_cfg_stack: vec![],
         }
     }
 
@@ -115,6 +133,18 @@ impl FormatState {
         self
     }
 
+    /// Enter the body of an item overriding some options
+    pub fn push_item_cfg(mut self, cfg: Rc<Config>) -> Self {
+        self.item_cfg_stack.push(cfg);
+        self
+    }
+
+    /// Leave the body of an item overriding some options
+    pub fn pop_item_cfg(mut self) -> Self {
+        self.item_cfg_stack.pop();
+        self
+    }
+
     /// Get last line content
     pub fn last_line(&self) -> &str {
         self.output.lines().last().unwrap_or("")
@@ -139,8 +169,7 @@ impl Default for FormatState {
 
 /// Formatting context - contains immutable configuration and data
 pub struct FormatContext {
-    pub local_cfg: FormatConfig,
-    pub global_cfg: Config,
+    pub global_cfg: Rc<Config>,
     pub token_tree: Vec<TokenTree>,
     pub comments: Vec<Comment>,
     pub line_mapping: FileLineMappingOneFile,
@@ -148,13 +177,11 @@ pub struct FormatContext {
     pub content: String,
 }
 
-#[derive(Clone, Default)]
-pub struct FormatConfig {
-    pub indent_size: usize,
-    pub max_len_no_add_line: f32,
-}
-
 /// Refactored Format structure - only contains immutable data
+///
+/// Every step mirrors its counterpart in `core::fmt::Format`, but takes the
+/// current `FormatState` by value and returns the updated one, so the output
+/// of both engines is identical. Both take their layout decisions from `core::fmt_layout`.
 pub struct FunctionalFormat {
     context: FormatContext,
 }
@@ -175,11 +202,7 @@ impl FunctionalFormat {
         syntax_handler.preprocess(&defs);
 
         let context = FormatContext {
-            local_cfg: FormatConfig {
-                indent_size: global_cfg.indent_size(),
-                max_len_no_add_line: global_cfg.max_width() as f32 * 0.75,
-            },
-            global_cfg,
+            global_cfg: Rc::new(global_cfg),
             token_tree,
             comments: ce.comments,
             line_mapping,
@@ -202,486 +225,1008 @@ impl FunctionalFormat {
     /// Core method for formatting with state
     fn format_tokens_with_state(&self, mut state: FormatState) -> FormatState {
         let mut pound_sign_idx = None;
-
         for (index, token) in self.context.token_tree.iter().enumerate() {
             if token.is_pound() {
                 pound_sign_idx = Some(index);
             }
-
-            let new_line = pound_sign_idx.map_or(false, |x| (x + 1) == index);
+            let new_line = pound_sign_idx.is_some_and(|x| (x + 1) == index);
             let next_token = self.context.token_tree.get(index + 1);
 
-            // Handle special module and address blocks
-            if let Some(result_state) = self.try_format_special_block(token, state.clone()) {
-                state = result_state;
+            let TokenTree::Nested {
+                kind: nkind, note, ..
+            } = token
+            else {
+                state = self.format_top_level_token(
+                    token,
+                    next_token,
+                    new_line,
+                    &mut pound_sign_idx,
+                    state,
+                );
                 continue;
+            };
+
+            let mut return_buf_cp = state.output.clone();
+            let skip_handler = self.context.syntax_handler.handler_immut::<SkipHandler>();
+            let is_mod_blk = skip_handler.is_module_block(nkind);
+            let is_addr_blk = note.is_some_and(|x| x == Note::ModuleAddress);
+            if is_mod_blk {
+                state.output = EXIST_MULTI_MODULE_TAG.to_string();
+            }
+            if is_addr_blk {
+                state.output = EXIST_MULTI_ADDRESS_TAG.to_string();
             }
 
-            // Format current token
-            state = self.format_token_with_state(token, next_token, new_line, state);
+            state = self.format_top_level_token(
+                token,
+                next_token,
+                new_line,
+                &mut pound_sign_idx,
+                state,
+            );
 
-            if new_line {
-                state = self.add_new_line_with_state(Some(token.end_pos()), state);
-                pound_sign_idx = None;
+            let cfg = self
+                .block_body_cfg(nkind, note, &state)
+                .unwrap_or_else(|| self.context.global_cfg.as_ref().clone());
+            // top level
+            if is_mod_blk {
+                state = self.new_line(Some(token.end_pos()), state);
+                if !skip_handler.has_skipped_module_body(nkind) {
+                    state.output = update_last_line(tune_module_buf(state.output, &cfg));
+                }
+                return_buf_cp.push_str(&state.output[EXIST_MULTI_MODULE_TAG.len()..]);
+                state.output = return_buf_cp;
+            } else if is_addr_blk {
+                state = self.new_line(Some(token.end_pos()), state);
+                state.output = self.process_address_modules(return_buf_cp, &state.output);
+            } else if nkind.kind == NestKind_::Brace {
+                state = self.new_line(Some(token.end_pos()), state);
+                state.output = update_last_line(tune_module_buf(state.output, &cfg));
             }
         }
 
         // Add remaining comments
-        state = self.add_remaining_comments_with_state(state);
-        state
+        self.add_comments(u32::MAX, "end_of_move_file", state)
     }
 
-    /// Try to format special blocks (module, address, etc.)
-    fn try_format_special_block(
+    /// Format a top level token, breaking the line after an attribute
+    fn format_top_level_token(
         &self,
         token: &TokenTree,
+        next_token: Option<&TokenTree>,
+        new_line: bool,
+        pound_sign_idx: &mut Option<usize>,
         mut state: FormatState,
-    ) -> Option<FormatState> {
-        let TokenTree::Nested {
-            kind: nkind, note, ..
-        } = token
-        else {
-            return None;
+    ) -> FormatState {
+        state = self.format_token_trees_internal(token, next_token, new_line, state);
+        if new_line {
+            state = self.new_line(Some(token.end_pos()), state);
+            *pound_sign_idx = None;
+        }
+        state
+    }
+
+    /// The config in effect: the one of the innermost module or function body being
+    /// formatted if its attributes override some options, the global one otherwise
+    fn cfg(&self, state: &FormatState) -> Rc<Config> {
+        state
+            .item_cfg_stack
+            .last()
+            .cloned()
+            .unwrap_or_else(|| self.context.global_cfg.clone())
+    }
+
+    fn local_cfg(&self, state: &FormatState) -> FormatConfig {
+        FormatConfig::new(&self.cfg(state))
+    }
+
+    /// Runs the layout decision `f` on the output of `state`
+    fn with_layout<R>(&self, state: &FormatState, f: impl FnOnce(&LayoutCtx) -> R) -> R {
+        let cfg = self.cfg(state);
+        f(&LayoutCtx {
+            cfg: &cfg,
+            content: &self.context.content,
+            line_mapping: &self.context.line_mapping,
+            syntax_handler: &self.context.syntax_handler,
+            last_line: state.last_line(),
+            depth: state.depth,
+            pre_simple_token: &state.pre_simple_token,
+        })
+    }
+
+    /// The config of the module or function body `kind`, if its attributes
+    /// like `#[fmt::max_width = 120]` override some options of the config in effect
+    fn block_body_cfg(
+        &self,
+        kind: &NestKind,
+        note: &Option<Note>,
+        state: &FormatState,
+    ) -> Option<Config> {
+        self.context
+            .syntax_handler
+            .handler_immut::<SkipHandler>()
+            .block_body_config(kind, block_body_type(note), &self.cfg(state))
+    }
+
+    /// Process modules inside the address block
+    fn process_address_modules(&self, mut return_buf_cp: String, fmt_buf: &str) -> String {
+        let def_vec_result = parse_file_string(&mut get_compile_env(), FileHash::empty(), fmt_buf);
+        let def_vec = def_vec_result.unwrap_or_default().0;
+
+        let Some(Definition::Address(address_def)) = def_vec.first() else {
+            return_buf_cp.push_str(&fmt_buf[EXIST_MULTI_ADDRESS_TAG.len()..]);
+            return return_buf_cp;
         };
 
-        let skip_handler = self.context.syntax_handler.handler_immut::<SkipHandler>();
-        let is_mod_blk = skip_handler.is_module_block(nkind);
-        let is_addr_blk = note.map_or(false, |x| x == Note::ModuleAddress);
+        let mut last_mod_end_loc = 0;
+        let mut fmt_slice = String::new();
+        for mod_def in &address_def.modules {
+            let m = &fmt_buf[mod_def.loc.start() as usize..mod_def.loc.end() as usize];
+            let mod_cfg = item_config(&self.context.global_cfg, &mod_def.attributes);
+            let tuning_mod_body = tune_module_buf(
+                m.to_string(),
+                mod_cfg.as_ref().unwrap_or(&self.context.global_cfg),
+            );
+            fmt_slice.push_str(&fmt_buf[last_mod_end_loc..mod_def.loc.start() as usize]);
+            fmt_slice.push_str(&tuning_mod_body);
+            last_mod_end_loc = mod_def.loc.end() as usize;
+        }
+        fmt_slice.push_str(&fmt_buf[last_mod_end_loc..]);
 
-        if is_mod_blk {
-            state = state.push_str(EXIST_MULTI_MODULE_TAG);
-            state = self.format_token_with_state(token, None, false, state);
-            state = self.add_new_line_with_state(Some(token.end_pos()), state);
+        return_buf_cp.push_str(&fmt_slice[EXIST_MULTI_ADDRESS_TAG.len()..]);
+        return_buf_cp
+    }
 
-            if !skip_handler.has_skipped_module_body(nkind) {
-                let tuned = tune_module_buf(state.output.clone(), &self.context.global_cfg);
-                state.output = tuned;
-                state.output = update_last_line(state.output);
-            }
+    /// Finalize output cleanup
+    fn finalize_output(&self, mut state: FormatState) -> FormatState {
+        // Remove trailing whitespace
+        state.output = remove_trailing_whitespaces_util(state.output);
+        // Handle final blank lines
+        state.output = update_last_line(state.output);
+        state
+    }
 
-            // Remove the tag and return the processed state
-            if state.output.starts_with(EXIST_MULTI_MODULE_TAG) {
-                state.output = state.output[EXIST_MULTI_MODULE_TAG.len()..].to_string();
+    fn process_fn_header(&self, mut state: FormatState) -> FormatState {
+        let indent_str = " ".repeat((state.depth + 1) * self.local_cfg(&state).indent_size);
+        if let Some(last_fun_idx) = state.output.rfind("fun") {
+            let fun_header: &str = &state.output[last_fun_idx..];
+            if let Some(specifier_idx) = fun_header.rfind("fun") {
+                let fun_specifier_fmted_str = fun_fmt::fun_header_specifier_fmt(
+                    &fun_header[specifier_idx + 1..],
+                    &indent_str,
+                );
+                state.output.truncate(last_fun_idx + specifier_idx + 1);
+                state.output.push_str(&fun_specifier_fmted_str);
             }
-            Some(state)
-        } else if is_addr_blk {
-            state = state.push_str(EXIST_MULTI_ADDRESS_TAG);
-            state = self.format_token_with_state(token, None, false, state);
-            state = self.add_new_line_with_state(Some(token.end_pos()), state);
+        }
+        state
+    }
 
-            // Process modules inside the address block
-            state = self.process_address_modules(state);
-            Some(state)
-        } else {
-            None
+    fn top_half_after_kind_start(
+        &self,
+        kind: &NestKind,
+        elements: &[TokenTree],
+        b_new_line_mode: bool,
+        b_add_indent: bool,
+        b_add_space_around_brace: bool,
+        mut state: FormatState,
+    ) -> FormatState {
+        // step1 -- format start_token
+        state = self.format_token_trees_internal(
+            &kind.start_token_tree(),
+            None,
+            b_new_line_mode,
+            state,
+        );
+
+        // step2 -- paired effect with step6
+        if b_add_indent && b_new_line_mode {
+            state = state.inc_depth();
         }
+
+        // step3
+        if b_new_line_mode {
+            state = self.add_new_line_after_nested_begin(kind, elements, state);
+        } else if b_add_space_around_brace {
+            state = state.push_str(" ");
+        }
+        state
     }
 
-    /// Process modules inside the address block
-    fn process_address_modules(&self, mut state: FormatState) -> FormatState {
-        let def_vec_result =
-            parse_file_string(&mut get_compile_env(), FileHash::empty(), &state.output);
-
-        if let Ok((def_vec, _)) = def_vec_result {
-            if let Some(Definition::Address(address_def)) = def_vec.first() {
-                let mut last_mod_end_loc = 0;
-                let mut fmt_slice = String::new();
-
-                for mod_def in &address_def.modules {
-                    let m = &state.output[mod_def.loc.start() as usize..mod_def.loc.end() as usize];
-                    let tuning_mod_body = tune_module_buf(m.to_string(), &self.context.global_cfg);
-                    fmt_slice
-                        .push_str(&state.output[last_mod_end_loc..mod_def.loc.start() as usize]);
-                    fmt_slice.push_str(&tuning_mod_body);
-                    last_mod_end_loc = mod_def.loc.end() as usize;
-                }
+    fn bottom_half_before_kind_end(
+        &self,
+        kind: &NestKind,
+        b_new_line_mode: bool,
+        b_add_indent: bool,
+        b_add_space_around_brace: bool,
+        nested_token_head: Tok,
+        mut state: FormatState,
+    ) -> FormatState {
+        // step5 -- add_comments which before kind.end_pos
+        let end_token = kind.end_token_tree();
+        state = self.add_comments(
+            kind.end_pos,
+            end_token.simple_str().unwrap_or_default(),
+            state,
+        );
+        // may be already add_a_new_line in step5 by add_comments(doc_comment in tail of line)
+        let ret_copy = state.output.clone();
+        state.output = ret_copy.trim_end().to_string();
+        let had_rm_added_new_line = state.output.lines().count() < ret_copy.lines().count();
 
-                fmt_slice.push_str(&state.output[last_mod_end_loc..]);
+        // step6 -- paired effect with step2
+        if b_add_indent && b_new_line_mode {
+            state = state.dec_depth();
+        }
+        // step7
+        if b_new_line_mode || had_rm_added_new_line {
+            // for <> don't add new line
+            let mut b_break_line_before_kind_end =
+                nested_token_head != Tok::If && kind.kind != NestKind_::Type;
+            if contains_comment(state.last_line()) && state.last_line().contains("//") {
+                b_break_line_before_kind_end = true;
+            }
+            if b_break_line_before_kind_end {
+                state = self.new_line(Some(kind.end_pos), state);
+            }
+        } else if b_add_space_around_brace {
+            state = state.push_str(" ");
+        }
+        state
+    }
 
-                if fmt_slice.starts_with(EXIST_MULTI_ADDRESS_TAG) {
-                    state.output = fmt_slice[EXIST_MULTI_ADDRESS_TAG.len()..].to_string();
-                } else {
-                    state.output = fmt_slice;
-                }
+    fn add_new_line_after_nested_begin(
+        &self,
+        kind: &NestKind,
+        elements: &[TokenTree],
+        mut state: FormatState,
+    ) -> FormatState {
+        if let Some(first) = elements.first() {
+            if self.translate_line(first.start_pos()) > self.translate_line(kind.start_pos) {
+                state = self.process_same_line_comment(kind.start_pos, true, state);
+                return self.new_line(None, state);
             }
         }
+        self.new_line(Some(kind.start_pos), state)
+    }
 
+    fn format_single_token(
+        &self,
+        nested_token: &TokenTree,
+        internal_token_idx: usize,
+        pound_sign_new_line: bool,
+        new_line: bool,
+        pound_sign: &mut Option<usize>,
+        mut state: FormatState,
+    ) -> FormatState {
+        let TokenTree::Nested { elements, .. } = nested_token else {
+            return state;
+        };
+        let token = elements.get(internal_token_idx).unwrap();
+        let next_t = elements.get(internal_token_idx + 1);
+
+        state =
+            self.format_token_trees_internal(token, next_t, pound_sign_new_line || new_line, state);
+
+        if pound_sign_new_line {
+            *pound_sign = None;
+            return self.new_line(Some(token.end_pos()), state);
+        }
+
+        if new_line {
+            let process_tail_comment_of_line = match next_t {
+                Some(next_token) => {
+                    self.translate_line(next_token.start_pos())
+                        > self.translate_line(token.end_pos())
+                }
+                None => {
+                    let remain_code_str = &self.context.content[token.end_pos() as usize..];
+                    let remain_code_first_word = remain_code_str
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default();
+                    remain_code_first_word.starts_with("//")
+                        || remain_code_first_word.starts_with("/*")
+                }
+            };
+            state = self.process_same_line_comment(
+                token.end_pos(),
+                process_tail_comment_of_line,
+                state,
+            );
+            state = self.new_line(None, state);
+        }
         state
     }
 
-    /// Format a single token
-    fn format_token_with_state(
+    fn format_each_token_in_nested_elements(
         &self,
-        token: &TokenTree,
-        next_token: Option<&TokenTree>,
-        new_line_after: bool,
-        state: FormatState,
+        nested_token: &TokenTree,
+        delimiter: Option<Delimiter>,
+        component_break_mode: bool,
+        mut state: FormatState,
     ) -> FormatState {
-        match token {
-            TokenTree::Nested { .. } => {
-                self.format_nested_token_with_state(token, next_token, state)
+        let TokenTree::Nested { elements, kind, .. } = nested_token else {
+            return state;
+        };
+        let call_handler = self.context.syntax_handler.handler_immut::<CallHandler>();
+        let nested_kind_len = self.get_kind_len_after_trim_space(*kind, false);
+        let old_kind = state.cur_nested_kind;
+        state = state.set_nested_kind(*kind);
+        let mut pound_sign = None;
+        let len = elements.len();
+        let mut internal_token_idx = 0;
+
+        let is_call = kind.kind == NestKind_::ParentTheses && call_handler.paren_in_call(kind);
+        while internal_token_idx < len {
+            let pound_sign_new_line = pound_sign
+                .map(|x| (x + 1) == internal_token_idx)
+                .unwrap_or_default();
+
+            let cur_token_tree = elements.get(internal_token_idx).unwrap();
+            let mut new_line = self.with_layout(&state, |ctx| {
+                need_new_line_for_cur_tok_finished(
+                    ctx,
+                    nested_token,
+                    delimiter,
+                    internal_token_idx,
+                    component_break_mode,
+                    nested_kind_len,
+                )
+            });
+            if is_call {
+                new_line |= component_break_mode
+                    && call_handler.should_call_component_split(
+                        self.cfg(&state).as_ref().clone(),
+                        kind,
+                        elements,
+                        internal_token_idx,
+                        state.cur_line_len(),
+                    );
             }
-            TokenTree::SimpleToken { .. } => {
-                self.format_simple_token_with_state(token, next_token, new_line_after, state)
+
+            if internal_token_idx == len - 1
+                && cur_token_tree.simple_str().unwrap_or_default() == ","
+            {
+                internal_token_idx += 1;
+                continue;
+            }
+
+            if cur_token_tree.is_pound() {
+                pound_sign = Some(internal_token_idx)
             }
+
+            if Tok::Period == state.get_pre_simple_tok() {
+                let in_link_access =
+                    expr_fmt::process_link_access(elements, internal_token_idx + 1);
+                let mut last_dot_idx = in_link_access.1;
+                let mut need_process_link =
+                    in_link_access.0 > 3 && last_dot_idx > internal_token_idx;
+                if !need_process_link {
+                    let in_link_call =
+                        call_handler.is_in_link_call(elements, internal_token_idx + 1);
+                    last_dot_idx = in_link_call.1;
+                    need_process_link = in_link_call.0 && last_dot_idx > internal_token_idx;
+                }
+
+                if need_process_link {
+                    state = state.inc_depth();
+                    while internal_token_idx <= last_dot_idx + 1 {
+                        let is_dot_new_line =
+                            elements.get(internal_token_idx + 1).is_some_and(|next_t| {
+                                next_t.simple_str().unwrap_or_default().contains('.')
+                            });
+                        state = self.format_single_token(
+                            nested_token,
+                            internal_token_idx,
+                            false,
+                            is_dot_new_line,
+                            &mut pound_sign,
+                            state,
+                        );
+                        internal_token_idx += 1;
+                    }
+                    state = state.dec_depth();
+                    continue;
+                }
+            }
+
+            state = self.format_single_token(
+                nested_token,
+                internal_token_idx,
+                pound_sign_new_line,
+                new_line,
+                &mut pound_sign,
+                state,
+            );
+            internal_token_idx += 1;
         }
+
+        state.set_nested_kind(old_kind)
+    }
+
+    fn should_skip_nested_token(&self, kind: &NestKind, note: &Option<Note>) -> bool {
+        self.context
+            .syntax_handler
+            .handler_immut::<SkipHandler>()
+            .should_skip_block_body(kind, block_body_type(note))
+    }
+
+    fn skip_nested_token_with_state(&self, kind: &NestKind, mut state: FormatState) -> FormatState {
+        let blk_body_str = &self.context.content[kind.start_pos as usize..=kind.end_pos as usize];
+        state = state.push_str(blk_body_str);
+
+        let skipped_comments = self.context.comments[state.comments_index..]
+            .iter()
+            .take_while(|c| c.start_offset <= kind.end_pos)
+            .count();
+        state = state.advance_comments_index(skipped_comments);
+        state.set_cur_line(self.translate_line(kind.end_pos))
     }
 
     /// Format a nested token
-    fn format_nested_token_with_state(
+    fn format_nested_token(
         &self,
-        token: &TokenTree,
+        nested_token: &TokenTree,
         next_token: Option<&TokenTree>,
-        state: FormatState,
+        mut state: FormatState,
     ) -> FormatState {
         let TokenTree::Nested {
             elements,
             kind,
             note,
-        } = token
+        } = nested_token
         else {
             return state;
         };
-
-        // Check if token should be skipped
         if self.should_skip_nested_token(kind, note) {
-            return self.skip_nested_token_with_state(token, state);
+            return self.skip_nested_token_with_state(kind, state);
+        }
+        let item_cfg = self.block_body_cfg(kind, note, &state).map(Rc::new);
+        if let Some(item_cfg) = &item_cfg {
+            state = state.push_item_cfg(item_cfg.clone());
         }
 
-        let (delimiter, has_colon) = analyze_token_tree_delimiter(elements);
-        let (break_mode, component_break_mode) =
-            self.get_break_mode_begin_nested(token, delimiter, &state);
+        let (delimiter, _) = analyze_token_tree_delimiter(elements);
+        if note.is_some_and(|x| x == Note::FunBody) {
+            state = self.process_fn_header(state);
+        }
+        let (mut b_new_line_mode, opt_component_break_mode) = self.with_layout(&state, |ctx| {
+            get_break_mode_begin_nested(ctx, nested_token, delimiter)
+        });
+
+        let mut b_add_indent = true;
+        for (i, ele) in elements.iter().enumerate() {
+            let ele_str = ele.simple_str().unwrap_or_default();
+            if !matches!(ele_str, "#" | "" | "module") || i > MIN_NESTED_LENGTH {
+                break;
+            }
+            if ele_str == "module" {
+                b_add_indent = false;
+                b_new_line_mode = true;
+                break;
+            }
+        }
 
-        // Process the start part
-        let mut state = self.format_nested_start_with_state(kind, elements, break_mode, state);
+        let nested_token_head = state.get_pre_simple_tok();
+        let b_add_space_around_brace = self.with_layout(&state, |ctx| {
+            judge_add_space_around_brace(ctx, nested_token, b_new_line_mode)
+        });
 
-        // Format inner elements
-        state = self.format_nested_elements_with_state(
-            token,
-            delimiter,
-            has_colon,
-            component_break_mode.unwrap_or(break_mode),
+        // step1-step3
+        state = self.top_half_after_kind_start(
+            kind,
+            elements,
+            b_new_line_mode,
+            b_add_indent,
+            b_add_space_around_brace,
             state,
         );
 
-        // Process the end part
-        state = self.format_nested_end_with_state(kind, break_mode, state);
+        // step4 -- format element
+        state = self.format_each_token_in_nested_elements(
+            nested_token,
+            delimiter,
+            opt_component_break_mode.unwrap_or(b_new_line_mode),
+            state,
+        );
 
-        // Format the end token
-        state = self.format_token_with_state(&kind.end_token_tree(), None, false, state);
+        // step5-step7
+        state = self.bottom_half_before_kind_end(
+            kind,
+            b_new_line_mode,
+            b_add_indent,
+            b_add_space_around_brace,
+            nested_token_head,
+            state,
+        );
+        if item_cfg.is_some() {
+            state = state.pop_item_cfg();
+        }
 
-        // Add necessary space
-        if expr_fmt::need_space(token, next_token) {
-            if state.get_pre_simple_tok() == Tok::NumSign && kind.kind == NestKind_::Bracket {
+        // step8 -- format end_token
+        state = self.format_token_trees_internal(&kind.end_token_tree(), None, false, state);
+        if expr_fmt::need_space(nested_token, next_token) {
+            if nested_token_head == Tok::NumSign && kind.kind == NestKind_::Bracket {
                 return state;
             }
             state = state.push_str(" ");
         }
-
         state
     }
 
-    /// Format a simple token
-    fn format_simple_token_with_state(
+    fn maybe_begin_of_if_else(
         &self,
         token: &TokenTree,
         next_token: Option<&TokenTree>,
-        new_line_after: bool,
         mut state: FormatState,
     ) -> FormatState {
+        if state.cur_nested_kind.kind == NestKind_::Bracket {
+            return state;
+        }
         let TokenTree::SimpleToken {
-            content,
-            pos,
-            tok: _,
-            note: _,
+            content, pos, tok, ..
         } = token
         else {
             return state;
         };
 
-        // Handle branch logic
-        state = self.handle_branch_logic_with_state(token, next_token, state);
-
-        // Add comments
-        state = self.add_comments_with_state(*pos, content.clone(), state);
-
-        // Process blank lines
-        state = self.process_blank_lines_with_state(token, state);
-
-        // Format token core logic
-        state = self.format_simple_token_core_with_state(token, next_token, new_line_after, state);
-
-        // Handle branch end logic
-        state = self.handle_branch_end_logic_with_state(token, next_token, state);
-
-        // Update previous token
-        state = state.set_pre_token(token.clone());
-
-        state
-    }
-
-    /// Add a new line
-    fn add_new_line_with_state(
-        &self,
-        comment_pos: Option<u32>,
-        mut state: FormatState,
-    ) -> FormatState {
-        if let Some(pos) = comment_pos {
-            state = self.process_same_line_comment_with_state(pos, false, state);
+        let cfg = self.cfg(&state);
+        let branch_handler = self.context.syntax_handler.handler_immut::<BranchHandler>();
+        let pre_tok = state.get_pre_simple_tok();
+        if !matches!(pre_tok, Tok::RParen | Tok::Else) && *tok != Tok::Else {
+            return state;
         }
 
-        state = state.push_str("\n");
-
-        // Add indentation
-        let indent = " ".repeat(state.depth * self.context.local_cfg.indent_size);
-        state = state.push_str(&indent);
-
-        state
-    }
-
-    /// Add comments
-    fn add_comments_with_state(
-        &self,
-        pos: u32,
-        content: String,
-        mut state: FormatState,
-    ) -> FormatState {
-        let mut comments_processed = 0;
-
-        for comment in &self.context.comments[state.comments_index..] {
-            if comment.start_offset > pos {
-                break;
+        let end_pos_of_if_cond_or_else = state.pre_simple_token.end_pos();
+        if Tok::LBrace != *tok
+            && content != "for"
+            && branch_handler.need_new_line_after_branch(
+                state.last_line().to_string(),
+                *pos,
+                cfg.as_ref().clone(),
+                end_pos_of_if_cond_or_else,
+            )
+        {
+            state = state.inc_depth();
+            if state.last_line().trim_start().is_empty() {
+                // maybe already added new line because of judge_cond() is a long nested expr
+                let indent_str = " ".repeat(self.local_cfg(&state).indent_size);
+                return state.push_str(&indent_str);
             }
+            return self.new_line(None, state);
+        }
 
-            let comment_line = self.translate_line(comment.start_offset);
-
-            // Handle new lines before the comment
-            if (comment_line - state.cur_line) > 1 {
-                if state.get_pre_simple_tok() != Tok::NumSign {
-                    state = self.add_new_line_with_state(None, state);
+        let mut new_line_before_else = false;
+        if *tok == Tok::Else {
+            let has_special_key = state.cur_line_len() != state.last_line().len();
+            if state.get_pre_simple_tok() == Tok::RBrace {
+                // process case:
+                // else if() {} `insert '\n' here` else
+                new_line_before_else = has_special_key;
+            } else if let Some(next_token) = next_token {
+                let last_line_len = state.last_line().len();
+                if last_line_len + content.len() + 2 + next_token.token_len() as usize
+                    > cfg.max_width() - MIN_NESTED_LENGTH
+                {
+                    new_line_before_else = true;
                 }
-            }
-
-            if (comment_line - state.cur_line) == 1 {
-                let trimmed = state.output.trim_end().to_string();
-                state.output = trimmed;
-                state = self.add_new_line_with_state(None, state);
-            }
 
-            // Add necessary spaces
-            if self.no_space_or_new_line_for_comment(&state.output) {
-                state = state.push_str(" ");
-            }
-
-            // Format and add the comment
-            let formatted_comment = comment.format_comment(
-                comment.comment_kind(),
-                state.depth * self.context.local_cfg.indent_size,
-                0,
-                &self.context.global_cfg,
-            );
-            state = state.push_str(&formatted_comment);
-
-            // Handle new lines after the comment
-            match comment.comment_kind() {
-                CommentKind::DocComment => {
-                    state = self.add_new_line_with_state(None, state);
+                if branch_handler.else_branch_too_long(
+                    state.last_line().to_string(),
+                    next_token.start_pos() as ByteIndex,
+                    cfg.as_ref().clone(),
+                ) {
+                    new_line_before_else = true;
                 }
-                _ => {
-                    let end = comment.start_offset + (comment.content.len() as u32);
-                    let line_start = comment_line;
-                    let line_end = self.translate_line(end);
 
-                    if line_start != line_end {
-                        state = self.add_new_line_with_state(None, state);
-                    } else if !matches!(content.as_str(), ")" | "," | ";") {
-                        state = state.push_str(" ");
-                    }
+                // process `else if`
+                let is_in_nested_else_branch = branch_handler.is_nested_within_an_outer_else(*pos);
+                if next_token.simple_str().unwrap_or_default() == "if" || is_in_nested_else_branch {
+                    new_line_before_else = true;
                 }
             }
-
-            comments_processed += 1;
-            state = state.set_cur_line(
-                self.translate_line(comment.start_offset + (comment.content.len() as u32) - 1),
-            );
         }
-
-        state = state.advance_comments_index(comments_processed);
+        if new_line_before_else {
+            state = self.new_line(None, state);
+        }
         state
     }
 
-    /// Add remaining comments
-    fn add_remaining_comments_with_state(&self, state: FormatState) -> FormatState {
-        self.add_comments_with_state(u32::MAX, "end_of_move_file".to_string(), state)
-    }
+    fn maybe_end_of_if_else(
+        &self,
+        token: &TokenTree,
+        next_token: Option<&TokenTree>,
+        mut state: FormatState,
+    ) -> FormatState {
+        let TokenTree::SimpleToken { content, pos, .. } = token else {
+            return state;
+        };
+        if state.cur_nested_kind.kind == NestKind_::Bracket {
+            return state;
+        }
+        let tok_end_pos = *pos + content.len() as u32;
+        let nested_branch_depth = self
+            .context
+            .syntax_handler
+            .handler_immut::<BranchHandler>()
+            .added_new_line_after_branch(tok_end_pos);
+        for _ in 0..nested_branch_depth {
+            state = state.dec_depth();
+        }
 
-    /// Finalize output cleanup
-    fn finalize_output(&self, mut state: FormatState) -> FormatState {
-        // Remove trailing whitespace
-        state.output = remove_trailing_whitespaces_util(state.output);
-        // Handle final blank lines
-        state.output = update_last_line(state.output);
+        if nested_branch_depth > 0
+            && next_token.is_some_and(|x| x.simple_str().unwrap_or_default() != ";")
+        {
+            state = self.new_line(None, state);
+        }
         state
     }
 
-    // Stub implementations of helper methods - these need to be fully implemented based on original code
-    fn should_skip_nested_token(&self, _kind: &NestKind, _note: &Option<Note>) -> bool {
-        false // TODO
-    }
-
-    fn skip_nested_token_with_state(
+    fn process_blank_lines_before_simple_token(
         &self,
         token: &TokenTree,
         mut state: FormatState,
     ) -> FormatState {
-        if let TokenTree::Nested { kind, .. } = token {
-            let blk_body_str =
-                &self.context.content[kind.start_pos as usize..=kind.end_pos as usize];
-            state = state.push_str(blk_body_str);
-            state = state.set_cur_line(self.translate_line(kind.end_pos));
+        let TokenTree::SimpleToken { pos, tok, .. } = token else {
+            return state;
+        };
+        if (self.translate_line(*pos) - state.cur_line) > 1
+            && expr_fmt::need_newline_when_trim_blank_line(&state.get_pre_simple_tok(), tok)
+            && !expr_fmt::drop_blank_lines_after(&state.get_pre_simple_tok(), &self.cfg(&state))
+        {
+            // There are multiple blank lines between the cur_line and the current code simple_token
+            state = self.new_line(None, state);
         }
         state
     }
 
-    fn get_break_mode_begin_nested(
-        &self,
-        _token: &TokenTree,
-        _delimiter: Option<Delimiter>,
-        _state: &FormatState,
-    ) -> (bool, Option<bool>) {
-        (false, None) // TODO
-    }
-
-    fn format_nested_start_with_state(
+    fn fmt_simple_token_core(
         &self,
-        kind: &NestKind,
-        _elements: &[TokenTree],
-        break_mode: bool,
+        token: &TokenTree,
+        next_token: Option<&TokenTree>,
+        new_line_after: bool,
         mut state: FormatState,
     ) -> FormatState {
-        // Format start token
-        state = self.format_token_with_state(&kind.start_token_tree(), None, break_mode, state);
+        let TokenTree::SimpleToken {
+            content,
+            pos,
+            tok,
+            note,
+        } = token
+        else {
+            return state;
+        };
+        let indent_size = self.local_cfg(&state).indent_size;
+
+        let not_break_special_tok =
+            *tok == Tok::NumTypedValue && content.len() > MAX_ANALYZE_LENGTH;
+        let last_line_len_after_trim_leading_space = state
+            .last_line()
+            .trim_start_matches(char::is_whitespace)
+            .len();
+        let mut leading_space_cnt =
+            state.last_line().len() - last_line_len_after_trim_leading_space;
+        if leading_space_cnt > indent_size && leading_space_cnt % 2 == 1 {
+            leading_space_cnt -= 1;
+            let remove_idx =
+                state.output.len() - last_line_len_after_trim_leading_space - indent_size;
+            state.output.remove(remove_idx);
+        }
+        let mut split_line_after_content = false;
+        if !not_break_special_tok
+            && last_line_len_after_trim_leading_space > 0
+            && self.with_layout(&state, |ctx| {
+                judge_change_new_line_when_over_limits(ctx, content, *tok, *note, next_token)
+            })
+        {
+            let mut new_line_after_equal = false;
+            if matches!(
+                *tok,
+                Tok::Equal | Tok::EqualEqual | Tok::EqualEqualGreater | Tok::LessEqualEqualGreater
+            ) {
+                state = state.push_str(content);
+                split_line_after_content = true;
+                new_line_after_equal = new_line_after;
+            }
+            if !new_line_after_equal {
+                let need_inc_depth = !matches!(
+                    state.cur_nested_kind.kind,
+                    NestKind_::Bracket | NestKind_::ParentTheses
+                );
+                if need_inc_depth && leading_space_cnt + indent_size != state.depth * indent_size {
+                    state = self.new_line(None, state.inc_depth()).dec_depth();
+                } else {
+                    state = self.new_line(None, state);
+                }
+            }
+        }
 
-        if break_mode {
-            state = state.inc_depth();
-            state = self.add_new_line_with_state(Some(kind.start_pos), state);
+        if !split_line_after_content {
+            state = state.push_str(content);
         }
 
+        state = state.set_cur_line(self.translate_line(*pos));
+        if !new_line_after && expr_fmt::need_space(token, next_token) {
+            state = state.push_str(" ");
+        }
         state
     }
 
-    fn format_nested_elements_with_state(
+    /// Format a simple token
+    fn format_simple_token(
         &self,
-        _token: &TokenTree,
-        _delimiter: Option<Delimiter>,
-        _has_colon: bool,
-        _component_break_mode: bool,
-        state: FormatState,
+        token: &TokenTree,
+        next_token: Option<&TokenTree>,
+        new_line_after: bool,
+        mut state: FormatState,
     ) -> FormatState {
-        // TODO
-        state
+        let TokenTree::SimpleToken { content, pos, .. } = token else {
+            return state;
+        };
+        // step1
+        state = self.maybe_begin_of_if_else(token, next_token, state);
+
+        // step2: add comment(xxx) before current simple_token
+        state = self.add_comments(*pos, content, state);
+
+        // step3
+        state = self.process_blank_lines_before_simple_token(token, state);
+
+        // step4
+        state = self.fmt_simple_token_core(token, next_token, new_line_after, state);
+
+        // step5
+        state = self.maybe_end_of_if_else(token, next_token, state);
+
+        // step6
+        state.set_pre_token(token.clone())
     }
 
-    fn format_nested_end_with_state(
+    fn need_inc_depth_when_cur_is_nested(
         &self,
-        _kind: &NestKind,
-        break_mode: bool,
-        mut state: FormatState,
+        next_token: Option<&TokenTree>,
+        new_line_after: bool,
+        state: FormatState,
     ) -> FormatState {
-        if break_mode {
-            state = state.dec_depth();
-            state = self.add_new_line_with_state(None, state);
+        let Some(next_token) = next_token.filter(|_| new_line_after) else {
+            return state;
+        };
+        let syntax_handler = &self.context.syntax_handler;
+        if syntax_handler
+            .handler_immut::<BinOpHandler>()
+            .need_inc_depth_by_long_op(next_token.clone())
+            || syntax_handler
+                .handler_immut::<LetHandler>()
+                .need_inc_depth_by_long_op(next_token.clone())
+        {
+            return state.inc_depth();
         }
         state
     }
 
-    fn handle_branch_logic_with_state(
+    fn need_inc_depth_when_cur_is_simple(
         &self,
-        _token: &TokenTree,
-        _next_token: Option<&TokenTree>,
+        token: &TokenTree,
+        next_token: Option<&TokenTree>,
+        new_line_after: bool,
         state: FormatState,
     ) -> FormatState {
-        // TODO
+        let Some(next_token) = next_token.filter(|_| new_line_after) else {
+            return state;
+        };
+        let syntax_handler = &self.context.syntax_handler;
+        let bin_op_handler = syntax_handler.handler_immut::<BinOpHandler>();
+        let is_cur_tok_bin_op = is_bin_op(token.get_end_tok());
+        let is_next_tok_bin_op = is_bin_op(next_token.get_start_tok());
+        if (is_cur_tok_bin_op && bin_op_handler.need_inc_depth_by_long_op(token.clone()))
+            || bin_op_handler.need_inc_depth_by_long_op(next_token.clone())
+        {
+            return state.inc_depth();
+        }
+
+        let let_handler = syntax_handler.handler_immut::<LetHandler>();
+        if let_handler.need_inc_depth_by_long_op(token.clone())
+            || (is_next_tok_bin_op && let_handler.need_inc_depth_by_long_op(next_token.clone()))
+        {
+            return state.inc_depth();
+        }
+
+        if syntax_handler
+            .handler_immut::<QuantHandler>()
+            .need_inc_depth_by_long_quant_exp(next_token.clone())
+        {
+            return state.inc_depth();
+        }
         state
     }
 
-    fn process_blank_lines_with_state(
+    fn need_dec_depth_when_cur_is_simple(
         &self,
-        _token: &TokenTree,
-        state: FormatState,
+        token: &TokenTree,
+        mut state: FormatState,
     ) -> FormatState {
-        // TODO
+        let syntax_handler = &self.context.syntax_handler;
+        let nested_break_line_depth = syntax_handler
+            .handler_immut::<BinOpHandler>()
+            .need_dec_depth_by_long_op(token.clone())
+            + syntax_handler
+                .handler_immut::<LetHandler>()
+                .need_dec_depth_by_long_op(token.clone())
+            + syntax_handler
+                .handler_immut::<QuantHandler>()
+                .need_dec_depth_by_long_quant_exp(token.clone());
+        for _ in 0..nested_break_line_depth {
+            state = state.dec_depth();
+        }
         state
     }
 
-    fn format_simple_token_core_with_state(
+    fn format_token_trees_internal(
         &self,
         token: &TokenTree,
         next_token: Option<&TokenTree>,
         new_line_after: bool,
         mut state: FormatState,
     ) -> FormatState {
-        let TokenTree::SimpleToken { content, pos, .. } = token else {
-            return state;
-        };
+        match token {
+            TokenTree::Nested { .. } => {
+                state = self.format_nested_token(token, next_token, state);
+                self.need_inc_depth_when_cur_is_nested(next_token, new_line_after, state)
+            }
+            TokenTree::SimpleToken { .. } => {
+                state = self.format_simple_token(token, next_token, new_line_after, state);
+                state = self.need_inc_depth_when_cur_is_simple(
+                    token,
+                    next_token,
+                    new_line_after,
+                    state,
+                );
+                self.need_dec_depth_when_cur_is_simple(token, state)
+            }
+        }
+    }
 
-        state = state.push_str(content);
-        state = state.set_cur_line(self.translate_line(*pos));
+    /// Add comments located before `pos`
+    fn add_comments(&self, pos: u32, content: &str, mut state: FormatState) -> FormatState {
+        let mut comment_nums_before_cur_simple_token = 0;
+        let mut last_cmt_is_block_cmt = false;
+        let mut last_cmt_start_pos = 0;
+        for c in &self.context.comments[state.comments_index..] {
+            if c.start_offset > pos {
+                break;
+            }
 
-        if !new_line_after && expr_fmt::need_space(token, next_token) {
-            state = state.push_str(" ");
-        }
+            let this_cmt_start_line = self.translate_line(c.start_offset);
+            if (this_cmt_start_line - state.cur_line) > 1
+                && state.get_pre_simple_tok() != Tok::NumSign
+                && !expr_fmt::drop_blank_lines_after(&state.get_pre_simple_tok(), &self.cfg(&state))
+            {
+                state = self.new_line(None, state);
+            }
+
+            if (this_cmt_start_line - state.cur_line) == 1 {
+                // if located after nestedToken start, maybe already chanedLine
+                state.output = state.output.trim_end().to_string();
+                state = self.new_line(None, state);
+            }
+
+            if no_space_or_new_line_for_comment(&state.output) {
+                state = state.push_str(" ");
+            }
+
+            let formatted_comment = c.format_comment(
+                c.comment_kind(),
+                state.depth * self.local_cfg(&state).indent_size,
+                0,
+                &self.cfg(&state),
+            );
+            state = state.push_str(&formatted_comment);
 
+            match c.comment_kind() {
+                CommentKind::DocComment => {
+                    state = self.new_line(None, state);
+                    last_cmt_is_block_cmt = false;
+                }
+                _ => {
+                    let end = c.start_offset + (c.content.len() as u32);
+                    if this_cmt_start_line != self.translate_line(end) {
+                        state = self.new_line(None, state);
+                    } else if !matches!(content, ")" | "," | ";") {
+                        state = state.push_str(" ");
+                    }
+                    last_cmt_is_block_cmt = true;
+                }
+            }
+            state = state
+                .advance_comments_index(1)
+                .set_cur_line(self.translate_line(c.start_offset + (c.content.len() as u32) - 1));
+            comment_nums_before_cur_simple_token += 1;
+            last_cmt_start_pos = c.start_offset;
+        }
+        if comment_nums_before_cur_simple_token > 0
+            && last_cmt_is_block_cmt
+            && self.translate_line(pos) - self.translate_line(last_cmt_start_pos) == 1
+        {
+            // process this case:
+            // line[i]: /*comment1*/ /*comment2*/
+            // line[i+1]: code // located in `pos`
+            if state.output.ends_with(' ') {
+                state.output.pop();
+            }
+            state.output = state.output.trim_end().to_string();
+            state = self.new_line(None, state);
+        }
         state
     }
 
-    fn handle_branch_end_logic_with_state(
+    fn process_same_line_comment(
         &self,
-        _token: &TokenTree,
-        _next_token: Option<&TokenTree>,
-        state: FormatState,
+        add_line_comment_pos: u32,
+        process_tail_comment_of_line: bool,
+        mut state: FormatState,
     ) -> FormatState {
-        // TODO
+        for c in &self.context.comments[state.comments_index..] {
+            if !process_tail_comment_of_line && c.start_offset > add_line_comment_pos {
+                break;
+            }
+
+            if self.translate_line(add_line_comment_pos) != self.translate_line(c.start_offset) {
+                break;
+            }
+
+            let kind = c.comment_kind();
+            let fmted_cmt_str = c.format_comment(
+                kind,
+                state.depth * self.local_cfg(&state).indent_size,
+                0,
+                &self.cfg(&state),
+            );
+            if no_space_or_new_line_for_comment(&state.output) {
+                state = state.push_str(" ");
+            }
+
+            state = state
+                .push_str(&fmted_cmt_str)
+                .advance_comments_index(1)
+                .set_cur_line(self.translate_line(c.start_offset + (c.content.len() as u32) - 1));
+
+            if let CommentKind::BlockComment = kind {
+                let end = c.start_offset + (c.content.len() as u32);
+                if self.translate_line(c.start_offset) != self.translate_line(end) {
+                    return self.new_line(None, state);
+                }
+            }
+        }
         state
     }
 
-    fn process_same_line_comment_with_state(
+    /// Add a new line, flushing the comments on the same line as `add_line_comment_option` first
+    fn new_line(
         &self,
-        _pos: u32,
-        _process_tail: bool,
-        state: FormatState,
+        add_line_comment_option: Option<u32>,
+        mut state: FormatState,
     ) -> FormatState {
-        // TODO
-        state
+        if let Some(add_line_comment) = add_line_comment_option {
+            state = self.process_same_line_comment(add_line_comment, false, state);
+        }
+        state = state.push_str("\n");
+        self.indent(state)
     }
 
-    // Helper method
-    fn translate_line(&self, pos: u32) -> u32 {
-        self.context
-            .line_mapping
-            .translate(pos, pos)
-            .unwrap_or_default()
-            .start
-            .line
+    fn indent(&self, state: FormatState) -> FormatState {
+        let indent_str = " ".repeat(state.depth * self.local_cfg(&state).indent_size);
+        state.push_str(&indent_str)
     }
 
-    fn no_space_or_new_line_for_comment(&self, output: &str) -> bool {
-        if let Some(last_char) = output.chars().last() {
-            !matches!(last_char, '\n' | ' ' | '(')
-        } else {
-            false
-        }
+    fn get_kind_len_after_trim_space(&self, kind: NestKind, join_by_space: bool) -> usize {
+        get_kind_len_after_trim_space(&self.context.content, kind, join_by_space)
     }
-}
 
-/// Helper function - extracted from the original code
-fn tune_module_buf(module_body: String, config: &Config) -> String {
-    let mut ret_module_body = fun_fmt::fmt_fun(module_body.clone(), config.clone());
-    if module_body.contains("spec ") {
-        ret_module_body = spec_fmt::fmt_spec(ret_module_body.clone(), config.clone());
+    // Helper method
+    fn translate_line(&self, pos: u32) -> u32 {
+        translate_line(&self.context.line_mapping, pos)
     }
-    ret_module_body = big_block_fmt::fmt_big_block(ret_module_body);
-    remove_trailing_whitespaces_util(ret_module_body)
 }
 
 /// Provide a simple wrapper for backward compatibility
@@ -696,10 +1241,24 @@ impl FunctionalFormat {
 pub fn format_entry_functional(
     content: impl AsRef<str>,
     config: Config,
-) -> Result<String, Diagnostics> {
-    let content = content.as_ref();
-    let format = FunctionalFormat::new(config, content)?;
-    Ok(format.format_token_trees())
+) -> Result<String, EngineError> {
+    format_entry_functional_timed(content.as_ref(), &config).map(|(result, _)| result)
+}
+
+/// Same as `format_entry_functional`, also returning the timer.
+pub(crate) fn format_entry_functional_timed(
+    content: &str,
+    config: &Config,
+) -> Result<(String, Timer), EngineError> {
+    let timer = Timer::start();
+    let format = FunctionalFormat::new(layout_config(config), content)?;
+    let timer = timer.done_parsing();
+    let result = apply_hard_tabs(format.format_token_trees(), config);
+    let result = keep_skipped_code(content, result)?;
+    if config.verify_formatting() {
+        verify_formatting(content, &result)?;
+    }
+    Ok((result, timer.done_formatting()))
 }
 
 #[cfg(test)]
@@ -0,0 +1,320 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::core::fmt::EngineError;
+use crate::core::format_with_engine;
+use crate::core::token_tree::CommentExtrator;
+use crate::syntax_fmt::skip_fmt::{item_max_widths, verbatim_ranges};
+use commentfmt::{Config, ErrorKind};
+use move_command_line_common::files::FileHash;
+use move_compiler::parser::lexer::{Lexer, Tok};
+use serde::Serialize;
+
+/// A line of the formatted result wider than `max_width`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LineOverflow {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column of the first character beyond `max_width`.
+    pub col: usize,
+    /// Width of the line, a tab counts as `tab_spaces`.
+    pub width: usize,
+    pub max_width: usize,
+}
+
+impl LineOverflow {
+    pub fn message(&self) -> String {
+        ErrorKind::LineOverflow(self.width, self.max_width).to_string()
+    }
+}
+
+/// Check that `formatted` keeps every token and every comment of `origin`,
+/// so that a formatter bug never makes it to the user's file.
+/// Commas are not compared since the formatter may add or remove trailing commas,
+/// and whitespace (as well as the `*` decorations of block comments) doesn't matter.
+/// A token mismatch is reported as `LostToken`, a comment mismatch as `LostComment`.
+pub fn verify_formatting(origin: &str, formatted: &str) -> Result<(), ErrorKind> {
+    let origin_tokens = lex_tokens(origin).map_err(|offset| lex_error("input", origin, offset))?;
+    let formatted_tokens =
+        lex_tokens(formatted).map_err(|offset| lex_error("formatted result", formatted, offset))?;
+    for (origin_token, formatted_token) in origin_tokens.iter().zip(&formatted_tokens) {
+        if origin_token.1 != formatted_token.1 {
+            return Err(ErrorKind::LostToken(format!(
+                "`{}` at line {} became `{}` at line {}",
+                origin_token.1,
+                line_of(origin, origin_token.0),
+                formatted_token.1,
+                line_of(formatted, formatted_token.0)
+            )));
+        }
+    }
+    if origin_tokens.len() != formatted_tokens.len() {
+        let (longer, content) = if origin_tokens.len() > formatted_tokens.len() {
+            (&origin_tokens, origin)
+        } else {
+            (&formatted_tokens, formatted)
+        };
+        let extra = &longer[origin_tokens.len().min(formatted_tokens.len())];
+        return Err(ErrorKind::LostToken(format!(
+            "{} tokens before formatting but {} after, first difference: `{}` at line {}",
+            origin_tokens.len(),
+            formatted_tokens.len(),
+            extra.1,
+            line_of(content, extra.0)
+        )));
+    }
+
+    let origin_comments = normalized_comments(origin);
+    let formatted_comments = normalized_comments(formatted);
+    if origin_comments != formatted_comments {
+        tracing::warn!(
+            "{} comments before formatting but {} after",
+            origin_comments.len(),
+            formatted_comments.len()
+        );
+        return Err(ErrorKind::LostComment);
+    }
+    Ok(())
+}
+
+/// Format `formatted`, the result of a first formatting pass, once more with the same
+/// config. Returns the result of the second pass if it differs from the first one,
+/// i.e. if formatting is not a fixed point.
+pub fn verify_idempotent(formatted: &str, config: Config) -> Result<Option<String>, EngineError> {
+    let second_pass = format_with_engine(formatted, config)?;
+    Ok(Some(second_pass).filter(|second_pass| second_pass != formatted))
+}
+
+/// Collect the lines of `formatted` which are still wider than `max_width`,
+/// e.g. because of a long string literal or an unbreakable expression.
+/// The `max_width` overridden by the attributes of a module or function applies to its body.
+/// The code kept verbatim (`#[fmt::skip]`, `// movefmt::off`) is not reported,
+/// the user chose its layout.
+pub fn find_line_overflows(formatted: &str, config: &Config) -> Vec<LineOverflow> {
+    let item_max_widths = item_max_widths(formatted, config);
+    let verbatim_ranges = verbatim_ranges(formatted);
+    let mut overflows = vec![];
+    let mut line_start = 0;
+    for (idx, line) in formatted.split_inclusive('\n').enumerate() {
+        let max_width = item_max_widths
+            .iter()
+            .rev()
+            .find(|(loc, _)| loc.start() as usize <= line_start && line_start < loc.end() as usize)
+            .map_or(config.max_width(), |(_, max_width)| *max_width);
+        let text = line.trim_end_matches(['\n', '\r']);
+        let mut width = 0;
+        let mut overflow_at = None;
+        for (char_idx, (byte_idx, ch)) in text.char_indices().enumerate() {
+            width += if ch == '\t' { config.tab_spaces() } else { 1 };
+            if width > max_width && overflow_at.is_none() {
+                overflow_at = Some((char_idx + 1, line_start + byte_idx));
+            }
+        }
+        line_start += line.len();
+        if let Some((col, offset)) = overflow_at {
+            if verbatim_ranges
+                .iter()
+                .any(|(start, end)| *start <= offset && offset < *end)
+            {
+                continue;
+            }
+            overflows.push(LineOverflow {
+                line: idx + 1,
+                col,
+                width,
+                max_width,
+            });
+        }
+    }
+    overflows
+}
+
+/// The non comma tokens of `content` with their byte offsets,
+/// or the offset where lexing failed.
+/// Operators are split into single characters and `&mut` into `&` and `mut`,
+/// so that `>>` closing two type parameter lists matches `> >`.
+fn lex_tokens(content: &str) -> Result<Vec<(usize, String)>, usize> {
+    let mut tokens = vec![];
+    let mut lexer = Lexer::new(content, FileHash::empty());
+    lexer.advance().map_err(|_| 0usize)?;
+    while lexer.peek() != Tok::EOF {
+        let start = lexer.start_loc();
+        let tok_content = lexer.content();
+        if lexer.peek() == Tok::Comma {
+            // the formatter may add or remove trailing commas
+        } else if lexer.peek() == Tok::AmpMut {
+            tokens.push((start, "&".to_string()));
+            tokens.push((start, "mut".to_string()));
+        } else if tok_content.chars().all(|ch| ch.is_ascii_punctuation()) {
+            tokens.extend(tok_content.chars().map(|ch| (start, ch.to_string())));
+        } else {
+            tokens.push((start, tok_content.to_string()));
+        }
+        lexer.advance().map_err(|_| start)?;
+    }
+    Ok(tokens)
+}
+
+fn normalized_comments(content: &str) -> Vec<String> {
+    CommentExtrator::new(content)
+        .map(|extractor| {
+            extractor
+                .comments
+                .into_iter()
+                .map(|comment| {
+                    comment
+                        .content
+                        .chars()
+                        .filter(|ch| !ch.is_whitespace() && *ch != '*')
+                        .collect()
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn lex_error(what: &str, content: &str, offset: usize) -> ErrorKind {
+    ErrorKind::LostToken(format!(
+        "failed to lex the {} after line {}",
+        what,
+        line_of(content, offset)
+    ))
+}
+
+/// 1-based line number of the byte `offset` of `content`.
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "module 0x1::m {
+    /* block
+     * comment */
+    fun f(a: u64, b: u64,): vector<vector<u8>> { a + b } // sum
+}
+";
+
+    #[test]
+    fn test_verify_accepts_layout_changes() {
+        let formatted = "module 0x1::m {
+    /* block
+       comment */
+    fun f(a: u64, b: u64): vector<vector<u8> > {
+        a + b
+    } // sum
+}
+";
+        assert!(verify_formatting(SOURCE, formatted).is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_lost_token() {
+        let formatted = SOURCE.replace("a + b", "a + a");
+        assert!(matches!(
+            verify_formatting(SOURCE, &formatted),
+            Err(ErrorKind::LostToken(_))
+        ));
+        let formatted = SOURCE.replace("): vector<vector<u8>> {", ") {");
+        assert!(matches!(
+            verify_formatting(SOURCE, &formatted),
+            Err(ErrorKind::LostToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_idempotent() {
+        let formatted = format_with_engine(SOURCE, Config::default()).unwrap();
+        assert_eq!(
+            verify_idempotent(&formatted, Config::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_line_overflows() {
+        let mut config = Config::default();
+        config.set().max_width(20);
+        config.set().tab_spaces(4);
+        let formatted =
+            "module 0x1::m {\n    const C: vector<u8> = b\"long\";\n\t\t\t\tfun f() {}\n}\n";
+        assert_eq!(
+            find_line_overflows(formatted, &config),
+            vec![
+                LineOverflow {
+                    line: 2,
+                    col: 21,
+                    width: 34,
+                    max_width: 20
+                },
+                LineOverflow {
+                    line: 3,
+                    col: 9,
+                    width: 26,
+                    max_width: 20
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_line_overflows_with_item_max_width() {
+        let mut config = Config::default();
+        config.set().max_width(20);
+        let formatted = "module 0x1::m {
+    #[fmt::max_width = 40]
+    fun f() {
+        let long_name = 1;
+    }
+    fun g() {
+        let long_name = 1;
+    }
+}
+";
+        let overflows = find_line_overflows(formatted, &config);
+        assert_eq!(
+            overflows
+                .iter()
+                .map(|overflow| (overflow.line, overflow.max_width))
+                .collect::<Vec<_>>(),
+            vec![(2, 20), (7, 20)]
+        );
+    }
+
+    #[test]
+    fn test_find_line_overflows_skips_verbatim_code() {
+        let mut config = Config::default();
+        config.set().max_width(20);
+        let formatted = "module 0x1::m {
+    #[fmt::skip]
+    fun f() { let long_name = 1; }
+    #[fmt::skip]
+    const LONG_NAME: u64 = 1;
+    // movefmt::off
+    const C: u64     = 100;
+    // movefmt::on
+    const D: u64 = 1000000;
+}
+";
+        let expected = vec![LineOverflow {
+            line: 9,
+            col: 21,
+            width: 27,
+            max_width: 20,
+        }];
+        assert_eq!(find_line_overflows(formatted, &config), expected);
+        let formatted = formatted.replace('\n', "\r\n");
+        assert_eq!(find_line_overflows(&formatted, &config), expected);
+    }
+
+    #[test]
+    fn test_verify_rejects_lost_comment() {
+        let formatted = SOURCE.replace(" // sum", "");
+        assert!(matches!(
+            verify_formatting(SOURCE, &formatted),
+            Err(ErrorKind::LostComment)
+        ));
+    }
+}
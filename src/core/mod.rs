@@ -1,3 +1,72 @@
 pub mod fmt;
+pub mod fmt_layout;
+pub mod fmt_range;
+pub mod fmt_recover;
 pub mod fmt_state;
+pub mod fmt_verify;
 pub mod token_tree;
+
+use crate::core::fmt::{EngineError, format_entry_timed};
+use crate::core::fmt_range::splice_items;
+use crate::core::fmt_recover::{TolerantOutput, format_entry_tolerant};
+use crate::core::fmt_state::format_entry_functional_timed;
+use crate::core::fmt_verify::verify_formatting;
+use crate::tools::utils::Timer;
+use commentfmt::Config;
+use std::ops::Range;
+
+/// Format `content` with the engine selected by `use_functional_formatter`.
+/// Every entry point formats through this function, so both engines are reachable from all of them.
+pub fn format_with_engine(content: impl AsRef<str>, config: Config) -> Result<String, EngineError> {
+    format_with_engine_timed(content.as_ref(), &config).map(|(result, _)| result)
+}
+
+/// Format `content` as the options of `config` ask: with the engine of `use_functional_formatter`,
+/// keeping the top level blocks which don't parse verbatim with `error_tolerant`.
+/// If `ranges` is given, only the top level items overlapping one of its byte ranges are formatted,
+/// failing if the items can't be matched after formatting.
+/// The binary, the language server and `api::format_str` all format through this function.
+pub fn format_source(
+    content: &str,
+    mut config: Config,
+    ranges: Option<&[Range<usize>]>,
+) -> Result<TolerantOutput, EngineError> {
+    // with `ranges`, only the result which is kept is verified, so that a failure
+    // out of the ranges doesn't prevent formatting them
+    let verify = config.verify_formatting();
+    if ranges.is_some() {
+        config.set().verify_formatting(false);
+    }
+    let output = if config.error_tolerant() {
+        format_entry_tolerant(content, config)?
+    } else {
+        let (text, timer) = format_with_engine_timed(content, &config)?;
+        TolerantOutput {
+            text,
+            unformatted_blocks: vec![],
+            timer: Some(timer),
+        }
+    };
+    match ranges {
+        Some(ranges) => {
+            let text = splice_items(content, &output.text, ranges)?;
+            if verify {
+                verify_formatting(content, &text)?;
+            }
+            Ok(TolerantOutput { text, ..output })
+        }
+        None => Ok(output),
+    }
+}
+
+/// Same as `format_with_engine`, also returning the timer.
+pub(crate) fn format_with_engine_timed(
+    content: &str,
+    config: &Config,
+) -> Result<(String, Timer), EngineError> {
+    if config.use_functional_formatter() {
+        format_entry_functional_timed(content, config)
+    } else {
+        format_entry_timed(content, config)
+    }
+}
@@ -34,6 +34,11 @@ macro_rules! impl_convert_loc {
 }
 extern crate move_ir_types;
 
+pub mod api;
 pub mod core;
 pub mod syntax_fmt;
 pub mod tools;
+
+pub use api::{
+    FormatError, FormatOutput, FormatTiming, FormatWarning, ParseDiagnostic, format_str,
+};
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::core::token_tree::*;
+use commentfmt::{Config, StyleEdition};
 use move_compiler::parser::lexer::Tok;
 use once_cell::sync::Lazy;
 
@@ -181,6 +182,26 @@ pub(crate) fn get_nested_and_comma_num(elements: &[TokenTree]) -> (usize, usize)
     result
 }
 
+/// Number of comma separated items at the top level of `elements`, eg: the number of
+/// parameters in a parameter list. A trailing comma doesn't count.
+pub(crate) fn get_top_level_item_num(elements: &[TokenTree]) -> usize {
+    let is_comma = |ele: &TokenTree| {
+        matches!(
+            ele,
+            TokenTree::SimpleToken {
+                tok: Tok::Comma,
+                ..
+            }
+        )
+    };
+    let comma_num = elements.iter().filter(|ele| is_comma(ele)).count();
+    match elements.last() {
+        None => 0,
+        Some(last) if is_comma(last) => comma_num,
+        Some(_) => comma_num + 1,
+    }
+}
+
 // Determines if a space is needed between the current and next token for formatting.
 pub(crate) fn need_space(current: &TokenTree, next: Option<&TokenTree>) -> bool {
     if next.is_none() {
@@ -418,3 +439,10 @@ pub(crate) fn process_link_access(elements: &[TokenTree], idx: usize) -> (usize,
 pub(crate) fn need_newline_when_trim_blank_line(current: &Tok, next: &Tok) -> bool {
     !(NO_BREAK_TOKENS_VEC.contains(current) || NO_BREAK_PAIRS_VEC.contains(&(*current, *next)))
 }
+
+/// Whether the blank lines following `current` are dropped, whatever comes next.
+/// From `StyleEdition::Edition2025`, a block never starts with a blank line,
+/// like it never ends with one.
+pub(crate) fn drop_blank_lines_after(current: &Tok, config: &Config) -> bool {
+    *current == Tok::LBrace && config.style_edition().at_least(StyleEdition::Edition2025)
+}
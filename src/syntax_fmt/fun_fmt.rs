@@ -6,6 +6,7 @@ use std::sync::Arc;
 
 use crate::core::token_tree::{NestKind, NestKind_, TokenTree};
 use crate::syntax_fmt::expr_fmt;
+use crate::syntax_fmt::skip_fmt::{item_max_widths, max_width_at};
 use crate::tools::utils::*;
 use commentfmt::Config;
 use move_command_line_common::files::FileHash;
@@ -403,6 +404,8 @@ pub(crate) fn process_fun_header_too_long(fmt_buffer: String, config: Config) ->
     let mut result = fmt_buffer.clone();
     let mut fun_extractor = FunHandler::new(fmt_buffer.clone());
     fun_extractor.preprocess(&Arc::new(get_defs(fmt_buffer.clone())));
+    // a function overriding `max_width` with attributes breaks its header at its own width
+    let item_max_widths = item_max_widths(&fmt_buffer, &config);
     let mut insert_char_nums = 0;
     let mut fun_idx = 0;
     for fun_loc in fun_extractor.loc_vec.iter() {
@@ -413,6 +416,11 @@ pub(crate) fn process_fun_header_too_long(fmt_buffer: String, config: Config) ->
             continue;
         }
 
+        let max_width = max_width_at(
+            &item_max_widths,
+            fun_extractor.body_loc_vec[fun_idx],
+            config.max_width(),
+        );
         let mut fun_name_str = &buf[fun_loc.start() as usize..ret_ty_loc.start() as usize];
         if !fun_name_str
             .chars()
@@ -425,7 +433,7 @@ pub(crate) fn process_fun_header_too_long(fmt_buffer: String, config: Config) ->
             continue;
         }
         let ret_ty_len = (ret_ty_loc.end() - ret_ty_loc.start()) as usize;
-        if fun_name_str.len() + ret_ty_len < config.max_width() {
+        if fun_name_str.len() + ret_ty_len < max_width {
             fun_idx += 1;
             continue;
         }
@@ -442,7 +450,7 @@ pub(crate) fn process_fun_header_too_long(fmt_buffer: String, config: Config) ->
         fun_name_str = &buf[fun_loc.start() as usize..(fun_loc.start() as usize) + insert_loc];
         tracing::debug!("fun_name_str = {}", fun_name_str);
         // there maybe comment bewteen fun_name and ret_ty
-        if fun_name_str.len() + ret_ty_len < config.max_width() {
+        if fun_name_str.len() + ret_ty_len < max_width {
             fun_idx += 1;
             continue;
         }
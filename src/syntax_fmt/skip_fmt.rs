@@ -3,7 +3,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::core::token_tree::*;
+use crate::tools::utils::get_compile_env;
+use commentfmt::{Config, ErrorKind};
+use move_command_line_common::files::FileHash;
 use move_compiler::parser::ast::*;
+use move_compiler::parser::syntax::parse_file_string;
 use move_compiler::shared::ast_debug;
 use move_ir_types::location::*;
 use std::{cell::RefCell, sync::Arc};
@@ -19,10 +23,12 @@ pub struct SkipHandler {
     pub struct_body_loc_vec: Vec<Loc>,
     pub fun_body_loc_vec: Vec<Loc>,
     pub skipped_body_loc_vec: RefCell<Vec<Loc>>,
+    /// Constants, spec blocks, use declarations and statements kept verbatim as a whole.
+    pub skipped_item_loc_vec: Vec<Loc>,
     pub source: String,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum SkipType {
     SkipModuleBody,
     SkipStructBody,
@@ -40,20 +46,69 @@ impl SingleSyntaxExtractor for SkipHandler {
             struct_body_loc_vec: vec![],
             fun_body_loc_vec: vec![],
             skipped_body_loc_vec: vec![].into(),
+            skipped_item_loc_vec: vec![],
             source: fmt_buffer.clone(),
         };
         this_skip_extractor
     }
 
-    fn collect_seq_item(&mut self, _s: &SequenceItem) {}
+    fn collect_seq_item(&mut self, s: &SequenceItem) {
+        if self.has_skip_comment(s.loc) {
+            self.skipped_item_loc_vec.push(s.loc);
+        }
+        match &s.value {
+            SequenceItem_::Seq(e) | SequenceItem_::Bind(_, _, e) => self.collect_expr(e),
+            _ => {}
+        }
+    }
 
-    fn collect_seq(&mut self, _s: &Sequence) {}
+    fn collect_seq(&mut self, s: &Sequence) {
+        for use_decl in s.0.iter() {
+            self.collect_use(use_decl);
+        }
+        for item in s.1.iter() {
+            self.collect_seq_item(item);
+        }
+        if let Some(t) = s.3.as_ref() {
+            if self.has_skip_comment(t.loc) {
+                self.skipped_item_loc_vec.push(t.loc);
+            }
+            self.collect_expr(t);
+        }
+    }
 
-    fn collect_spec(&mut self, _spec_block: &SpecBlock) {}
+    fn collect_spec(&mut self, spec_block: &SpecBlock) {
+        if has_skip_attribute(&spec_block.value.attributes) {
+            self.skipped_item_loc_vec.push(spec_block.loc);
+        }
+    }
 
-    fn collect_expr(&mut self, _e: &Exp) {}
+    fn collect_expr(&mut self, e: &Exp) {
+        // statements can only be found in blocks
+        match &e.value {
+            Exp_::Block(b) => self.collect_seq(b),
+            Exp_::IfElse(c, then_, else_) => {
+                self.collect_expr(c.as_ref());
+                self.collect_expr(then_.as_ref());
+                if let Some(else_) = else_ {
+                    self.collect_expr(else_.as_ref());
+                }
+            }
+            Exp_::While(_, c, body) => {
+                self.collect_expr(c.as_ref());
+                self.collect_expr(body.as_ref());
+            }
+            Exp_::Loop(_, body) => self.collect_expr(body.as_ref()),
+            Exp_::Lambda(_, body, _, _) => self.collect_expr(body.as_ref()),
+            _ => {}
+        }
+    }
 
-    fn collect_const(&mut self, _c: &Constant) {}
+    fn collect_const(&mut self, c: &Constant) {
+        if has_skip_attribute(&c.attributes) {
+            self.skipped_item_loc_vec.push(c.loc);
+        }
+    }
 
     fn collect_struct(&mut self, s: &StructDefinition) {
         self.struct_attributes.push(s.attributes.clone());
@@ -63,23 +118,37 @@ impl SingleSyntaxExtractor for SkipHandler {
     fn collect_function(&mut self, d: &Function) {
         self.fun_attributes.push(d.attributes.clone());
         self.fun_body_loc_vec.push(d.body.loc);
+        if let FunctionBody_::Defined(seq) = &d.body.value {
+            self.collect_seq(seq);
+        }
     }
 
     fn collect_module(&mut self, d: &ModuleDefinition) {
         self.module_attributes.push(d.attributes.clone());
         self.module_body_loc_vec.push(d.loc);
         for m in d.members.iter() {
-            if let ModuleMember::Function(x) = &m {
-                self.collect_function(x)
-            }
-            if let ModuleMember::Struct(x) = &m {
-                self.collect_struct(x)
+            match &m {
+                ModuleMember::Function(x) => self.collect_function(x),
+                ModuleMember::Struct(x) => self.collect_struct(x),
+                ModuleMember::Constant(x) => self.collect_const(x),
+                ModuleMember::Spec(x) => self.collect_spec(x),
+                ModuleMember::Use(x) => self.collect_use(x),
+                _ => {}
             }
         }
     }
 
     fn collect_script(&mut self, d: &Script) {
+        for use_decl in d.uses.iter() {
+            self.collect_use(use_decl);
+        }
+        for c in d.constants.iter() {
+            self.collect_const(c);
+        }
         self.collect_function(&d.function);
+        for s in d.specs.iter() {
+            self.collect_spec(s);
+        }
     }
 
     fn collect_definition(&mut self, d: &Definition) {
@@ -112,15 +181,17 @@ impl Preprocessor for SkipHandler {
 }
 
 impl SkipHandler {
-    pub(crate) fn should_skip_block_body(&self, kind: &NestKind, skip_type: SkipType) -> bool {
-        if SkipType::SkipNone == skip_type {
-            return false;
-        }
+    /// The attributes and the location of the module, struct or function whose body is `kind`.
+    fn find_block_body(
+        &self,
+        kind: &NestKind,
+        skip_type: SkipType,
+    ) -> Option<(&Vec<Attributes>, Loc)> {
         let (body_attributes, body_loc_vec) = match skip_type {
             SkipType::SkipModuleBody => (&self.module_attributes, &self.module_body_loc_vec),
             SkipType::SkipStructBody => (&self.struct_attributes, &self.struct_body_loc_vec),
             SkipType::SkipFunBody => (&self.fun_attributes, &self.fun_body_loc_vec),
-            _ => (&vec![], &vec![]),
+            SkipType::SkipNone => return None,
         };
 
         let len = body_loc_vec.len();
@@ -131,7 +202,7 @@ impl SkipHandler {
             if kind.end_pos < body_loc_vec[left].start()
                 || kind.start_pos > body_loc_vec[right - 1].end()
             {
-                return false;
+                return None;
             }
 
             let mid = left + (right - left) / 2;
@@ -139,15 +210,7 @@ impl SkipHandler {
             let mid_body_loc = body_loc_vec[mid];
 
             if kind.end_pos + 1 == mid_body_loc.end() {
-                for attribute in &body_attributes[mid] {
-                    let attribute_str = ast_debug::display(&attribute.value);
-                    if attribute_str.contains("#[fmt::skip]") {
-                        tracing::trace!("{:?}", attribute_str);
-                        self.skipped_body_loc_vec.borrow_mut().push(mid_body_loc);
-                        return true;
-                    }
-                }
-                return false;
+                return Some((&body_attributes[mid], mid_body_loc));
             } else if mid_loc.start() < kind.start_pos {
                 left = mid + 1;
             } else {
@@ -155,7 +218,33 @@ impl SkipHandler {
             }
         }
 
-        false
+        None
+    }
+
+    pub(crate) fn should_skip_block_body(&self, kind: &NestKind, skip_type: SkipType) -> bool {
+        let Some((body_attributes, body_loc)) = self.find_block_body(kind, skip_type) else {
+            return false;
+        };
+        if !has_skip_attribute(body_attributes) {
+            return false;
+        }
+        self.skipped_body_loc_vec.borrow_mut().push(body_loc);
+        true
+    }
+
+    /// The config of the module or function whose body is `kind`,
+    /// if its attributes override some options of `config`, see `item_config`.
+    pub(crate) fn block_body_config(
+        &self,
+        kind: &NestKind,
+        skip_type: SkipType,
+        config: &Config,
+    ) -> Option<Config> {
+        if skip_type == SkipType::SkipStructBody {
+            return None;
+        }
+        let (body_attributes, _) = self.find_block_body(kind, skip_type)?;
+        item_config(config, body_attributes)
     }
 
     pub(crate) fn has_skipped_module_body(&self, kind: &NestKind) -> bool {
@@ -168,6 +257,23 @@ impl SkipHandler {
         false
     }
 
+    fn collect_use(&mut self, use_decl: &UseDecl) {
+        if has_skip_attribute(&use_decl.attributes) {
+            self.skipped_item_loc_vec.push(use_decl.loc);
+        }
+    }
+
+    /// Whether the line just before `loc` is the comment `// movefmt::skip`,
+    /// which stands for `#[fmt::skip]` on statements since they can't have attributes.
+    fn has_skip_comment(&self, loc: Loc) -> bool {
+        let before = self.source[..loc.start() as usize].trim_end();
+        before.rsplit('\n').next().is_some_and(|line| {
+            line.trim()
+                .strip_prefix("//")
+                .is_some_and(|comment| comment.trim() == FMT_SKIP_MARKER)
+        })
+    }
+
     pub(crate) fn is_module_block(&self, kind: &NestKind) -> bool {
         if kind.kind != NestKind_::Brace {
             return false;
@@ -182,6 +288,333 @@ impl SkipHandler {
     }
 }
 
+/// The kind of item whose body is the nested token with `note`.
+pub(crate) fn block_body_type(note: &Option<Note>) -> SkipType {
+    match note.unwrap_or_default() {
+        Note::StructDefinition => SkipType::SkipStructBody,
+        Note::FunBody => SkipType::SkipFunBody,
+        Note::ModuleDef => SkipType::SkipModuleBody,
+        _ => SkipType::SkipNone,
+    }
+}
+
+/// The options which can be overridden for the body of a module or function,
+/// with `#[fmt::max_width = 120]` or `#[fmt::config(max_width = 120, ..)]`.
+pub const ITEM_CONFIG_OPTIONS: &[&str] = &[
+    "max_width",
+    "prefer_one_line_for_short_branch_blk",
+    "prefer_one_line_for_short_call_para_list",
+    "prefer_one_line_for_short_fn_header_para_list",
+    "prefer_one_line_for_short_lambda_para_list",
+];
+const FMT_ATTRIBUTE_PREFIX: &str = "fmt::";
+const FMT_CONFIG_ATTRIBUTE: &str = "fmt::config";
+
+/// `config` with the overrides of the `#[fmt::<option> = <value>]` and
+/// `#[fmt::config(<option> = <value>, ..)]` attributes, `None` if there is none.
+/// Options not in `ITEM_CONFIG_OPTIONS` and invalid values are ignored with a warning.
+pub(crate) fn item_config(config: &Config, attributes: &[Attributes]) -> Option<Config> {
+    let mut overrides = vec![];
+    for attr in attributes.iter().flat_map(|attrs| attrs.value.iter()) {
+        match &attr.value {
+            Attribute_::Assigned(name, value) => {
+                if let Some(option) = name.value.as_str().strip_prefix(FMT_ATTRIBUTE_PREFIX) {
+                    overrides.push((option.to_string(), attribute_value_str(value)));
+                }
+            }
+            Attribute_::Parameterized(name, options)
+                if name.value.as_str() == FMT_CONFIG_ATTRIBUTE =>
+            {
+                for option in options.value.iter() {
+                    if let Attribute_::Assigned(option, value) = &option.value {
+                        overrides.push((
+                            option.value.as_str().to_string(),
+                            attribute_value_str(value),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if overrides.is_empty() {
+        return None;
+    }
+
+    let mut item_cfg = config.clone();
+    for (option, value) in overrides {
+        match value {
+            Some(value)
+                if ITEM_CONFIG_OPTIONS.contains(&option.as_str())
+                    && Config::is_valid_key_val(&option, &value) =>
+            {
+                item_cfg.override_value(&option, &value);
+            }
+            _ => tracing::warn!(
+                "ignore the attribute `fmt::{}`, only a number or a bool can be set to one of {:?}",
+                option,
+                ITEM_CONFIG_OPTIONS
+            ),
+        }
+    }
+    Some(item_cfg)
+}
+
+/// The `max_width` in effect in the bodies of the modules and functions of `content`
+/// which override it with attributes, the functions after the modules.
+pub(crate) fn item_max_widths(content: &str, config: &Config) -> Vec<(Loc, usize)> {
+    if !content.contains(FMT_ATTRIBUTE_PREFIX) {
+        return vec![];
+    }
+    let Some(skip_handler) = collect_skip_handler(content) else {
+        return vec![];
+    };
+
+    let mut max_widths = vec![];
+    for (attributes, loc) in skip_handler
+        .module_attributes
+        .iter()
+        .zip(&skip_handler.module_body_loc_vec)
+    {
+        if let Some(item_cfg) = item_config(config, attributes) {
+            max_widths.push((*loc, item_cfg.max_width()));
+        }
+    }
+    let module_max_widths = max_widths.len();
+    for (attributes, loc) in skip_handler
+        .fun_attributes
+        .iter()
+        .zip(&skip_handler.fun_body_loc_vec)
+    {
+        let mut module_cfg = config.clone();
+        if let Some((_, max_width)) =
+            max_widths[..module_max_widths]
+                .iter()
+                .find(|(module_loc, _)| {
+                    module_loc.start() <= loc.start() && loc.end() <= module_loc.end()
+                })
+        {
+            module_cfg.set().max_width(*max_width);
+        }
+        if let Some(item_cfg) = item_config(&module_cfg, attributes) {
+            max_widths.push((*loc, item_cfg.max_width()));
+        }
+    }
+    max_widths
+}
+
+/// The `max_width` in effect at `loc`, the innermost of `item_max_widths` holding it,
+/// `max_width` outside of them.
+pub(crate) fn max_width_at(item_max_widths: &[(Loc, usize)], loc: Loc, max_width: usize) -> usize {
+    item_max_widths
+        .iter()
+        .rev()
+        .find(|(item_loc, _)| item_loc.start() <= loc.start() && loc.end() <= item_loc.end())
+        .map_or(max_width, |(_, item_max_width)| *item_max_width)
+}
+
+fn attribute_value_str(value: &AttributeValue) -> Option<String> {
+    let AttributeValue_::Value(value) = &value.value else {
+        return None;
+    };
+    match &value.value {
+        Value_::Num(num) => Some(num.as_str().to_string()),
+        Value_::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether one of the attributes is `fmt::skip`, e.g. `#[fmt::skip]` or `#[test, fmt::skip]`.
+fn has_skip_attribute(attributes: &[Attributes]) -> bool {
+    attributes.iter().any(|attrs| {
+        attrs.value.iter().any(|attr| {
+            matches!(&attr.value, Attribute_::Name(name) if name.value.as_str() == FMT_SKIP_ATTRIBUTE)
+        })
+    })
+}
+
+/// The `SkipHandler` of `content`, `None` if it doesn't parse.
+fn collect_skip_handler(content: &str) -> Option<SkipHandler> {
+    let (defs, _) = parse_file_string(&mut get_compile_env(), FileHash::empty(), content).ok()?;
+    let mut skip_handler = SkipHandler::new(content.to_string());
+    skip_handler.preprocess(&Arc::new(defs));
+    Some(skip_handler)
+}
+
+/// Locations of the items of `content` to keep verbatim, sorted and without nested ones.
+fn skipped_item_locs(content: &str) -> Vec<Loc> {
+    let Some(skip_handler) = collect_skip_handler(content) else {
+        return vec![];
+    };
+    let mut item_locs = skip_handler.skipped_item_loc_vec;
+    item_locs.sort_by_key(|loc| (loc.start(), std::cmp::Reverse(loc.end())));
+    let mut result: Vec<Loc> = vec![];
+    for loc in item_locs {
+        if result.last().is_none_or(|last| last.end() <= loc.start()) {
+            result.push(loc);
+        }
+    }
+    result
+}
+
+/// Put the original bytes of every constant, spec block, use declaration or statement
+/// marked with `#[fmt::skip]` (`// movefmt::skip` for statements) back into `formatted`.
+/// The skipped items of both texts are matched in order, formatting keeps every token.
+/// Fails if they can't be matched, rather than changing a skipped item.
+fn keep_skipped_items(origin: &str, formatted: String) -> Result<String, ErrorKind> {
+    if !origin.contains(FMT_SKIP_ATTRIBUTE) {
+        return Ok(formatted);
+    }
+    let origin_locs = skipped_item_locs(origin);
+    if origin_locs.is_empty() {
+        return Ok(formatted);
+    }
+    let formatted_locs = skipped_item_locs(&formatted);
+    if origin_locs.len() != formatted_locs.len() {
+        return Err(ErrorKind::LostSkippedCode(format!(
+            "{} skipped items before formatting but {} after",
+            origin_locs.len(),
+            formatted_locs.len()
+        )));
+    }
+    let mut result = String::with_capacity(formatted.len());
+    let mut last_end = 0;
+    for (origin_loc, loc) in origin_locs.iter().zip(&formatted_locs) {
+        result.push_str(&formatted[last_end..loc.start() as usize]);
+        result.push_str(&origin[origin_loc.start() as usize..origin_loc.end() as usize]);
+        last_end = loc.end() as usize;
+    }
+    result.push_str(&formatted[last_end..]);
+    Ok(result)
+}
+
+/// Byte ranges of `content` holding code the user asked to skip, which `keep_skipped_code`
+/// restores verbatim: the bodies of the modules, structs and functions and the items marked
+/// with `#[fmt::skip]`, the statements after `// movefmt::skip` and the `// movefmt::off` regions.
+pub(crate) fn verbatim_ranges(content: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    // `movefmt::skip` contains `fmt::skip` as well
+    if content.contains(FMT_SKIP_ATTRIBUTE) {
+        if let Some(skip_handler) = collect_skip_handler(content) {
+            let bodies = [
+                (
+                    &skip_handler.module_attributes,
+                    &skip_handler.module_body_loc_vec,
+                ),
+                (
+                    &skip_handler.struct_attributes,
+                    &skip_handler.struct_body_loc_vec,
+                ),
+                (&skip_handler.fun_attributes, &skip_handler.fun_body_loc_vec),
+            ];
+            for (attributes, locs) in bodies {
+                for (attributes, loc) in attributes.iter().zip(locs) {
+                    if has_skip_attribute(attributes) {
+                        ranges.push((loc.start() as usize, loc.end() as usize));
+                    }
+                }
+            }
+            for loc in &skip_handler.skipped_item_loc_vec {
+                ranges.push((loc.start() as usize, loc.end() as usize));
+            }
+        }
+    }
+    if content.contains(FMT_OFF_MARKER) {
+        ranges.extend(fmt_off_regions(content));
+    }
+    ranges
+}
+
+/// Undo the formatting of everything the user asked to skip, see `keep_skipped_items`
+/// and `keep_fmt_off_regions`.
+pub(crate) fn keep_skipped_code(origin: &str, formatted: String) -> Result<String, ErrorKind> {
+    keep_fmt_off_regions(origin, keep_skipped_items(origin, formatted)?)
+}
+
+pub const FMT_SKIP_ATTRIBUTE: &str = "fmt::skip";
+/// The comment standing for `#[fmt::skip]` before a statement.
+pub const FMT_SKIP_MARKER: &str = "movefmt::skip";
+
+/// The marker comments of a region kept verbatim, e.g. a hand-aligned table of constants.
+pub const FMT_OFF_MARKER: &str = "movefmt::off";
+pub const FMT_ON_MARKER: &str = "movefmt::on";
+
+/// Byte ranges of the regions between a `// movefmt::off` and the following `// movefmt::on`.
+/// A region starts right after the `off` comment and ends at the beginning of the line of
+/// the `on` comment (or at the comment itself if some code precedes it on that line),
+/// so that both markers stay formatted. A region without `on` runs to the end of the file.
+fn fmt_off_regions(content: &str) -> Vec<(usize, usize)> {
+    let Ok(extractor) = CommentExtrator::new(content) else {
+        return vec![];
+    };
+    let mut regions = vec![];
+    let mut region_start = None;
+    for comment in &extractor.comments {
+        let Some(text) = comment.content.strip_prefix("//") else {
+            continue;
+        };
+        let is_off = text.trim() == FMT_OFF_MARKER;
+        let is_on = text.trim() == FMT_ON_MARKER;
+        if !(is_off && region_start.is_none() || is_on && region_start.is_some()) {
+            continue;
+        }
+        // the offset of a `//` comment ended by a new line is one past its start
+        let offset = comment.start_offset as usize;
+        let Some(start) = (offset.saturating_sub(1)..=offset).find(|idx| {
+            content
+                .get(*idx..)
+                .is_some_and(|rest| rest.starts_with(&comment.content))
+        }) else {
+            continue;
+        };
+        if is_off {
+            region_start = Some(start + comment.content.len());
+        } else if let Some(region_start) = region_start.take() {
+            let line_start = content[..start].rfind('\n').map_or(0, |idx| idx + 1);
+            let region_end = if content[line_start..start].trim().is_empty() {
+                line_start.max(region_start)
+            } else {
+                start
+            };
+            regions.push((region_start, region_end));
+        }
+    }
+    if let Some(region_start) = region_start {
+        regions.push((region_start, content.len()));
+    }
+    regions
+}
+
+/// Put the original bytes of every `// movefmt::off` ... `// movefmt::on` region back into
+/// `formatted`. This runs on the final result, so whatever the token walk or the
+/// post-passes (`tune_module_buf`, `fmt_fun`, `fmt_spec`, `fmt_big_block`) did inside a
+/// region is undone. The markers are matched in order, since formatting keeps the comments.
+/// Fails if they can't be matched, rather than formatting a region.
+fn keep_fmt_off_regions(origin: &str, formatted: String) -> Result<String, ErrorKind> {
+    if !origin.contains(FMT_OFF_MARKER) {
+        return Ok(formatted);
+    }
+    let origin_regions = fmt_off_regions(origin);
+    let formatted_regions = fmt_off_regions(&formatted);
+    if origin_regions.len() != formatted_regions.len() {
+        return Err(ErrorKind::LostSkippedCode(format!(
+            "{} movefmt::off regions before formatting but {} after",
+            origin_regions.len(),
+            formatted_regions.len()
+        )));
+    }
+    let mut result = String::with_capacity(formatted.len());
+    let mut last_end = 0;
+    for ((origin_start, origin_end), (start, end)) in origin_regions.iter().zip(&formatted_regions)
+    {
+        result.push_str(&formatted[last_end..*start]);
+        result.push_str(&origin[*origin_start..*origin_end]);
+        last_end = *end;
+    }
+    result.push_str(&formatted[last_end..]);
+    Ok(result)
+}
+
 #[allow(dead_code)]
 fn get_fun_attributes(fmt_buffer: String) {
     // let buf = fmt_buffer.clone();
@@ -219,3 +652,111 @@ module 0x42::LambdaTest1 {
         .to_string(),
     );
 }
+
+#[test]
+fn test_keep_skipped_items_mismatch() {
+    let origin = "module 0x1::m {
+    #[fmt::skip]
+    const A: u64   = 1;
+}
+";
+    assert_eq!(
+        keep_skipped_items(origin, origin.to_string()).unwrap(),
+        origin
+    );
+    // the attribute is gone, the original can't be put back
+    assert!(matches!(
+        keep_skipped_items(
+            origin,
+            "module 0x1::m {\n    const A: u64 = 1;\n}\n".to_string()
+        ),
+        Err(ErrorKind::LostSkippedCode(_))
+    ));
+}
+
+#[test]
+fn test_keep_fmt_off_regions() {
+    let origin = "module 0x1::m {
+    // movefmt::off
+    const A: u64    = 1;
+    const BB: u64   = 22;
+        // movefmt::on
+    fun f() {   1 + 2; // movefmt::off
+  3 +   4; 5 +   6; // movefmt::on
+    }
+    // movefmt::off
+    const C: u64   = 3;
+}
+";
+    let formatted = "module 0x1::m {
+    // movefmt::off
+    const A: u64 = 1;
+    const BB: u64 = 22;
+    // movefmt::on
+    fun f() {
+        1 + 2; // movefmt::off
+        3 + 4;
+        5 + 6; // movefmt::on
+    }
+
+    // movefmt::off
+    const C: u64 = 3;
+}
+";
+    assert_eq!(
+        keep_fmt_off_regions(origin, formatted.to_string()).unwrap(),
+        "module 0x1::m {
+    // movefmt::off
+    const A: u64    = 1;
+    const BB: u64   = 22;
+    // movefmt::on
+    fun f() {
+        1 + 2; // movefmt::off
+  3 +   4; 5 +   6; // movefmt::on
+    }
+
+    // movefmt::off
+    const C: u64   = 3;
+}
+"
+    );
+}
+
+#[test]
+fn test_keep_fmt_off_regions_unbalanced() {
+    // without `movefmt::on` the region runs to the end of the file
+    let origin = "module 0x1::m {
+    fun f() {   1 + 2; }
+    // movefmt::off
+    fun g() {   3 + 4; }
+}
+";
+    let formatted = "module 0x1::m {
+    fun f() {
+        1 + 2;
+    }
+
+    // movefmt::off
+    fun g() {
+        3 + 4;
+    }
+}
+";
+    assert_eq!(
+        keep_fmt_off_regions(origin, formatted.to_string()).unwrap(),
+        "module 0x1::m {
+    fun f() {
+        1 + 2;
+    }
+
+    // movefmt::off
+    fun g() {   3 + 4; }
+}
+"
+    );
+    // the marker is gone, the region can't be put back
+    assert!(matches!(
+        keep_fmt_off_regions(origin, formatted.replace("    // movefmt::off\n", "")),
+        Err(ErrorKind::LostSkippedCode(_))
+    ));
+}
@@ -2,6 +2,7 @@
 // Copyright (c) The BitsLab.MoveBit Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+use super::skip_fmt::{item_max_widths, max_width_at};
 use super::syntax_trait::SingleSyntaxExtractor;
 use crate::tools::utils::*;
 use commentfmt::Config;
@@ -311,6 +312,8 @@ pub fn process_spec_fn_header_too_long(fmt_buffer: String, config: Config) -> St
     let buf = fmt_buffer.clone();
     let mut result = fmt_buffer.clone();
     let spec_extractor = SpecExtractor::new(fmt_buffer.clone());
+    // spec functions are module members, they follow the `max_width` of their module
+    let item_max_widths = item_max_widths(&fmt_buffer, &config);
     let mut insert_char_nums = 0;
     let mut fun_idx = 0;
     for fun_loc in spec_extractor.spec_fn_loc_vec.iter() {
@@ -321,6 +324,7 @@ pub fn process_spec_fn_header_too_long(fmt_buffer: String, config: Config) -> St
             continue;
         }
 
+        let max_width = max_width_at(&item_max_widths, *fun_loc, config.max_width());
         let mut fun_name_str = &buf[fun_loc.start() as usize..ret_ty_loc.start() as usize];
         if !fun_name_str
             .chars()
@@ -334,7 +338,7 @@ pub fn process_spec_fn_header_too_long(fmt_buffer: String, config: Config) -> St
         }
 
         let ret_ty_len = (ret_ty_loc.end() - ret_ty_loc.start()) as usize;
-        if fun_name_str.len() + ret_ty_len < config.max_width() {
+        if fun_name_str.len() + ret_ty_len < max_width {
             fun_idx += 1;
             continue;
         }
@@ -361,7 +365,7 @@ pub fn process_spec_fn_header_too_long(fmt_buffer: String, config: Config) -> St
         fun_name_str = &buf[fun_loc.start() as usize..(fun_loc.start() as usize) + insert_loc];
         tracing::debug!("spec_fun_name_str = {}", fun_name_str);
         // there maybe comment bewteen fun_name and ret_ty
-        if fun_name_str.len() + ret_ty_len < config.max_width() {
+        if fun_name_str.len() + ret_ty_len < max_width {
             fun_idx += 1;
             continue;
         }
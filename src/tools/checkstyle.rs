@@ -0,0 +1,110 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::tools::json_report::{JsonFileReport, JsonFileStatus};
+use std::fmt::Write;
+
+/// Render the reports of `--emit checkstyle`: every changed chunk is a warning,
+/// a file which could not be parsed or read is an error, so is a block kept verbatim
+/// by `error_tolerant`. Skipped files are left out.
+pub fn to_checkstyle(files: &[JsonFileReport]) -> String {
+    let mut output = String::new();
+    output.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    output.push_str("<checkstyle version=\"4.3\">\n");
+    for file in files {
+        let error = match file.status {
+            JsonFileStatus::SkippedByConfig | JsonFileStatus::SkippedNotInPackage => continue,
+            JsonFileStatus::ParseError | JsonFileStatus::VerifyError | JsonFileStatus::IoError => {
+                file.diagnostics.clone().unwrap_or_default()
+            }
+            JsonFileStatus::Formatted | JsonFileStatus::Unchanged => String::new(),
+        };
+        let _ = writeln!(output, "<file name=\"{}\">", xml_escape(&file.name));
+        if !error.is_empty() {
+            let _ = writeln!(
+                output,
+                "<error line=\"1\" severity=\"error\" message=\"{}\" source=\"movefmt\" />",
+                xml_escape(error.trim_end())
+            );
+        }
+        for block in &file.unformatted_blocks {
+            let _ = writeln!(
+                output,
+                "<error line=\"{}\" severity=\"error\" message=\"{}\" source=\"movefmt\" />",
+                block.line,
+                xml_escape(block.diagnostics.trim_end())
+            );
+        }
+        for chunk in &file.chunks {
+            let _ = writeln!(
+                output,
+                "<error line=\"{}\" severity=\"warning\" message=\"{}\" source=\"movefmt\" />",
+                chunk.line_number_orig.max(1),
+                xml_escape(&chunk.message())
+            );
+        }
+        for overflow in &file.line_overflows {
+            let _ = writeln!(
+                output,
+                "<error line=\"{}\" column=\"{}\" severity=\"warning\" message=\"{}\" \
+                 source=\"movefmt\" />",
+                overflow.line,
+                overflow.col,
+                xml_escape(&overflow.message())
+            );
+        }
+        output.push_str("</file>\n");
+    }
+    output.push_str("</checkstyle>");
+    output
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '&' => escaped.push_str("&amp;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' => escaped.push_str("&#9;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checkstyle_findings() {
+        let origin = "module 0x1::m {\nfun f(): vector<u8> {}\n    fun g() {}\n}\n";
+        let formatted = "module 0x1::m {\n    fun f(): vector<u8> {}\n    fun g() {}\n}\n";
+        let files = vec![
+            JsonFileReport::formatted("a.move".to_string(), origin, formatted),
+            JsonFileReport::formatted("b.move".to_string(), formatted, formatted),
+            JsonFileReport::not_formatted(
+                "c.move".to_string(),
+                JsonFileStatus::SkippedByConfig,
+                None,
+            ),
+        ];
+        assert_eq!(
+            to_checkstyle(&files),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<checkstyle version=\"4.3\">
+<file name=\"a.move\">
+<error line=\"2\" severity=\"warning\" \
+             message=\"Replace line 2 with `    fun f(): vector&lt;u8&gt; {}`\" source=\"movefmt\" />
+</file>
+<file name=\"b.move\">
+</file>
+</checkstyle>"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{Result, bail, format_err};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Which changes of the local git repository to look at.
+#[derive(Clone, Debug)]
+pub enum GitChanges {
+    /// Changes of the working tree since the revision, including the untracked files.
+    Since(String),
+    /// Changes in the index.
+    Staged,
+}
+
+/// A Move file changed in the local git repository.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    /// Changed line ranges (1-based, inclusive) of the file,
+    /// `None` if the whole file is new to git.
+    pub changed_lines: Option<Vec<(usize, usize)>>,
+}
+
+/// Collect the Move files changed in the git repository containing the current directory.
+/// Deleted files are not reported.
+/// With `GitChanges::Staged`, the lines of a file which also has unstaged changes are not
+/// reported, since the file formatted is the one of the working tree, not the staged one.
+pub fn changed_move_files(changes: &GitChanges) -> Result<Vec<ChangedFile>> {
+    let cur_dir = std::env::current_dir()?;
+    let root = PathBuf::from(run_git(&cur_dir, &["rev-parse", "--show-toplevel"])?.trim());
+
+    let mut diff_args = vec![
+        "diff",
+        "-U0",
+        "--no-color",
+        "--no-ext-diff",
+        "--diff-filter=ACMR",
+        // whatever `diff.noprefix` or `diff.mnemonicPrefix` say
+        "--src-prefix=a/",
+        "--dst-prefix=b/",
+    ];
+    match changes {
+        GitChanges::Since(rev) => diff_args.push(rev.as_str()),
+        GitChanges::Staged => diff_args.push("--cached"),
+    }
+    diff_args.extend(["--", "*.move"]);
+    let mut files: Vec<ChangedFile> = parse_changed_lines(&run_git(&root, &diff_args)?)
+        .into_iter()
+        .map(|(path, lines)| ChangedFile {
+            path: root.join(path),
+            changed_lines: Some(lines),
+        })
+        .collect();
+
+    match changes {
+        GitChanges::Since(_) => {
+            let untracked = run_git(
+                &root,
+                &[
+                    "ls-files",
+                    "-z",
+                    "--others",
+                    "--exclude-standard",
+                    "--",
+                    "*.move",
+                ],
+            )?;
+            files.extend(
+                untracked
+                    .split('\0')
+                    .filter(|path| !path.is_empty())
+                    .map(|path| ChangedFile {
+                        path: root.join(path),
+                        changed_lines: None,
+                    }),
+            );
+        }
+        GitChanges::Staged => {
+            let unstaged = run_git(&root, &["diff", "--name-only", "-z", "--", "*.move"])?;
+            let unstaged: Vec<PathBuf> = unstaged
+                .split('\0')
+                .filter(|path| !path.is_empty())
+                .map(|path| root.join(path))
+                .collect();
+            for file in files.iter_mut() {
+                if file.changed_lines.is_some() && unstaged.contains(&file.path) {
+                    tracing::warn!(
+                        "`{}` has unstaged changes, the staged lines don't match it, formatting the whole file",
+                        file.path.display()
+                    );
+                    file.changed_lines = None;
+                }
+            }
+        }
+    }
+
+    // same as the files collected by `--dir-path`
+    files.retain(|file| {
+        file.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.ends_with(".move") && !name.contains(".fmt") && !name.contains(".out")
+            })
+    });
+    Ok(files)
+}
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        // paths are printed as they are, only the ones with special characters are quoted
+        .args(["-c", "core.quotePath=false"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format_err!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse the output of `git diff -U0` into the changed line ranges of each new file.
+/// A deletion is reported as the line before the deleted lines.
+fn parse_changed_lines(diff_output: &str) -> Vec<(PathBuf, Vec<(usize, usize)>)> {
+    let mut result: Vec<(PathBuf, Vec<(usize, usize)>)> = vec![];
+    let mut pre_line = "";
+    for line in diff_output.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            if pre_line.starts_with("--- ") {
+                // git ends the header with a tab when the path has a space
+                let path = unquote_path(path.trim_end_matches('\t'));
+                let path = path.strip_prefix("b/").unwrap_or(&path);
+                result.push((PathBuf::from(path), vec![]));
+            }
+        } else if let Some(hunk) = line.strip_prefix("@@ ") {
+            // @@ -old_start[,old_count] +new_start[,new_count] @@
+            let new_range = hunk
+                .split(' ')
+                .find_map(|range| range.strip_prefix('+'))
+                .unwrap_or_default();
+            let (start, count) = match new_range.split_once(',') {
+                Some((start, count)) => (start.parse().unwrap_or(0), count.parse().unwrap_or(1)),
+                None => (new_range.parse().unwrap_or(0), 1),
+            };
+            if let Some((_, lines)) = result.last_mut() {
+                if count == 0 {
+                    lines.push((start.max(1), start.max(1)));
+                } else {
+                    lines.push((start, start + count - 1));
+                }
+            }
+        }
+        pre_line = line;
+    }
+    result
+}
+
+/// Undo the C-style quoting of the paths git prints with special characters,
+/// e.g. `"b/a\"b.move"`. Other paths are returned as they are.
+fn unquote_path(path: &str) -> String {
+    let Some(quoted) = path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    else {
+        return path.to_string();
+    };
+    let mut bytes = vec![];
+    let mut chars = quoted.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('f') => bytes.push(0x0c),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('v') => bytes.push(0x0b),
+            // an octal escape of one byte, e.g. `\303\251` for `é`
+            Some(first @ '0'..='7') => {
+                let octal: String = std::iter::once(first)
+                    .chain(chars.by_ref().take(2))
+                    .collect();
+                bytes.push(u8::from_str_radix(&octal, 8).unwrap_or_default());
+            }
+            Some(ch) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            }
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changed_lines_from_diff() {
+        let diff_output = "diff --git a/sources/a.move b/sources/a.move
+index 1111111..2222222 100644
+--- a/sources/a.move
++++ b/sources/a.move
+@@ -3 +3 @@ module 0x1::a {
+-    fun f() {}
++    fun f() { }
+@@ -10,2 +10,0 @@ module 0x1::a {
+-    fun g() {}
+-    fun h() {}
+@@ -20,0 +19,3 @@ module 0x1::a {
++++ b/fake/header.move
++    fun i() {}
++
+diff --git a/sources/b.move b/sources/b.move
+new file mode 100644
+--- /dev/null
++++ b/sources/b.move
+@@ -0,0 +1,2 @@
++module 0x1::b {
++}
+";
+        assert_eq!(
+            parse_changed_lines(diff_output),
+            vec![
+                (
+                    PathBuf::from("sources/a.move"),
+                    vec![(3, 3), (10, 10), (19, 21)]
+                ),
+                (PathBuf::from("sources/b.move"), vec![(1, 2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn changed_lines_of_quoted_paths() {
+        let diff_output = "diff --git a/sources/a b.move b/sources/a b.move
+--- a/sources/a b.move\t
++++ b/sources/a b.move\t
+@@ -1 +1 @@
+-module 0x1::a {}
++module 0x1::a { }
+diff --git \"a/sources/c\\\"d.move\" \"b/sources/c\\\"d.move\"
+--- \"a/sources/c\\\"d.move\"
++++ \"b/sources/c\\\"d.move\"
+@@ -2 +2 @@
+-}
++ }
+";
+        assert_eq!(
+            parse_changed_lines(diff_output),
+            vec![
+                (PathBuf::from("sources/a b.move"), vec![(1, 1)]),
+                (PathBuf::from("sources/c\"d.move"), vec![(2, 2)]),
+            ]
+        );
+        assert_eq!(unquote_path("\"b/\\303\\251.move\""), "b/é.move");
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::core::fmt_verify::LineOverflow;
+use crate::tools::movefmt_diff::{ModifiedChunk, ModifiedLines, make_diff};
+use serde::Serialize;
+
+/// The outcome of a file in the report of `--emit json`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsonFileStatus {
+    /// Formatting changed the file.
+    Formatted,
+    /// The file was already formatted.
+    Unchanged,
+    /// Escaped by `skip_formatting_dirs` in movefmt.toml.
+    SkippedByConfig,
+    /// Escaped because the file doesn't belong to any Move-Package.
+    SkippedNotInPackage,
+    /// Parse failed, see `diagnostics`.
+    ParseError,
+    /// Formatting would have lost a comment or changed a token, see `diagnostics`.
+    VerifyError,
+    /// The file or its config could not be read or written, see `diagnostics`.
+    IoError,
+}
+
+/// One file of the report of `--emit json`.
+#[derive(Serialize, Debug)]
+pub struct JsonFileReport {
+    pub name: String,
+    pub status: JsonFileStatus,
+    /// The hunks turning the original file into the formatted one,
+    /// line numbers are those of the original file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ModifiedChunk>,
+    /// With `--verify-idempotent`, the hunks of a second formatting pass,
+    /// line numbers are those of the formatted file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub second_pass_chunks: Vec<ModifiedChunk>,
+    /// Lines of the formatted file still wider than `max_width`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub line_overflows: Vec<LineOverflow>,
+    /// With `error_tolerant`, the top level blocks which were kept verbatim.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unformatted_blocks: Vec<JsonUnformattedBlock>,
+    /// Compiler diagnostics, or the message of a verify or io error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<String>,
+}
+
+impl JsonFileReport {
+    /// Report of a formatted file, `Unchanged` if `formatted` is the same as `origin`.
+    pub fn formatted(name: String, origin: &str, formatted: &str) -> Self {
+        let chunks = ModifiedLines::from(make_diff(origin, formatted, 0)).chunks;
+        let status = if chunks.is_empty() {
+            JsonFileStatus::Unchanged
+        } else {
+            JsonFileStatus::Formatted
+        };
+        JsonFileReport {
+            name,
+            status,
+            chunks,
+            second_pass_chunks: vec![],
+            line_overflows: vec![],
+            unformatted_blocks: vec![],
+            diagnostics: None,
+        }
+    }
+
+    /// Records the hunks of a second formatting pass which didn't keep `formatted` as it is.
+    pub fn with_second_pass(mut self, formatted: &str, second_pass: &str) -> Self {
+        self.second_pass_chunks = ModifiedLines::from(make_diff(formatted, second_pass, 0)).chunks;
+        self
+    }
+
+    /// Report of a file which was not formatted.
+    pub fn not_formatted(
+        name: String,
+        status: JsonFileStatus,
+        diagnostics: Option<String>,
+    ) -> Self {
+        JsonFileReport {
+            name,
+            status,
+            chunks: vec![],
+            second_pass_chunks: vec![],
+            line_overflows: vec![],
+            unformatted_blocks: vec![],
+            diagnostics,
+        }
+    }
+}
+
+/// A top level block kept verbatim by `error_tolerant`, lines are those of the original file.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct JsonUnformattedBlock {
+    pub line: usize,
+    pub end_line: usize,
+    /// Compiler diagnostics, or the message of a verify error.
+    pub diagnostics: String,
+}
+
+/// The document printed by `--emit json`.
+#[derive(Serialize, Debug, Default)]
+pub struct JsonReport {
+    pub files: Vec<JsonFileReport>,
+}
+
+impl JsonReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_chunks_and_status() {
+        let origin = "module 0x1::m {\nfun f() {}\n    fun g() {}\n}\n";
+        let formatted = "module 0x1::m {\n    fun f() {}\n    fun g() {}\n}\n";
+        let report = JsonReport {
+            files: vec![
+                JsonFileReport::formatted("a.move".to_string(), origin, formatted),
+                JsonFileReport::formatted("b.move".to_string(), formatted, formatted),
+                JsonFileReport::not_formatted(
+                    "c.move".to_string(),
+                    JsonFileStatus::ParseError,
+                    Some("error[E01002]: unexpected token".to_string()),
+                ),
+            ],
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "files": [
+                    {
+                        "name": "a.move",
+                        "status": "formatted",
+                        "chunks": [
+                            {
+                                "line_number_orig": 2,
+                                "lines_removed": 1,
+                                "lines": ["    fun f() {}"]
+                            }
+                        ]
+                    },
+                    { "name": "b.move", "status": "unchanged" },
+                    {
+                        "name": "c.move",
+                        "status": "parse-error",
+                        "diagnostics": "error[E01002]: unexpected token"
+                    }
+                ]
+            })
+        );
+    }
+}
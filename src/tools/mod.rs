@@ -1,2 +1,7 @@
+pub mod checkstyle;
+pub mod git_changes;
+pub mod json_report;
 pub mod movefmt_diff;
+pub mod sarif;
+pub mod text_edits;
 pub mod utils;
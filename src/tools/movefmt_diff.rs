@@ -1,3 +1,4 @@
+use serde::Serialize;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::fmt;
@@ -37,7 +38,7 @@ impl Mismatch {
 
 /// A single span of changed lines, with 0 or more removed lines
 /// and a vector of 0 or more inserted lines.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct ModifiedChunk {
     /// The first to be removed from the original text
     pub line_number_orig: u32,
@@ -47,6 +48,35 @@ pub struct ModifiedChunk {
     pub lines: Vec<String>,
 }
 
+impl ModifiedChunk {
+    /// The last line of the original text replaced by this chunk,
+    /// the line before `line_number_orig` if nothing is removed.
+    pub fn last_line_number_orig(&self) -> u32 {
+        (self.line_number_orig + self.lines_removed).saturating_sub(1)
+    }
+
+    /// A one sentence description of the change, for the findings of code scanning reports.
+    pub fn message(&self) -> String {
+        let removed = if self.lines_removed > 1 {
+            format!(
+                "lines {}-{}",
+                self.line_number_orig,
+                self.last_line_number_orig()
+            )
+        } else {
+            format!("line {}", self.line_number_orig)
+        };
+        let expected = self.lines.join("\n");
+        if self.lines_removed == 0 {
+            format!("Insert `{}` before {}", expected, removed)
+        } else if self.lines.is_empty() {
+            format!("Remove {}", removed)
+        } else {
+            format!("Replace {} with `{}`", removed, expected)
+        }
+    }
+}
+
 /// Set of changed sections of a file.
 #[derive(Debug, PartialEq, Eq)]
 pub struct ModifiedLines {
@@ -283,10 +313,19 @@ where
 }
 
 pub fn print_mismatches_default_message(result: HashMap<PathBuf, Vec<Mismatch>>) {
+    print_mismatches(result, |file_name, line_num| {
+        format!("\nMismatch at {}:{}:", file_name.display(), line_num)
+    });
+}
+
+pub fn print_mismatches<F>(result: HashMap<PathBuf, Vec<Mismatch>>, mismatch_msg_formatter: F)
+where
+    F: Fn(&Path, u32) -> String,
+{
     for (file_name, diff) in result {
-        let mismatch_msg_formatter =
-            |line_num| format!("\nMismatch at {}:{}:", file_name.display(), line_num);
-        print_diff(diff, mismatch_msg_formatter);
+        print_diff(diff, |line_num| {
+            mismatch_msg_formatter(&file_name, line_num)
+        });
     }
 
     if let Some(mut t) = term::stdout() {
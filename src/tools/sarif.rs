@@ -0,0 +1,246 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::tools::json_report::{JsonFileReport, JsonFileStatus};
+use crate::tools::movefmt_diff::ModifiedChunk;
+use serde_json::{Value, json};
+use std::path::Path;
+
+const RULE_NOT_FORMATTED: &str = "movefmt/not-formatted";
+const RULE_LINE_OVERFLOW: &str = "movefmt/line-overflow";
+const RULE_PARSE_ERROR: &str = "movefmt/parse-error";
+const RULE_VERIFY_ERROR: &str = "movefmt/verify-error";
+const RULE_IO_ERROR: &str = "movefmt/io-error";
+const RULE_KEPT_VERBATIM: &str = "movefmt/kept-verbatim";
+/// The base of the relative artifact URIs, the directory movefmt runs in.
+const SRCROOT: &str = "%SRCROOT%";
+
+/// Render the reports of `--emit sarif` as a SARIF 2.1.0 log: every changed chunk is a
+/// warning carrying the formatted lines as a fix, so is every line wider than `max_width`,
+/// a file which could not be parsed or read is an error, so is a block kept verbatim
+/// by `error_tolerant`.
+/// Skipped and unchanged files produce no result.
+pub fn to_sarif(files: &[JsonFileReport]) -> String {
+    let mut results = vec![];
+    for file in files {
+        let rule_id = match file.status {
+            JsonFileStatus::ParseError => RULE_PARSE_ERROR,
+            JsonFileStatus::VerifyError => RULE_VERIFY_ERROR,
+            JsonFileStatus::IoError => RULE_IO_ERROR,
+            _ => {
+                results.extend(
+                    file.chunks
+                        .iter()
+                        .map(|chunk| chunk_result(&file.name, chunk)),
+                );
+                results.extend(file.unformatted_blocks.iter().map(|block| {
+                    let location = location(&file.name, block.line as u32, block.end_line as u32);
+                    json!({
+                        "ruleId": RULE_KEPT_VERBATIM,
+                        "level": "error",
+                        "message": { "text": block.diagnostics },
+                        "locations": [location],
+                    })
+                }));
+                results.extend(file.line_overflows.iter().map(|overflow| {
+                    let line = overflow.line as u32;
+                    let mut location = location(&file.name, line, line);
+                    location["physicalLocation"]["region"]["startColumn"] = json!(overflow.col);
+                    json!({
+                        "ruleId": RULE_LINE_OVERFLOW,
+                        "level": "warning",
+                        "message": { "text": overflow.message() },
+                        "locations": [location],
+                    })
+                }));
+                continue;
+            }
+        };
+        results.push(json!({
+            "ruleId": rule_id,
+            "level": "error",
+            "message": { "text": file.diagnostics.clone().unwrap_or_default() },
+            "locations": [location(&file.name, 1, 1)],
+        }));
+    }
+
+    let mut run = json!({
+        "tool": {
+            "driver": {
+                "name": "movefmt",
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": "https://github.com/movebit/movefmt",
+                "rules": [
+                    rule(RULE_NOT_FORMATTED, "The code is not formatted with movefmt"),
+                    rule(
+                        RULE_LINE_OVERFLOW,
+                        "The line is wider than max_width after formatting"
+                    ),
+                    rule(RULE_PARSE_ERROR, "The file could not be parsed"),
+                    rule(
+                        RULE_VERIFY_ERROR,
+                        "Formatting would lose a comment or change a token"
+                    ),
+                    rule(RULE_IO_ERROR, "The file could not be read or written"),
+                    rule(
+                        RULE_KEPT_VERBATIM,
+                        "The block doesn't parse and was kept verbatim by error_tolerant"
+                    ),
+                ],
+            }
+        },
+        "results": results,
+    });
+    if let Ok(current_dir) = std::env::current_dir() {
+        run["originalUriBaseIds"] =
+            json!({ SRCROOT: { "uri": format!("{}/", file_uri(&current_dir)) } });
+    }
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [run],
+    });
+    serde_json::to_string_pretty(&log).unwrap_or_default()
+}
+
+fn rule(id: &str, description: &str) -> Value {
+    json!({ "id": id, "shortDescription": { "text": description } })
+}
+
+/// The `artifactLocation` of the file named `name` in the reports: a `file://` URI if the
+/// path is absolute, otherwise a URI relative to `%SRCROOT%`.
+fn artifact_location(name: &str) -> Value {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        json!({ "uri": file_uri(path) })
+    } else {
+        // a colon in the first segment would read as a scheme
+        let uri = percent_encode(&name.replace('\\', "/")).replace(':', "%3A");
+        json!({ "uri": uri, "uriBaseId": SRCROOT })
+    }
+}
+
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    // `C:/dir` on Windows
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        format!("/{path}")
+    };
+    format!("file://{}", percent_encode(&path))
+}
+
+/// Escapes everything but the unreserved characters of RFC 3986 and the separators of a path.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn location(uri: &str, start_line: u32, end_line: u32) -> Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": artifact_location(uri),
+            "region": { "startLine": start_line, "endLine": end_line },
+        }
+    })
+}
+
+fn chunk_result(uri: &str, chunk: &ModifiedChunk) -> Value {
+    let start_line = chunk.line_number_orig.max(1);
+    let mut inserted = chunk.lines.join("\n");
+    if !chunk.lines.is_empty() {
+        inserted.push('\n');
+    }
+    json!({
+        "ruleId": RULE_NOT_FORMATTED,
+        "level": "warning",
+        "message": { "text": chunk.message() },
+        "locations": [location(uri, start_line, chunk.last_line_number_orig().max(start_line))],
+        "fixes": [{
+            "description": { "text": "Format with movefmt" },
+            "artifactChanges": [{
+                "artifactLocation": artifact_location(uri),
+                "replacements": [{
+                    // whole lines: from the start of the first removed line
+                    // to the start of the line after the last removed one
+                    "deletedRegion": {
+                        "startLine": start_line,
+                        "startColumn": 1,
+                        "endLine": start_line + chunk.lines_removed,
+                        "endColumn": 1,
+                    },
+                    "insertedContent": { "text": inserted },
+                }],
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sarif_results_and_fixes() {
+        let origin = "module 0x1::m {\nfun f() {}\nfun g() {}\n}\n";
+        let formatted = "module 0x1::m {\n    fun f() {}\n    fun g() {}\n}\n";
+        let files = vec![
+            JsonFileReport::formatted("a.move".to_string(), origin, formatted),
+            JsonFileReport::formatted("b.move".to_string(), formatted, formatted),
+            JsonFileReport::not_formatted(
+                "c.move".to_string(),
+                JsonFileStatus::ParseError,
+                Some("unexpected token".to_string()),
+            ),
+        ];
+        let log: Value = serde_json::from_str(&to_sarif(&files)).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+        let results = log["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0]["ruleId"], RULE_NOT_FORMATTED);
+        assert_eq!(
+            results[0]["locations"][0]["physicalLocation"],
+            json!({
+                "artifactLocation": { "uri": "a.move", "uriBaseId": SRCROOT },
+                "region": { "startLine": 2, "endLine": 3 },
+            })
+        );
+        assert_eq!(
+            results[0]["fixes"][0]["artifactChanges"][0]["replacements"][0],
+            json!({
+                "deletedRegion": { "startLine": 2, "startColumn": 1, "endLine": 4, "endColumn": 1 },
+                "insertedContent": { "text": "    fun f() {}\n    fun g() {}\n" },
+            })
+        );
+
+        assert_eq!(results[1]["ruleId"], RULE_PARSE_ERROR);
+        assert_eq!(results[1]["level"], "error");
+        assert_eq!(results[1]["message"]["text"], "unexpected token");
+    }
+
+    #[test]
+    fn sarif_artifact_uris() {
+        assert_eq!(
+            artifact_location("/work/my package/sources/a+b.move"),
+            json!({ "uri": "file:///work/my%20package/sources/a%2Bb.move" })
+        );
+        assert_eq!(
+            artifact_location("sources/#1.move"),
+            json!({ "uri": "sources/%231.move", "uriBaseId": SRCROOT })
+        );
+        let log: Value = serde_json::from_str(&to_sarif(&[])).unwrap();
+        let base = &log["runs"][0]["originalUriBaseIds"][SRCROOT]["uri"];
+        assert!(base.as_str().unwrap().starts_with("file:///"));
+        assert!(base.as_str().unwrap().ends_with('/'));
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) The BitsLab.MoveBit Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use lsp_types::{Position, Range, TextEdit};
+
+/// Compute the minimal line based `TextEdit`s which turn `origin` into `formatted`.
+/// Unchanged lines are never touched, so editors keep cursors and marks on them.
+pub fn mk_text_edits(origin: &str, formatted: &str) -> Vec<TextEdit> {
+    let origin_lines: Vec<&str> = origin.split_inclusive('\n').collect();
+    let formatted_lines: Vec<&str> = formatted.split_inclusive('\n').collect();
+
+    let mut edits = vec![];
+    // (first replaced line of origin, replacement text)
+    let mut hunk: Option<(usize, String)> = None;
+    let mut origin_idx = 0;
+    for result in diff::slice(&origin_lines, &formatted_lines) {
+        match result {
+            diff::Result::Left(_) => {
+                hunk.get_or_insert_with(|| (origin_idx, String::new()));
+                origin_idx += 1;
+            }
+            diff::Result::Right(line) => {
+                hunk.get_or_insert_with(|| (origin_idx, String::new()))
+                    .1
+                    .push_str(line);
+            }
+            diff::Result::Both(..) => {
+                if let Some((start, new_text)) = hunk.take() {
+                    edits.push(mk_edit(&origin_lines, start, origin_idx, new_text));
+                }
+                origin_idx += 1;
+            }
+        }
+    }
+    if let Some((start, new_text)) = hunk.take() {
+        edits.push(mk_edit(&origin_lines, start, origin_idx, new_text));
+    }
+    edits
+}
+
+fn mk_edit(origin_lines: &[&str], start: usize, end: usize, new_text: String) -> TextEdit {
+    TextEdit::new(
+        Range::new(
+            line_position(origin_lines, start),
+            line_position(origin_lines, end),
+        ),
+        new_text,
+    )
+}
+
+/// The position right before `origin_lines[line]`, or the end of the document.
+fn line_position(origin_lines: &[&str], line: usize) -> Position {
+    match origin_lines.last() {
+        Some(last) if line == origin_lines.len() && !last.ends_with('\n') => {
+            Position::new(line as u32 - 1, last.encode_utf16().count() as u32)
+        }
+        _ => Position::new(line as u32, 0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(origin: &str, edits: &[TextEdit]) -> String {
+        let lines: Vec<&str> = origin.split_inclusive('\n').collect();
+        let offset = |pos: &Position| -> usize {
+            let line_start: usize = lines[..pos.line as usize].iter().map(|l| l.len()).sum();
+            let col: usize = lines
+                .get(pos.line as usize)
+                .map(|l| {
+                    l.chars()
+                        .scan(0, |utf16, c| {
+                            *utf16 += c.len_utf16();
+                            Some((*utf16, c.len_utf8()))
+                        })
+                        .take_while(|(utf16, _)| *utf16 <= pos.character as usize)
+                        .map(|(_, len)| len)
+                        .sum()
+                })
+                .unwrap_or(0);
+            line_start + col
+        };
+        let mut result = origin.to_string();
+        for edit in edits.iter().rev() {
+            let (start, end) = (offset(&edit.range.start), offset(&edit.range.end));
+            result.replace_range(start..end, &edit.new_text);
+        }
+        result
+    }
+
+    #[test]
+    fn no_edits_when_unchanged() {
+        let text = "module 0x1::m {\n    fun f() {}\n}\n";
+        assert!(mk_text_edits(text, text).is_empty());
+    }
+
+    #[test]
+    fn only_changed_lines_are_edited() {
+        let origin = "module 0x1::m {\nfun f() {}\n    fun g() {}\nfun h() {}\n}\n";
+        let formatted = "module 0x1::m {\n    fun f() {}\n    fun g() {}\n    fun h() {}\n}\n";
+        let edits = mk_text_edits(origin, formatted);
+        assert_eq!(edits.len(), 2);
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(1, 0), Position::new(2, 0))
+        );
+        assert_eq!(
+            edits[1].range,
+            Range::new(Position::new(3, 0), Position::new(4, 0))
+        );
+        assert_eq!(apply(origin, &edits), formatted);
+    }
+
+    #[test]
+    fn missing_trailing_new_line() {
+        let origin = "script {\nfun main() {}\n}";
+        let formatted = "script {\n    fun main() {}\n}\n";
+        let edits = mk_text_edits(origin, formatted);
+        assert_eq!(edits.last().unwrap().range.end, Position::new(2, 1));
+        assert_eq!(apply(origin, &edits), formatted);
+    }
+}
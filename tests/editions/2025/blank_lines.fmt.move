@@ -0,0 +1,15 @@
+module 0x42::editions {
+    struct Counter has drop {
+        value: u64
+    }
+
+    public fun bump(counter: &mut Counter) {
+        // the new value
+        let value = counter.value + 1;
+        counter.value = value;
+    }
+
+    public fun reset(counter: &mut Counter) {
+        counter.value = 0;
+    }
+}
@@ -283,3 +283,406 @@ fn regression_test_main() {
     }
     eprintln!("formated {} files", num);
 }
+
+/// The configs both engines are compared with on the whole corpus, one for each
+/// kind of layout decision an option drives.
+fn engine_comparison_configs() -> Vec<(&'static str, commentfmt::Config)> {
+    let mut narrow = commentfmt::Config::default();
+    narrow.set().max_width(60);
+    let mut small_indent = commentfmt::Config::default();
+    small_indent.set().indent_size(2);
+    let mut no_one_line = commentfmt::Config::default();
+    no_one_line
+        .set()
+        .prefer_one_line_for_short_branch_blk(false);
+    no_one_line
+        .set()
+        .prefer_one_line_for_short_call_para_list(false);
+    no_one_line
+        .set()
+        .prefer_one_line_for_short_fn_header_para_list(false);
+    no_one_line
+        .set()
+        .prefer_one_line_for_short_lambda_para_list(false);
+    let mut edition_2025 = commentfmt::Config::default();
+    edition_2025
+        .set()
+        .style_edition(commentfmt::StyleEdition::Edition2025);
+    vec![
+        ("default", commentfmt::Config::default()),
+        ("max_width = 60", narrow),
+        ("indent_size = 2", small_indent),
+        ("prefer_one_line_for_short_* = false", no_one_line),
+        ("style_edition = 2025", edition_2025),
+    ]
+}
+
+/// The fixtures the reference engine is known to fail on with one of the configs of
+/// `engine_comparison_configs`, as `(path suffix, config name)`.
+/// The reference engine must format every other fixture which parses.
+const KNOWN_REFERENCE_ENGINE_FAILURES: &[(&str, &str)] = &[];
+
+#[test]
+fn test_functional_formatter_matches_fmt() {
+    let configs = engine_comparison_configs();
+    let mut num: usize = 0;
+    for x in walkdir::WalkDir::new("./tests").into_iter().flatten() {
+        let file_name = x.file_name().to_str().unwrap();
+        if !x.file_type().is_file()
+            || !file_name.ends_with(".move")
+            || file_name.contains(".fmt")
+            || file_name.contains(".out")
+        {
+            continue;
+        }
+        let content_origin = std::fs::read_to_string(x.path()).unwrap();
+        if parse_file_string(&mut get_compile_env(), FileHash::empty(), &content_origin).is_err() {
+            continue;
+        }
+
+        for (config_name, config) in &configs {
+            let expected = movefmt::core::fmt::format_entry(&content_origin, config.clone());
+            let actual =
+                movefmt::core::fmt_state::format_entry_functional(&content_origin, config.clone());
+            let known_failure = KNOWN_REFERENCE_ENGINE_FAILURES
+                .iter()
+                .any(|(suffix, name)| x.path().ends_with(suffix) && name == config_name);
+            if known_failure {
+                // keep the list up to date, and both engines must agree on it
+                assert!(
+                    expected.is_err() && actual.is_err(),
+                    "{:?} with {} is listed in KNOWN_REFERENCE_ENGINE_FAILURES but is formatted",
+                    x.path(),
+                    config_name
+                );
+                continue;
+            }
+            let expected = expected.unwrap_or_else(|e| {
+                panic!("fmt fails on {:?} with {}: {:?}", x.path(), config_name, e)
+            });
+            let actual = actual.unwrap_or_else(|e| {
+                panic!(
+                    "functional formatter fails on {:?} with {}: {:?}",
+                    x.path(),
+                    config_name,
+                    e
+                )
+            });
+            assert!(
+                expected == actual,
+                "functional formatter differs from fmt on {:?} with {}:\n{:#?}",
+                x.path(),
+                config_name,
+                movefmt_diff::make_diff(&expected, &actual, movefmt_diff::DIFF_CONTEXT_SIZE)
+            );
+        }
+        num += 1;
+    }
+    eprintln!("compared {} files with {} configs", num, configs.len());
+}
+
+#[test]
+fn test_fmt_fixtures_idempotent() {
+    let mut num: usize = 0;
+    for x in walkdir::WalkDir::new("./tests").into_iter().flatten() {
+        let file_name = x.file_name().to_str().unwrap();
+        if !x.file_type().is_file() || !file_name.ends_with(".fmt.move") {
+            continue;
+        }
+        let expected = std::fs::read_to_string(x.path()).unwrap();
+        if parse_file_string(&mut get_compile_env(), FileHash::empty(), &expected).is_err() {
+            continue;
+        }
+
+        let second_pass =
+            movefmt::core::fmt_verify::verify_idempotent(&expected, commentfmt::Config::default())
+                .unwrap();
+        if let Some(second_pass) = second_pass {
+            panic!(
+                "formatting {:?} again changes it:\n{:#?}",
+                x.path(),
+                movefmt_diff::make_diff(&expected, &second_pass, movefmt_diff::DIFF_CONTEXT_SIZE)
+            );
+        }
+        num += 1;
+    }
+    eprintln!("formatted {} fixtures twice", num);
+}
+
+#[test]
+fn test_hard_tabs() {
+    let content =
+        "module 0x1::m {\nfun f(x: u64): u64 {\nif (x > 0) {\nx + 1\n} else {\n0\n}\n}\n}\n";
+    for tab_spaces in [2, 4] {
+        let mut spaces_config = commentfmt::Config::default();
+        spaces_config.set().indent_size(tab_spaces);
+        let mut tabs_config = commentfmt::Config::default();
+        tabs_config.set().hard_tabs(true);
+        tabs_config.set().tab_spaces(tab_spaces);
+
+        let expected = movefmt::core::fmt::format_entry(content, spaces_config)
+            .unwrap()
+            .replace(&" ".repeat(tab_spaces), "\t");
+        assert!(expected.contains("\n\t\t"));
+        let actual = movefmt::core::fmt::format_entry(content, tabs_config.clone()).unwrap();
+        assert_eq!(expected, actual);
+        let actual =
+            movefmt::core::fmt_state::format_entry_functional(content, tabs_config).unwrap();
+        assert_eq!(expected, actual);
+    }
+}
+
+#[test]
+fn test_hard_tabs_keep_block_comments() {
+    let content =
+        "module 0x1::m {\n/* first\n        continued\n    end */\nfun f(): u64 {\n1\n}\n}\n";
+    let spaces_config = commentfmt::Config::default();
+    let mut tabs_config = commentfmt::Config::default();
+    tabs_config.set().hard_tabs(true);
+
+    let engines: [fn(&str, commentfmt::Config) -> String; 2] = [
+        |content, config| movefmt::core::fmt::format_entry(content, config).unwrap(),
+        |content, config| {
+            movefmt::core::fmt_state::format_entry_functional(content, config).unwrap()
+        },
+    ];
+    for format in engines {
+        // only the lines out of the comment are indented with tabs
+        let expected: String = format(content, spaces_config.clone())
+            .split_inclusive('\n')
+            .map(|line| {
+                let trimmed = line.trim_start_matches(' ');
+                if trimmed.starts_with("continued") || trimmed.starts_with("end */") {
+                    line.to_string()
+                } else {
+                    let leading_space_cnt = line.len() - trimmed.len();
+                    "\t".repeat(leading_space_cnt / 4)
+                        + &" ".repeat(leading_space_cnt % 4)
+                        + trimmed
+                }
+            })
+            .collect();
+        assert!(
+            expected
+                .lines()
+                .any(|line| line.starts_with(' ') && line.trim_start() == "continued"),
+            "{}",
+            expected
+        );
+        assert_eq!(expected, format(content, tabs_config.clone()));
+    }
+}
+
+#[test]
+fn test_one_fn_header_para_per_line() {
+    let content =
+        "module 0x1::m {\npublic entry fun f(a: u64, b: Table<u64, u8>) {}\nfun g(a: u64) {}\n}\n";
+    let mut config = commentfmt::Config::default();
+    config
+        .set()
+        .prefer_one_line_for_short_fn_header_para_list(false);
+
+    let actual = movefmt::core::fmt::format_entry(content, config.clone()).unwrap();
+    let lines: Vec<&str> = actual.lines().map(|line| line.trim()).collect();
+    assert!(lines.contains(&"a: u64,"), "{}", actual);
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with("b: Table<u64, u8>")),
+        "{}",
+        actual
+    );
+    assert!(lines.contains(&"fun g(a: u64) {}"), "{}", actual);
+    let functional = movefmt::core::fmt_state::format_entry_functional(content, config).unwrap();
+    assert_eq!(actual, functional);
+}
+
+#[test]
+fn test_fmt_off_region() {
+    let table = "    const TABLE: vector<u64> = vector[\n        1,    2,    3,\n        40,   50,   60,\n    ];\n";
+    let content = format!(
+        "module 0x1::m {{\n    // movefmt::off\n{}    // movefmt::on\n    fun f(){{ let x   = 1; }}\n}}\n",
+        table
+    );
+    let config = commentfmt::Config::default();
+
+    let actual = movefmt::core::fmt::format_entry(&content, config.clone()).unwrap();
+    assert!(actual.contains(table), "{}", actual);
+    assert!(!actual.contains("let x   = 1"), "{}", actual);
+    let functional = movefmt::core::fmt_state::format_entry_functional(&content, config).unwrap();
+    assert_eq!(actual, functional);
+}
+
+#[test]
+fn test_fmt_skip_items() {
+    let kept = [
+        "use std::vector::{  length,   push_back };",
+        "const TABLE: vector<u64> = vector[\n        1,    2,    3,\n        40,   50,   60,\n    ];",
+        "{ Red,   Green,\n        Blue }",
+        "let   m = 1   +   2;",
+        "spec f {   ensures   result == 6; }",
+    ];
+    let content = format!(
+        "module 0x1::m {{
+    #[test_only, fmt::skip]
+    {}
+    #[fmt::skip]
+    {}
+    #[fmt::skip]
+    enum Color {}
+    fun f(): u64 {{
+        // movefmt::skip
+        {}
+        let   n = 3;
+        m + n
+    }}
+    #[fmt::skip]
+    {}
+}}
+",
+        kept[0], kept[1], kept[2], kept[3], kept[4]
+    );
+    let config = commentfmt::Config::default();
+
+    let actual = movefmt::core::fmt::format_entry(&content, config.clone()).unwrap();
+    for kept in kept {
+        assert!(actual.contains(kept), "{}", actual);
+    }
+    assert!(actual.contains("let n = 3;"), "{}", actual);
+    let functional = movefmt::core::fmt_state::format_entry_functional(&content, config).unwrap();
+    assert_eq!(actual, functional);
+}
+
+#[test]
+fn test_item_config_attributes() {
+    let body = "    fun f() {\n        call_something(argument_one, argument_two, argument_three);\n    }\n";
+    let plain = format!("module 0x1::m {{\n{}}}\n", body);
+    let attributed = format!("module 0x1::m {{\n    #[fmt::max_width = 40]\n{}}}\n", body);
+    let mut narrow_config = commentfmt::Config::default();
+    narrow_config.set().max_width(40);
+
+    let expected = movefmt::core::fmt::format_entry(&plain, narrow_config).unwrap();
+    let actual =
+        movefmt::core::fmt::format_entry(&attributed, commentfmt::Config::default()).unwrap();
+    assert_eq!(
+        expected,
+        actual.replace("    #[fmt::max_width = 40]\n", ""),
+        "{}",
+        actual
+    );
+    assert_ne!(
+        movefmt::core::fmt::format_entry(&plain, commentfmt::Config::default()).unwrap(),
+        expected
+    );
+    let functional = movefmt::core::fmt_state::format_entry_functional(
+        &attributed,
+        commentfmt::Config::default(),
+    )
+    .unwrap();
+    assert_eq!(actual, functional);
+}
+
+#[test]
+fn test_item_config_breaks_fun_header() {
+    let fun = "    fun f(a: u64, b: u64): (u64, u64, u64, u64) {\n        (a, b, a, b)\n    }\n";
+    let plain = format!("module 0x1::m {{\n{}}}\n", fun);
+    let attributed = format!("module 0x1::m {{\n    #[fmt::max_width = 40]\n{}}}\n", fun);
+
+    let unchanged =
+        movefmt::core::fmt::format_entry(&plain, commentfmt::Config::default()).unwrap();
+    assert!(
+        unchanged.contains(fun.lines().next().unwrap()),
+        "{}",
+        unchanged
+    );
+    let actual =
+        movefmt::core::fmt::format_entry(&attributed, commentfmt::Config::default()).unwrap();
+    assert!(
+        actual.contains("    fun f(a: u64, b: u64):\n        (u64, u64, u64, u64) {\n"),
+        "{}",
+        actual
+    );
+    let functional = movefmt::core::fmt_state::format_entry_functional(
+        &attributed,
+        commentfmt::Config::default(),
+    )
+    .unwrap();
+    assert_eq!(actual, functional);
+}
+
+#[test]
+fn test_format_str() {
+    let mut config = commentfmt::Config::default();
+    config.set().verbose(commentfmt::Verbosity::Verbose);
+    let output = movefmt::format_str("module 0x1::m {\nfun f() {}\n}\n", &config).unwrap();
+    assert_eq!(output.text, "module 0x1::m {\n    fun f() {}\n}\n");
+    assert!(output.changed);
+    assert!(output.warnings.is_empty());
+    assert!(!movefmt::format_str(&output.text, &config).unwrap().changed);
+
+    match movefmt::format_str("module 0x1::m {\n    fun f() { let }\n}\n", &config) {
+        Err(movefmt::FormatError::Parse(diagnostics)) => {
+            assert_eq!(diagnostics[0].line, 2, "{:?}", diagnostics);
+            assert_eq!(diagnostics[0].column, 19, "{:?}", diagnostics);
+        }
+        result => panic!("expected a parse error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_error_tolerant() {
+    let content = "module 0x1::a {\nfun f() {}\n}\n\nmodule 0x1::b {\n    fun g() { let }\n}\n";
+    let mut config = commentfmt::Config::default();
+    assert!(movefmt::format_str(content, &config).is_err());
+
+    config.set().error_tolerant(true);
+    let output = movefmt::format_str(content, &config).unwrap();
+    assert_eq!(
+        output.text,
+        "module 0x1::a {\n    fun f() {}\n}\n\nmodule 0x1::b {\n    fun g() { let }\n}\n"
+    );
+    assert_eq!(output.warnings.len(), 1, "{:?}", output.warnings);
+    assert_eq!(output.warnings[0].line, 6);
+    assert!(output.warnings[0].message.starts_with("kept verbatim"));
+}
+
+#[test]
+fn test_style_editions() {
+    // tests/editions/<edition> holds the output each edition must keep producing byte for byte
+    let mut num: usize = 0;
+    for edition_dir in std::fs::read_dir("./tests/editions").unwrap().flatten() {
+        let edition: commentfmt::StyleEdition =
+            edition_dir.file_name().to_str().unwrap().parse().unwrap();
+        let mut config = commentfmt::Config::default();
+        config.set().style_edition(edition);
+        for file in std::fs::read_dir(edition_dir.path()).unwrap().flatten() {
+            let path = file.path();
+            let file_name = path.to_str().unwrap();
+            if !file_name.ends_with(".move") || file_name.contains(".fmt") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).unwrap();
+            let expected = std::fs::read_to_string(format!(
+                "{}.fmt.move",
+                file_name.trim_end_matches(".move")
+            ))
+            .unwrap();
+            let actual = movefmt::core::fmt::format_entry(&content, config.clone()).unwrap();
+            assert_eq!(
+                actual, expected,
+                "{} with style_edition {}",
+                file_name, edition
+            );
+            let actual =
+                movefmt::core::fmt_state::format_entry_functional(&content, config.clone())
+                    .unwrap();
+            assert_eq!(
+                actual, expected,
+                "{} with style_edition {} and the functional formatter",
+                file_name, edition
+            );
+            num += 1;
+        }
+    }
+    assert!(num > 0);
+}